@@ -15,13 +15,18 @@ export default function Home() {
   useEffect(() => {
     const ws = new WebSocket("ws://localhost:3002/ws");
 
-    ws.onopen = () => console.log("WebSocket opened");
+    ws.onopen = () => {
+      console.log("WebSocket opened");
+      ws.send(JSON.stringify({ v: 1, id: 1, command: "subscribe", channels: ["detections"] }));
+    };
 
     ws.onmessage = (event) => {
       try {
-        const data = JSON.parse(event.data);
-        if (data !== undefined) {
-          setInfo(data);
+        const message = JSON.parse(event.data);
+        if (message.type === "event" && message.channel === "detections") {
+          setInfo(message.data);
+        } else if (message.type === "error") {
+          console.error("WebSocket request failed", message.error);
         }
       } catch (err) {
         console.error("Error parsing Websocket message", err);
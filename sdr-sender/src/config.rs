@@ -0,0 +1,373 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use serde::{Serialize, Deserialize};
+use wire::formats::SampleFormat;
+
+/// Command line of the sender. Every option overrides the matching setting of the config file.
+#[derive(Parser, Debug)]
+#[command(version, about = "Streams I/Q samples from an SDR to the detection server")]
+pub struct Cli {
+    /// TOML configuration file. Settings missing from it keep their default value.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration as TOML and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    /// Where samples come from [default: soapy if built with the soapy feature, synthetic otherwise].
+    #[arg(long, value_enum)]
+    pub source: Option<SourceKind>,
+    /// SoapySDR device arguments, e.g. driver=rtlsdr or driver=hackrf,serial=...
+    #[arg(long)]
+    pub device_args: Option<String>,
+    /// Receiver channel.
+    #[arg(long)]
+    pub channel: Option<usize>,
+    /// Receiver antenna, e.g. RX or "Antenna A".
+    #[arg(long)]
+    pub antenna: Option<String>,
+    /// Receiver filter bandwidth (in Hz).
+    #[arg(long)]
+    pub bandwidth: Option<f64>,
+    /// Manual gain (set with --gain) or the receiver's AGC.
+    #[arg(long, value_enum)]
+    pub gain_mode: Option<GainMode>,
+    /// Raw I/Q recording to play back with the file source.
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+    /// Format of the recording: cf32, cs16, cs8 or cu8.
+    #[arg(long)]
+    pub file_format: Option<SampleFormat>,
+    /// Start the recording over when it ends.
+    #[arg(long)]
+    pub repeat: bool,
+    /// Simulator scenario played by the synthetic source.
+    #[arg(long)]
+    pub scenario: Option<PathBuf>,
+    /// Samples per second.
+    #[arg(long)]
+    pub sample_rate: Option<f64>,
+    /// Center frequency (in Hz).
+    #[arg(long)]
+    pub center_freq: Option<f64>,
+    /// Receiver gain (in dB).
+    #[arg(long)]
+    pub gain: Option<f64>,
+    /// Address of the server's UDP listener [default: 127.0.0.1:5454].
+    #[arg(long)]
+    pub udp_addr: Option<String>,
+    /// Send raw samples without the packet header.
+    #[arg(long)]
+    pub no_header: bool,
+    /// Name of this sensor on the server.
+    #[arg(long)]
+    pub sensor_id: Option<String>,
+    /// Center frequencies to sweep through (in Hz, comma separated), instead of staying on
+    /// --center-freq.
+    #[arg(long, value_delimiter = ',')]
+    pub sweep: Option<Vec<f64>>,
+    /// Time spent on each band of the sweep (in ms).
+    #[arg(long)]
+    pub dwell_ms: Option<u64>,
+    /// Samples dropped after each retune while the receiver settles (in ms).
+    #[arg(long)]
+    pub settle_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// A receiver, through SoapySDR.
+    Soapy,
+    /// A raw I/Q recording.
+    File,
+    /// A scenario of the signal simulator.
+    Synthetic,
+}
+
+/// The receiver if the sender was built with SoapySDR support, otherwise the simulator, so a
+/// default build doesn't pick a source it can't open.
+impl Default for SourceKind {
+    fn default() -> Self {
+        if cfg!(feature = "soapy") {
+            SourceKind::Soapy
+        } else {
+            SourceKind::Synthetic
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GainMode {
+    /// `gain` dB.
+    #[default]
+    Manual,
+    Agc,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SoapyConfig {
+    pub device_args: String,
+    pub channel: usize,
+    /// The device's default if unset.
+    pub antenna: Option<String>,
+    /// The device's default if unset (in Hz).
+    pub bandwidth: Option<f64>,
+    pub gain_mode: GainMode,
+}
+
+impl Default for SoapyConfig {
+    fn default() -> Self {
+        Self {
+            device_args: "driver=sdrplay".into(),
+            channel: 0,
+            antenna: None,
+            bandwidth: None,
+            gain_mode: GainMode::Manual,
+        }
+    }
+}
+
+/// Played back at `sample_rate` and reported at `center_freq`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub path: PathBuf,
+    pub format: SampleFormat,
+    pub repeat: bool,
+}
+
+/// Visits `bands` in turn, staying `dwell_ms` on each. The first `settle_ms` of every visit are
+/// dropped, as the receiver may still be delivering samples from the previous band.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweepConfig {
+    /// Center frequencies (in Hz). Empty to stay on `center_freq`.
+    pub bands: Vec<f64>,
+    pub dwell_ms: u64,
+    pub settle_ms: u64,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        Self {
+            bands: Vec::new(),
+            dwell_ms: 500,
+            settle_ms: 20,
+        }
+    }
+}
+
+/// The scenario sets the sample rate and center frequency.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyntheticConfig {
+    /// Defaults to the simulator's example scenario, relative to the repository root.
+    pub scenario: PathBuf,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        Self {
+            scenario: "simulator/scenarios/mavic_with_wifi.toml".into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub sample_rate: f64,
+    pub center_freq: f64,
+    pub gain: f64,
+    /// Address of the server's UDP listener. Defaults to the server's default UDP port, 5454.
+    /// Earlier versions sent to 127.0.0.1:4001, which is the server's HTTP port.
+    pub udp_addr: String,
+    /// Prefix every datagram with a header so the server can detect lost packets.
+    pub send_header: bool,
+    /// Sent in the header, so the server keeps this sender's samples apart from other senders'.
+    /// The server falls back to the sender's address (IP and port) if unset.
+    pub sensor_id: Option<String>,
+    pub source: SourceKind,
+    pub soapy: SoapyConfig,
+    pub file: FileConfig,
+    pub synthetic: SyntheticConfig,
+    pub sweep: SweepConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            sample_rate: 62_500.0,
+            center_freq: 915.0e6, // 915 MHz
+            gain: 40.0,
+            udp_addr: "127.0.0.1:5454".into(), // Replace with your receiver's IP
+            send_header: true,
+            sensor_id: None,
+            source: SourceKind::default(),
+            soapy: SoapyConfig::default(),
+            file: FileConfig::default(),
+            synthetic: SyntheticConfig::default(),
+            sweep: SweepConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_cli(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+
+        if let Some(sample_rate) = cli.sample_rate {
+            config.sample_rate = sample_rate;
+        }
+        if let Some(center_freq) = cli.center_freq {
+            config.center_freq = center_freq;
+        }
+        if let Some(gain) = cli.gain {
+            config.gain = gain;
+        }
+        if let Some(udp_addr) = &cli.udp_addr {
+            config.udp_addr = udp_addr.clone();
+        }
+        if cli.no_header {
+            config.send_header = false;
+        }
+        if let Some(sensor_id) = &cli.sensor_id {
+            config.sensor_id = Some(sensor_id.clone());
+        }
+        if let Some(source) = cli.source {
+            config.source = source;
+        }
+        if let Some(device_args) = &cli.device_args {
+            config.soapy.device_args = device_args.clone();
+        }
+        if let Some(channel) = cli.channel {
+            config.soapy.channel = channel;
+        }
+        if let Some(antenna) = &cli.antenna {
+            config.soapy.antenna = Some(antenna.clone());
+        }
+        if let Some(bandwidth) = cli.bandwidth {
+            config.soapy.bandwidth = Some(bandwidth);
+        }
+        if let Some(gain_mode) = cli.gain_mode {
+            config.soapy.gain_mode = gain_mode;
+        }
+        if let Some(file) = &cli.file {
+            config.file.path = file.clone();
+        }
+        if let Some(format) = cli.file_format {
+            config.file.format = format;
+        }
+        if cli.repeat {
+            config.file.repeat = true;
+        }
+        if let Some(scenario) = &cli.scenario {
+            config.synthetic.scenario = scenario.clone();
+        }
+        if let Some(bands) = &cli.sweep {
+            config.sweep.bands = bands.clone();
+        }
+        if let Some(dwell_ms) = cli.dwell_ms {
+            config.sweep.dwell_ms = dwell_ms;
+        }
+        if let Some(settle_ms) = cli.settle_ms {
+            config.sweep.settle_ms = settle_ms;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+
+        if !self.sample_rate.is_finite() || self.sample_rate <= 0.0 || self.sample_rate > u32::MAX as f64 {
+            return invalid(format!("sample_rate must be between 0 and {} Hz, got {}", u32::MAX, self.sample_rate));
+        }
+        if !self.center_freq.is_finite() || self.center_freq <= 0.0 {
+            return invalid(format!("center_freq must be greater than 0 Hz, got {}", self.center_freq));
+        }
+        if !self.gain.is_finite() {
+            return invalid(format!("gain must be a number, got {}", self.gain));
+        }
+        if self.udp_addr.parse::<SocketAddr>().is_err() {
+            return invalid(format!("udp_addr '{}' is not an address like 127.0.0.1:5454", self.udp_addr));
+        }
+        if let Some(sensor_id) = &self.sensor_id {
+            if sensor_id.is_empty() || sensor_id.len() > u8::MAX as usize {
+                return invalid(format!("sensor_id must be 1 to {} bytes long", u8::MAX));
+            }
+            if !self.send_header {
+                return invalid("sensor_id is sent in the packet header, so send_header must be on".into());
+            }
+        }
+        if !self.sweep.bands.is_empty() {
+            if self.source != SourceKind::Soapy {
+                return invalid("sweeping needs a receiver, set source to soapy".into());
+            }
+            if !self.send_header {
+                return invalid("each band's center frequency is sent in the packet header, so send_header must be on".into());
+            }
+            if let Some(band) = self.sweep.bands.iter().find(|band| !band.is_finite() || **band <= 0.0) {
+                return invalid(format!("sweep.bands must be frequencies above 0 Hz, got {}", band));
+            }
+            if self.sweep.dwell_ms <= self.sweep.settle_ms {
+                return invalid(format!(
+                    "sweep.dwell_ms ({}) must be longer than sweep.settle_ms ({})",
+                    self.sweep.dwell_ms, self.sweep.settle_ms
+                ));
+            }
+        }
+        match self.source {
+            SourceKind::Soapy => {
+                if self.soapy.bandwidth.is_some_and(|bandwidth| !bandwidth.is_finite() || bandwidth <= 0.0) {
+                    return invalid("soapy.bandwidth must be greater than 0 Hz".into());
+                }
+            }
+            SourceKind::File => {
+                if self.file.path.as_os_str().is_empty() {
+                    return invalid("the file source needs file.path".into());
+                }
+            }
+            SourceKind::Synthetic => {
+                if self.synthetic.scenario.as_os_str().is_empty() {
+                    return invalid("the synthetic source needs synthetic.scenario".into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
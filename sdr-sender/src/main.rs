@@ -1,39 +1,88 @@
-use soapysdr::Device;
+use clap::Parser;
+use config::{Cli, Config};
+use num_complex::Complex;
+use source::{open_source, SampleSource, SourceError};
 use std::net::UdpSocket;
 use std::time::Duration;
+use sweep::Sweep;
+use wire::formats::SampleFormat;
+use wire::packet::{PacketHeader, HEADER_LEN};
 
-const SAMPLE_RATE: f64 = 62_500.0;
-const CENTER_FREQ: f64 = 915.0e6; // 915 MHz
-const GAIN: f64 = 40.0;
-const UDP_ADDR: &str = "127.0.0.1:4001"; // Replace with your receiver's IP
+mod config;
+#[cfg(feature = "soapy")]
+mod soapy;
+mod source;
+mod sweep;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize SDRPlay device
-    let dev = Device::new("driver=sdrplay")?; // TODO look into this
+    let cli = Cli::parse();
+    let config = Config::from_cli(&cli)?;
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config)?);
+        return Ok(());
+    }
 
-    // Configure the SDR
-    let mut rx_stream = dev.rx_stream::<f32>(&[0])?;
-    dev.set_sample_rate(soapysdr::Direction::Rx, 0, SAMPLE_RATE)?;
-    dev.set_frequency(soapysdr::Direction::Rx, 0, CENTER_FREQ, ())?;
-    dev.set_gain(soapysdr::Direction::Rx, 0, GAIN)?;
+    // Open the receiver, recording or simulator picked in the configuration
+    let mut source = open_source(&config)?;
+    eprintln!("Streaming {} to {}", source.describe(), config.udp_addr);
 
-    // Start streaming
-    rx_stream.activate(None)?;
+    // Sweep through the configured bands, if any
+    let mut sweep = Sweep::new(&config.sweep, source.sample_rate());
+    if let Some(sweep) = &sweep {
+        eprintln!("Sweeping {} bands, {} ms each", config.sweep.bands.len(), config.sweep.dwell_ms);
+        source.tune(sweep.band())?;
+    }
 
     // Create a UDP socket
     let socket = UdpSocket::bind("0.0.0.0:0")?;
     socket.set_write_timeout(Some(Duration::from_millis(100)))?;
 
-    let mut buffer = vec![0.0f32; 4096];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); 4096];
+    let mut datagram = Vec::with_capacity(HEADER_LEN + buffer.len() * 8);
+    let mut header = PacketHeader {
+        sequence: 0,
+        timestamp: 0,
+        center_freq: source.center_freq() as u64,
+        sample_rate: source.sample_rate() as u32,
+        format: SampleFormat::Cf32,
+        sensor_id: config.sensor_id.clone(),
+    };
 
     loop {
         // Read I/Q samples
-        match rx_stream.read(&mut [&mut buffer], 1000000) {
+        match source.read(&mut buffer) {
+            Ok(0) => {
+                eprintln!("End of {}", source.describe());
+                return Ok(());
+            }
+            Ok(samples) if sweep.as_ref().is_some_and(Sweep::settling) => {
+                // Left out of the sample clock too: the server starts the band over when the
+                // center frequency changes, and a gap would be taken for lost datagrams
+                retune(&mut sweep, source.as_mut(), samples)?;
+            }
             Ok(samples) => {
                 let iq_bytes: &[u8] = bytemuck::cast_slice(&buffer[..samples]);
-                socket.send_to(iq_bytes, UDP_ADDR)?;
+                datagram.clear();
+                if config.send_header {
+                    header.center_freq = source.center_freq() as u64;
+                    header.encode(&mut datagram);
+                }
+                datagram.extend_from_slice(iq_bytes);
+                socket.send_to(&datagram, &config.udp_addr)?;
+
+                header.sequence += 1;
+                header.timestamp += samples as u64;
+                retune(&mut sweep, source.as_mut(), samples)?;
             }
-            Err(e) => eprintln!("Error reading SDR data: {:?}", e),
+            Err(e) => eprintln!("Error reading samples: {}", e),
         }
     }
 }
+
+/// Moves the source to the next band once the sweep's dwell on the current one is over.
+fn retune(sweep: &mut Option<Sweep>, source: &mut dyn SampleSource, samples: usize) -> Result<(), SourceError> {
+    match sweep.as_mut().and_then(|sweep| sweep.advance(samples)) {
+        Some(band) => source.tune(band),
+        None => Ok(()),
+    }
+}
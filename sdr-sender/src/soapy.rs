@@ -0,0 +1,85 @@
+use num_complex::Complex;
+use soapysdr::{Device, Direction, RxStream};
+
+use crate::config::{Config, GainMode};
+use crate::source::{SampleSource, SourceError};
+
+/// How long a read waits for samples (in µs).
+const READ_TIMEOUT_US: i64 = 1_000_000;
+
+/// Any receiver SoapySDR has a module for: SDRplay, RTL-SDR, HackRF, Airspy, USRP...
+pub struct SoapySource {
+    // Also keeps the device open as long as the stream.
+    device: Device,
+    channel: usize,
+    stream: RxStream<Complex<f32>>,
+    description: String,
+    sample_rate: f64,
+    center_freq: f64,
+}
+
+impl SoapySource {
+    pub fn open(config: &Config) -> Result<Self, SourceError> {
+        let soapy = &config.soapy;
+        let channel = soapy.channel;
+        let device = Device::new(soapy.device_args.as_str()).map_err(SourceError::Soapy)?;
+
+        device.set_sample_rate(Direction::Rx, channel, config.sample_rate).map_err(SourceError::Soapy)?;
+        device.set_frequency(Direction::Rx, channel, config.center_freq, ()).map_err(SourceError::Soapy)?;
+        if let Some(antenna) = &soapy.antenna {
+            device.set_antenna(Direction::Rx, channel, antenna.as_str()).map_err(SourceError::Soapy)?;
+        }
+        if let Some(bandwidth) = soapy.bandwidth {
+            device.set_bandwidth(Direction::Rx, channel, bandwidth).map_err(SourceError::Soapy)?;
+        }
+        match soapy.gain_mode {
+            GainMode::Agc => device.set_gain_mode(Direction::Rx, channel, true).map_err(SourceError::Soapy)?,
+            GainMode::Manual => {
+                device.set_gain_mode(Direction::Rx, channel, false).map_err(SourceError::Soapy)?;
+                device.set_gain(Direction::Rx, channel, config.gain).map_err(SourceError::Soapy)?;
+            }
+        }
+
+        let mut stream = device.rx_stream::<Complex<f32>>(&[channel]).map_err(SourceError::Soapy)?;
+        stream.activate(None).map_err(SourceError::Soapy)?;
+
+        Ok(Self {
+            device,
+            channel,
+            stream,
+            description: format!("SoapySDR device '{}' channel {}", soapy.device_args, channel),
+            sample_rate: config.sample_rate,
+            center_freq: config.center_freq,
+        })
+    }
+}
+
+impl SampleSource for SoapySource {
+    fn read(&mut self, buffer: &mut [Complex<f32>]) -> Result<usize, SourceError> {
+        // A receiver never runs out of samples, so don't let an empty read end the stream.
+        loop {
+            let read = self.stream.read(&mut [&mut *buffer], READ_TIMEOUT_US).map_err(SourceError::Soapy)?;
+            if read > 0 {
+                return Ok(read);
+            }
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn center_freq(&self) -> f64 {
+        self.center_freq
+    }
+
+    fn tune(&mut self, center_freq: f64) -> Result<(), SourceError> {
+        self.device.set_frequency(Direction::Rx, self.channel, center_freq, ()).map_err(SourceError::Soapy)?;
+        self.center_freq = center_freq;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        self.description.clone()
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use num_complex::Complex;
+use simulator::scenario::{Scenario, ScenarioError};
+use simulator::signals::Simulator;
+use wire::formats::SampleFormat;
+
+use crate::config::{Config, SourceKind};
+
+/// Where the sender gets its I/Q samples from.
+pub trait SampleSource {
+    /// Fills the start of `buffer` and returns how many samples were read, 0 at the end of the
+    /// stream.
+    fn read(&mut self, buffer: &mut [Complex<f32>]) -> Result<usize, SourceError>;
+
+    fn sample_rate(&self) -> f64;
+
+    fn center_freq(&self) -> f64;
+
+    /// Moves to another center frequency (in Hz). Only receivers can.
+    fn tune(&mut self, _center_freq: f64) -> Result<(), SourceError> {
+        Err(SourceError::NotTunable(self.describe()))
+    }
+
+    /// One line description for the logs.
+    fn describe(&self) -> String;
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    Open(PathBuf, std::io::Error),
+    Read(PathBuf, std::io::Error),
+    Scenario(ScenarioError),
+    /// The source was left out of this build.
+    Unavailable(&'static str),
+    NotTunable(String),
+    #[cfg(feature = "soapy")]
+    Soapy(soapysdr::Error),
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceError::Open(path, e) => write!(f, "could not open {}: {}", path.display(), e),
+            SourceError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            SourceError::Scenario(e) => write!(f, "invalid scenario: {}", e),
+            SourceError::Unavailable(reason) => write!(f, "{}", reason),
+            SourceError::NotTunable(source) => write!(f, "{} can't be retuned", source),
+            #[cfg(feature = "soapy")]
+            SourceError::Soapy(e) => write!(f, "SoapySDR: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// Opens the source selected in the configuration.
+pub fn open_source(config: &Config) -> Result<Box<dyn SampleSource>, SourceError> {
+    match config.source {
+        #[cfg(feature = "soapy")]
+        SourceKind::Soapy => Ok(Box::new(crate::soapy::SoapySource::open(config)?)),
+        #[cfg(not(feature = "soapy"))]
+        SourceKind::Soapy => Err(SourceError::Unavailable(
+            "this sender was built without SoapySDR support, rebuild it with --features soapy",
+        )),
+        SourceKind::File => Ok(Box::new(FileSource::open(config)?)),
+        SourceKind::Synthetic => Ok(Box::new(SyntheticSource::open(&config.synthetic.scenario)?)),
+    }
+}
+
+/// Holds sources that aren't bound to a receiver's clock back to real time.
+struct Pacer {
+    started: Instant,
+    sample_rate: f64,
+    samples: u64,
+}
+
+impl Pacer {
+    fn new(sample_rate: f64) -> Self {
+        Self { started: Instant::now(), sample_rate, samples: 0 }
+    }
+
+    /// Waits until `samples` more samples would have been received.
+    fn wait(&mut self, samples: usize) {
+        self.samples += samples as u64;
+        let due = Duration::from_secs_f64(self.samples as f64 / self.sample_rate);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Raw interleaved I/Q recording, played back at the configured sample rate.
+pub struct FileSource {
+    path: PathBuf,
+    reader: BufReader<File>,
+    format: SampleFormat,
+    repeat: bool,
+    sample_rate: f64,
+    center_freq: f64,
+    pacer: Pacer,
+    bytes: Vec<u8>,
+}
+
+impl FileSource {
+    pub fn open(config: &Config) -> Result<Self, SourceError> {
+        let path = config.file.path.clone();
+        let file = File::open(&path).map_err(|e| SourceError::Open(path.clone(), e))?;
+        Ok(Self {
+            path,
+            reader: BufReader::new(file),
+            format: config.file.format,
+            repeat: config.file.repeat,
+            sample_rate: config.sample_rate,
+            center_freq: config.center_freq,
+            pacer: Pacer::new(config.sample_rate),
+            bytes: Vec::new(),
+        })
+    }
+
+    /// Reads up to `len` bytes, fewer only at the end of the file.
+    fn read_bytes(&mut self, len: usize) -> std::io::Result<usize> {
+        self.bytes.resize(len, 0);
+        let mut filled = 0;
+        while filled < len {
+            match self.reader.read(&mut self.bytes[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl SampleSource for FileSource {
+    fn read(&mut self, buffer: &mut [Complex<f32>]) -> Result<usize, SourceError> {
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let len = buffer.len() * bytes_per_sample;
+        // A trailing fragment shorter than one sample is dropped, so it ends the file like EOF
+        let mut filled = self.read_bytes(len).map_err(|e| SourceError::Read(self.path.clone(), e))?;
+        filled -= filled % bytes_per_sample;
+        if filled == 0 && self.repeat {
+            self.reader.rewind().map_err(|e| SourceError::Read(self.path.clone(), e))?;
+            filled = self.read_bytes(len).map_err(|e| SourceError::Read(self.path.clone(), e))?;
+            filled -= filled % bytes_per_sample;
+        }
+
+        let samples = self.format.parse(&self.bytes[..filled]);
+        buffer[..samples.len()].copy_from_slice(&samples);
+        self.pacer.wait(samples.len());
+        Ok(samples.len())
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn center_freq(&self) -> f64 {
+        self.center_freq
+    }
+
+    fn describe(&self) -> String {
+        format!("{} ({})", self.path.display(), self.format)
+    }
+}
+
+/// Scenario of the signal simulator, generated in real time.
+pub struct SyntheticSource {
+    path: PathBuf,
+    simulator: Simulator,
+    pacer: Pacer,
+}
+
+impl SyntheticSource {
+    pub fn open(path: &Path) -> Result<Self, SourceError> {
+        let scenario = Scenario::load(path).map_err(SourceError::Scenario)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            pacer: Pacer::new(scenario.sample_rate as f64),
+            simulator: Simulator::new(&scenario),
+        })
+    }
+}
+
+impl SampleSource for SyntheticSource {
+    fn read(&mut self, buffer: &mut [Complex<f32>]) -> Result<usize, SourceError> {
+        let samples = self.simulator.next_block(buffer.len());
+        buffer[..samples.len()].copy_from_slice(&samples);
+        self.pacer.wait(samples.len());
+        Ok(samples.len())
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.simulator.sample_rate() as f64
+    }
+
+    fn center_freq(&self) -> f64 {
+        self.simulator.center_freq() as f64
+    }
+
+    fn describe(&self) -> String {
+        format!("synthetic scenario {}", self.path.display())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::config::SweepConfig;
+
+/// Decides when to move to the next band of a sweep, counting samples rather than wall-clock time
+/// so the dwell matches what was actually received.
+pub struct Sweep {
+    bands: Vec<f64>,
+    current: usize,
+    dwell_samples: u64,
+    settle_samples: u64,
+    /// Samples read since the last retune.
+    position: u64,
+}
+
+impl Sweep {
+    /// `None` if no bands are configured.
+    pub fn new(config: &SweepConfig, sample_rate: f64) -> Option<Self> {
+        if config.bands.is_empty() {
+            return None;
+        }
+        let samples = |ms: u64| (ms as f64 * sample_rate / 1000.0) as u64;
+        Some(Self {
+            bands: config.bands.clone(),
+            current: 0,
+            dwell_samples: samples(config.dwell_ms).max(1),
+            settle_samples: samples(config.settle_ms),
+            position: 0,
+        })
+    }
+
+    pub fn band(&self) -> f64 {
+        self.bands[self.current]
+    }
+
+    /// The receiver may still be delivering samples from the previous band.
+    pub fn settling(&self) -> bool {
+        self.position < self.settle_samples
+    }
+
+    /// Counts `samples` more samples. Returns the band to tune to once the dwell is over.
+    pub fn advance(&mut self, samples: usize) -> Option<f64> {
+        self.position += samples as u64;
+        if self.position < self.dwell_samples {
+            return None;
+        }
+        self.position = 0;
+        self.current = (self.current + 1) % self.bands.len();
+        Some(self.band())
+    }
+}
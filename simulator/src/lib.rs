@@ -0,0 +1,4 @@
+//! Synthetic RF scenarios for exercising the detection server without an SDR.
+
+pub mod scenario;
+pub mod signals;
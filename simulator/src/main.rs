@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use simulator::scenario::{Scenario, Segment};
+use simulator::signals::Simulator;
+use wire::formats::SampleFormat;
+use wire::packet::{PacketHeader, HEADER_LEN};
+
+/// Largest UDP payload.
+const MAX_DATAGRAM: usize = 65_507;
+
+/// Command line of the simulator.
+#[derive(Parser, Debug)]
+#[command(version, about = "Streams synthetic drone scenarios to the detection server")]
+struct Cli {
+    /// TOML scenario file, see `scenarios/` for examples.
+    scenario: PathBuf,
+    /// Address of the server's UDP listener.
+    #[arg(long, default_value = "127.0.0.1:5454")]
+    udp_addr: SocketAddr,
+    /// Sample format on the wire or in the output file: cf32, cs16, cs8 or cu8.
+    #[arg(long, default_value_t)]
+    format: SampleFormat,
+    /// Send raw samples without the packet header.
+    #[arg(long)]
+    no_header: bool,
+    /// Name of the simulated sensor on the server, sent in the packet header.
+    #[arg(long, conflicts_with = "no_header", value_parser = parse_sensor_id)]
+    sensor_id: Option<String>,
+    /// Samples per datagram.
+    #[arg(long, default_value_t = 4096)]
+    samples_per_packet: usize,
+    /// Send as fast as possible instead of at the scenario's sample rate.
+    #[arg(long)]
+    fast: bool,
+    /// Write the samples to this file instead of sending them.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Add the ground truth of the output file to this evaluation labels file (`labels.json`),
+    /// creating it if needed.
+    #[arg(long, requires = "output")]
+    labels: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let scenario = Scenario::load(&cli.scenario)?;
+    let mut simulator = Simulator::new(&scenario);
+    let header_len = if cli.no_header { 0 } else { first_header(&simulator, &cli).encoded_len() };
+    let max_samples = (MAX_DATAGRAM - header_len) / cli.format.bytes_per_sample();
+    if cli.samples_per_packet == 0 || cli.samples_per_packet > max_samples {
+        return Err(format!("samples_per_packet must be between 1 and {} for {}", max_samples, cli.format).into());
+    }
+
+    let truth = scenario.ground_truth();
+    for segment in &truth {
+        eprintln!("{}: {:.2} s to {:.2} s", segment.uav_type, segment.start_s, segment.end_s);
+    }
+
+    match &cli.output {
+        Some(path) => {
+            write_file(&mut simulator, path, &cli)?;
+            if let Some(labels) = &cli.labels {
+                add_labels(labels, path, &truth)?;
+            }
+        }
+        None => stream(&mut simulator, &cli)?,
+    }
+    eprintln!("Done, {} samples", simulator.position());
+    Ok(())
+}
+
+fn parse_sensor_id(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.len() > u8::MAX as usize {
+        return Err(format!("must be 1 to {} bytes long", u8::MAX));
+    }
+    Ok(value.into())
+}
+
+/// Header of the first datagram, moved on for the next ones.
+fn first_header(simulator: &Simulator, cli: &Cli) -> PacketHeader {
+    PacketHeader {
+        sequence: 0,
+        timestamp: 0,
+        center_freq: simulator.center_freq(),
+        sample_rate: simulator.sample_rate(),
+        format: cli.format,
+        sensor_id: cli.sensor_id.clone(),
+    }
+}
+
+fn stream(simulator: &mut Simulator, cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_write_timeout(Some(Duration::from_millis(100)))?;
+
+    let started = Instant::now();
+    let mut datagram = Vec::with_capacity(HEADER_LEN + cli.samples_per_packet * cli.format.bytes_per_sample());
+    let mut header = first_header(simulator, cli);
+
+    loop {
+        header.timestamp = simulator.position();
+        let samples = simulator.next_block(cli.samples_per_packet);
+        if samples.is_empty() {
+            return Ok(());
+        }
+
+        datagram.clear();
+        if !cli.no_header {
+            header.encode(&mut datagram);
+        }
+        cli.format.encode(&samples, &mut datagram);
+
+        // Send each datagram once its last sample would have been received
+        if !cli.fast {
+            let due = Duration::from_secs_f64(simulator.position() as f64 / simulator.sample_rate() as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        socket.send_to(&datagram, cli.udp_addr)?;
+        header.sequence += 1;
+    }
+}
+
+fn write_file(simulator: &mut Simulator, path: &Path, cli: &Cli) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut data = Vec::new();
+    loop {
+        let samples = simulator.next_block(cli.samples_per_packet);
+        if samples.is_empty() {
+            return writer.flush();
+        }
+        data.clear();
+        cli.format.encode(&samples, &mut data);
+        writer.write_all(&data)?;
+    }
+}
+
+/// Sets the segments of `recording` in the labels file, keeping the other recordings' labels.
+fn add_labels(labels: &Path, recording: &Path, truth: &[Segment]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut manifest: serde_json::Map<String, serde_json::Value> = match std::fs::read_to_string(labels) {
+        Ok(content) => serde_json::from_str(&content)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::Map::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let name = recording.file_name().and_then(|name| name.to_str()).ok_or("output has no file name")?;
+    manifest.insert(name.into(), serde_json::to_value(truth)?);
+    std::fs::write(labels, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+/// Timeline of a simulated RF environment: a receiver noise floor and the emitters heard on top
+/// of it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Samples per second.
+    pub sample_rate: u32,
+    /// Center frequency reported to the server (in Hz).
+    pub center_freq: u64,
+    pub duration_s: f64,
+    /// Power of the receiver noise (in dBFS).
+    #[serde(default = "default_noise_dbfs")]
+    pub noise_dbfs: f32,
+    /// Seed of every random choice, so a scenario always produces the same samples.
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default)]
+    pub emitters: Vec<Emitter>,
+}
+
+fn default_noise_dbfs() -> f32 {
+    -60.0
+}
+
+/// One transmitter, active between `start_s` and `end_s`. Unknown fields can't be rejected
+/// because of the flattened `signal`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Emitter {
+    /// UAV type this emitter belongs to, the ground truth of the scenario. Interferers have none.
+    pub uav_type: Option<String>,
+    #[serde(default)]
+    pub start_s: f64,
+    /// Until the end of the scenario if unset.
+    pub end_s: Option<f64>,
+    /// Power above the receiver noise (in dB) at `start_s`.
+    pub snr_db: f32,
+    /// Power above the receiver noise (in dB) at `end_s`, if it changes linearly over time.
+    pub end_snr_db: Option<f32>,
+    /// Offset from the center frequency (in Hz).
+    #[serde(default)]
+    pub freq_offset_hz: f64,
+    /// Frequency drift, e.g. of a warming oscillator (in Hz/s).
+    #[serde(default)]
+    pub drift_hz_per_s: f64,
+    #[serde(flatten)]
+    pub signal: Signal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Signal {
+    /// Frequency hopping FSK control link.
+    Fhss {
+        channels: usize,
+        channel_spacing_hz: f64,
+        /// Time between the starts of two hops.
+        hop_interval_ms: f64,
+        /// Time on air per hop, at most `hop_interval_ms`.
+        dwell_ms: f64,
+        symbol_rate: f64,
+        /// FSK frequency deviation (in Hz).
+        deviation_hz: f64,
+    },
+    /// OFDM video downlink, continuous or in periodic bursts.
+    Ofdm {
+        bandwidth_hz: f64,
+        subcarriers: usize,
+        /// Continuous if unset.
+        burst_ms: Option<f64>,
+        /// Time between the starts of two bursts, needed with `burst_ms`.
+        period_ms: Option<f64>,
+    },
+    /// Wi-Fi like OFDM frames with random lengths and gaps.
+    Wifi {
+        bandwidth_hz: f64,
+        /// Shortest and longest frame.
+        min_frame_ms: f64,
+        max_frame_ms: f64,
+        /// Fraction of the time on air.
+        duty_cycle: f64,
+    },
+    /// Band limited noise, e.g. a wideband jammer or a video link seen through a narrow receiver.
+    Noise { bandwidth_hz: f64 },
+    /// Unmodulated carrier.
+    Tone,
+}
+
+/// Time a UAV type is on air, in the format of the server's evaluation labels.
+#[derive(Serialize, Clone, Debug)]
+pub struct Segment {
+    pub uav_type: String,
+    pub start_s: f64,
+    pub end_s: f64,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ScenarioError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ScenarioError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ScenarioError::Read(path.to_path_buf(), e))?;
+        let scenario: Self = toml::from_str(&content).map_err(|e| ScenarioError::Parse(path.to_path_buf(), e))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |reason: String| Err(ScenarioError::Invalid(reason));
+        let nyquist = self.sample_rate as f64 / 2.0;
+
+        if self.sample_rate == 0 {
+            return invalid("sample_rate must be greater than 0".into());
+        }
+        if !self.duration_s.is_finite() || self.duration_s <= 0.0 {
+            return invalid(format!("duration_s must be greater than 0, got {}", self.duration_s));
+        }
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            let name = emitter.uav_type.clone().unwrap_or_else(|| format!("emitter #{}", i + 1));
+            if emitter.end_s.is_some_and(|end_s| end_s <= emitter.start_s) || emitter.start_s < 0.0 {
+                return invalid(format!("{}: start_s must be at least 0 and before end_s", name));
+            }
+            if emitter.freq_offset_hz.abs() >= nyquist {
+                return invalid(format!("{}: freq_offset_hz must be within ±{} Hz", name, nyquist));
+            }
+            let positive = |value: f64| value.is_finite() && value > 0.0;
+            let valid = match &emitter.signal {
+                Signal::Fhss { channels, channel_spacing_hz, hop_interval_ms, dwell_ms, symbol_rate, deviation_hz } => {
+                    *channels > 0 && positive(*channel_spacing_hz) && positive(*hop_interval_ms)
+                        && positive(*dwell_ms) && dwell_ms <= hop_interval_ms && positive(*symbol_rate)
+                        && positive(*deviation_hz)
+                        && (*channels as f64 * channel_spacing_hz / 2.0 + emitter.freq_offset_hz.abs()) < nyquist
+                }
+                Signal::Ofdm { bandwidth_hz, subcarriers, burst_ms, period_ms } => {
+                    positive(*bandwidth_hz) && *bandwidth_hz < self.sample_rate as f64 && *subcarriers > 0
+                        && match (burst_ms, period_ms) {
+                            (None, None) => true,
+                            (Some(burst_ms), Some(period_ms)) => positive(*burst_ms) && burst_ms <= period_ms,
+                            _ => false,
+                        }
+                }
+                Signal::Wifi { bandwidth_hz, min_frame_ms, max_frame_ms, duty_cycle } => {
+                    positive(*bandwidth_hz) && *bandwidth_hz < self.sample_rate as f64
+                        && positive(*min_frame_ms) && min_frame_ms <= max_frame_ms
+                        && *duty_cycle > 0.0 && *duty_cycle <= 1.0
+                }
+                Signal::Noise { bandwidth_hz } => positive(*bandwidth_hz),
+                Signal::Tone => true,
+            };
+            if !valid {
+                return invalid(format!("{}: invalid {:?} parameters", name, emitter.signal));
+            }
+        }
+        Ok(())
+    }
+
+    /// When each UAV type is on air, clipped to the scenario's duration.
+    pub fn ground_truth(&self) -> Vec<Segment> {
+        self.emitters.iter()
+            .filter(|emitter| emitter.start_s < self.duration_s)
+            .filter_map(|emitter| {
+                Some(Segment {
+                    uav_type: emitter.uav_type.clone()?,
+                    start_s: emitter.start_s,
+                    end_s: emitter.end_s.unwrap_or(self.duration_s).min(self.duration_s),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use num_complex::Complex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::scenario::{Emitter, Scenario, Signal};
+
+/// Synthesises the samples of a scenario, block by block.
+pub struct Simulator {
+    sample_rate: u32,
+    center_freq: u64,
+    /// Total number of samples of the scenario.
+    length: u64,
+    position: u64,
+    /// Standard deviation of each of I and Q of the receiver noise.
+    noise_sigma: f64,
+    noise_power: f64,
+    emitters: Vec<EmitterState>,
+    rng: StdRng,
+}
+
+impl Simulator {
+    pub fn new(scenario: &Scenario) -> Self {
+        let fs = scenario.sample_rate as f64;
+        let length = (scenario.duration_s * fs).round() as u64;
+        let noise_power = 10f64.powf(scenario.noise_dbfs as f64 / 10.0);
+        let emitters = scenario.emitters.iter().enumerate()
+            .map(|(i, emitter)| EmitterState::new(emitter, fs, length, scenario.seed.wrapping_add(i as u64 + 1)))
+            .collect();
+
+        Self {
+            sample_rate: scenario.sample_rate,
+            center_freq: scenario.center_freq,
+            length,
+            position: 0,
+            noise_sigma: (noise_power / 2.0).sqrt(),
+            noise_power,
+            emitters,
+            rng: StdRng::seed_from_u64(scenario.seed),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn center_freq(&self) -> u64 {
+        self.center_freq
+    }
+
+    /// Number of samples generated so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Next `max` samples at most. Returns an empty vector at the end of the scenario.
+    pub fn next_block(&mut self, max: usize) -> Vec<Complex<f32>> {
+        let n = (max as u64).min(self.length - self.position) as usize;
+        let mut block: Vec<Complex<f64>> = (0..n)
+            .map(|_| gaussian(&mut self.rng, self.noise_sigma))
+            .collect();
+
+        for emitter in &mut self.emitters {
+            emitter.add_to(&mut block, self.position, self.noise_power);
+        }
+        self.position += n as u64;
+
+        block.into_iter().map(|sample| Complex::new(sample.re as f32, sample.im as f32)).collect()
+    }
+}
+
+/// Complex Gaussian sample with a standard deviation of `sigma` on each of I and Q (Box-Muller).
+fn gaussian(rng: &mut StdRng, sigma: f64) -> Complex<f64> {
+    let u1: f64 = rng.random::<f64>().max(f64::MIN_POSITIVE);
+    let u2: f64 = rng.random();
+    let radius = sigma * (-2.0 * u1.ln()).sqrt();
+    Complex::from_polar(radius, 2.0 * PI * u2)
+}
+
+struct EmitterState {
+    /// First and last (excluded) sample it is active.
+    start: u64,
+    end: u64,
+    snr_db: (f64, f64),
+    fs: f64,
+    freq_offset: f64,
+    drift: f64,
+    carrier_phase: f64,
+    modulation: Modulation,
+    rng: StdRng,
+}
+
+impl EmitterState {
+    fn new(emitter: &Emitter, fs: f64, length: u64, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let start = ((emitter.start_s * fs).round() as u64).min(length);
+        let end = emitter.end_s.map_or(length, |end_s| ((end_s * fs).round() as u64).min(length));
+        let snr_db = emitter.snr_db as f64;
+        Self {
+            start,
+            end,
+            snr_db: (snr_db, emitter.end_snr_db.map_or(snr_db, |end_snr_db| end_snr_db as f64)),
+            fs,
+            freq_offset: emitter.freq_offset_hz,
+            drift: emitter.drift_hz_per_s,
+            carrier_phase: 0.0,
+            modulation: Modulation::new(&emitter.signal, fs, &mut rng),
+            rng,
+        }
+    }
+
+    /// Adds this emitter's samples to `block`, which starts at sample `position` of the scenario.
+    fn add_to(&mut self, block: &mut [Complex<f64>], position: u64, noise_power: f64) {
+        for (i, sample) in block.iter_mut().enumerate() {
+            let n = position + i as u64;
+            if n < self.start || n >= self.end {
+                continue;
+            }
+            let elapsed = (n - self.start) as f64;
+            let progress = elapsed / (self.end - self.start) as f64;
+            let snr_db = self.snr_db.0 + (self.snr_db.1 - self.snr_db.0) * progress;
+            let amplitude = (noise_power * 10f64.powf(snr_db / 10.0)).sqrt();
+
+            let baseband = self.modulation.next(self.fs, &mut self.rng);
+            *sample += baseband * Complex::from_polar(amplitude, self.carrier_phase);
+
+            let freq = self.freq_offset + self.drift * elapsed / self.fs;
+            self.carrier_phase = (self.carrier_phase + 2.0 * PI * freq / self.fs) % (2.0 * PI);
+        }
+    }
+}
+
+/// Baseband waveform of an emitter, with a mean power of 1 while on air.
+enum Modulation {
+    Fhss {
+        /// Offset of every channel from the emitter's frequency (in Hz).
+        channels: Vec<f64>,
+        /// Channel of each hop, repeated.
+        sequence: Vec<usize>,
+        hop_samples: f64,
+        dwell_samples: f64,
+        symbol_samples: f64,
+        deviation: f64,
+        /// Samples since the emitter started.
+        n: u64,
+        /// Index and value (±1) of the current symbol.
+        symbol: (u64, f64),
+        phase: f64,
+    },
+    Ofdm {
+        ofdm: Ofdm,
+        gate: Gate,
+    },
+    Noise {
+        /// Recent white noise samples, averaged to limit the bandwidth.
+        taps: VecDeque<Complex<f64>>,
+        length: usize,
+        sum: Complex<f64>,
+    },
+    Tone,
+}
+
+impl Modulation {
+    fn new(signal: &Signal, fs: f64, rng: &mut StdRng) -> Self {
+        let samples = |ms: f64| ms * fs / 1000.0;
+        match signal {
+            Signal::Fhss { channels, channel_spacing_hz, hop_interval_ms, dwell_ms, symbol_rate, deviation_hz } => {
+                let centre = (*channels as f64 - 1.0) / 2.0;
+                Modulation::Fhss {
+                    channels: (0..*channels).map(|i| (i as f64 - centre) * channel_spacing_hz).collect(),
+                    sequence: (0..channels * 4).map(|_| rng.random_range(0..*channels)).collect(),
+                    hop_samples: samples(*hop_interval_ms).max(1.0),
+                    dwell_samples: samples(*dwell_ms),
+                    symbol_samples: (fs / symbol_rate).max(1.0),
+                    deviation: *deviation_hz,
+                    n: 0,
+                    symbol: (u64::MAX, 1.0),
+                    phase: 0.0,
+                }
+            }
+            Signal::Ofdm { bandwidth_hz, subcarriers, burst_ms, period_ms } => Modulation::Ofdm {
+                ofdm: Ofdm::new(*bandwidth_hz, *subcarriers, fs),
+                gate: match (burst_ms, period_ms) {
+                    (Some(burst_ms), Some(period_ms)) => Gate::Periodic {
+                        on: samples(*burst_ms) as u64,
+                        period: (samples(*period_ms) as u64).max(1),
+                        n: 0,
+                    },
+                    _ => Gate::Always,
+                },
+            },
+            Signal::Wifi { bandwidth_hz, min_frame_ms, max_frame_ms, duty_cycle } => {
+                // Roughly 312.5 kHz per subcarrier, like 802.11a/g/n.
+                let subcarriers = ((bandwidth_hz / 312_500.0).round() as usize).clamp(8, 64);
+                Modulation::Ofdm {
+                    ofdm: Ofdm::new(*bandwidth_hz, subcarriers, fs),
+                    gate: Gate::Random {
+                        frame: (samples(*min_frame_ms).max(1.0) as u64, samples(*max_frame_ms).max(1.0) as u64),
+                        duty_cycle: *duty_cycle,
+                        on: false,
+                        remaining: 0,
+                    },
+                }
+            }
+            Signal::Noise { bandwidth_hz } => {
+                let length = ((fs / bandwidth_hz).round() as usize).max(1);
+                Modulation::Noise { taps: VecDeque::with_capacity(length), length, sum: Complex::new(0.0, 0.0) }
+            }
+            Signal::Tone => Modulation::Tone,
+        }
+    }
+
+    fn next(&mut self, fs: f64, rng: &mut StdRng) -> Complex<f64> {
+        match self {
+            Modulation::Fhss { channels, sequence, hop_samples, dwell_samples, symbol_samples, deviation, n, symbol, phase } => {
+                let sample = *n as f64;
+                *n += 1;
+                let hop = (sample / *hop_samples) as usize;
+                if sample - hop as f64 * *hop_samples >= *dwell_samples {
+                    return Complex::new(0.0, 0.0);
+                }
+                let index = (sample / *symbol_samples) as u64;
+                if index != symbol.0 {
+                    *symbol = (index, if rng.random::<bool>() { 1.0 } else { -1.0 });
+                }
+                let freq = channels[sequence[hop % sequence.len()]] + symbol.1 * *deviation;
+                *phase = (*phase + 2.0 * PI * freq / fs) % (2.0 * PI);
+                Complex::from_polar(1.0, *phase)
+            }
+            Modulation::Ofdm { ofdm, gate } => {
+                let sample = ofdm.next(rng);
+                if gate.is_on(rng) { sample } else { Complex::new(0.0, 0.0) }
+            }
+            Modulation::Noise { taps, length, sum } => {
+                let sample = gaussian(rng, std::f64::consts::FRAC_1_SQRT_2);
+                taps.push_back(sample);
+                *sum += sample;
+                if taps.len() > *length {
+                    *sum -= taps.pop_front().unwrap();
+                }
+                *sum / (*length as f64).sqrt()
+            }
+            Modulation::Tone => Complex::new(1.0, 0.0),
+        }
+    }
+}
+
+/// QPSK on evenly spaced subcarriers, without a cyclic prefix.
+struct Ofdm {
+    /// Rotation of every subcarrier per sample.
+    steps: Vec<Complex<f64>>,
+    /// Current value of every subcarrier.
+    phasors: Vec<Complex<f64>>,
+    symbol_samples: u64,
+    n: u64,
+}
+
+impl Ofdm {
+    fn new(bandwidth: f64, subcarriers: usize, fs: f64) -> Self {
+        let spacing = bandwidth / subcarriers as f64;
+        let centre = (subcarriers as f64 - 1.0) / 2.0;
+        Self {
+            steps: (0..subcarriers)
+                .map(|k| Complex::from_polar(1.0, 2.0 * PI * (k as f64 - centre) * spacing / fs))
+                .collect(),
+            phasors: vec![Complex::new(0.0, 0.0); subcarriers],
+            symbol_samples: ((fs / spacing).round() as u64).max(1),
+            n: 0,
+        }
+    }
+
+    fn next(&mut self, rng: &mut StdRng) -> Complex<f64> {
+        if self.n.is_multiple_of(self.symbol_samples) {
+            let scale = 1.0 / (self.phasors.len() as f64).sqrt();
+            for phasor in &mut self.phasors {
+                let quadrant = rng.random_range(0..4) as f64;
+                *phasor = Complex::from_polar(scale, PI / 4.0 + quadrant * PI / 2.0);
+            }
+        }
+        self.n += 1;
+
+        let sample = self.phasors.iter().sum();
+        for (phasor, step) in self.phasors.iter_mut().zip(&self.steps) {
+            *phasor *= step;
+        }
+        sample
+    }
+}
+
+/// When a bursty emitter is on air.
+enum Gate {
+    Always,
+    /// `on` samples out of every `period`.
+    Periodic { on: u64, period: u64, n: u64 },
+    /// Frames of random length, separated by random gaps that average out to the duty cycle.
+    Random { frame: (u64, u64), duty_cycle: f64, on: bool, remaining: u64 },
+}
+
+impl Gate {
+    fn is_on(&mut self, rng: &mut StdRng) -> bool {
+        match self {
+            Gate::Always => true,
+            Gate::Periodic { on, period, n } => {
+                let is_on = *n % *period < *on;
+                *n += 1;
+                is_on
+            }
+            Gate::Random { frame, duty_cycle, on, remaining } => {
+                while *remaining == 0 {
+                    *on = !*on;
+                    let mean_frame = (frame.0 + frame.1) as f64 / 2.0;
+                    *remaining = if *on {
+                        rng.random_range(frame.0..=frame.1)
+                    } else {
+                        let mean_gap = mean_frame * (1.0 - *duty_cycle) / *duty_cycle;
+                        (rng.random::<f64>() * 2.0 * mean_gap) as u64
+                    };
+                }
+                *remaining -= 1;
+                *on
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::processing::DetectionInfo;
+
+/// Default score a UAV type needs to open a track.
+pub const RAISE_THRESHOLD: f32 = 0.7;
+/// Default score below which a track counts as lost. Lower than `RAISE_THRESHOLD` so a score
+/// hovering around one threshold doesn't toggle the alarm.
+pub const CLEAR_THRESHOLD: f32 = 0.5;
+/// Default time a candidate has to stay above the clear threshold before the alarm is raised (in ms).
+pub const MIN_DWELL_MS: u64 = 1500;
+/// Default time a lost track is kept before the alarm is cleared (in ms).
+pub const HANG_TIME_MS: u64 = 3000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlarmConfig {
+    pub raise_threshold: f32,
+    pub clear_threshold: f32,
+    pub min_dwell_ms: u64,
+    pub hang_time_ms: u64,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self {
+            raise_threshold: RAISE_THRESHOLD,
+            clear_threshold: CLEAR_THRESHOLD,
+            min_dwell_ms: MIN_DWELL_MS,
+            hang_time_ms: HANG_TIME_MS,
+        }
+    }
+}
+
+impl AlarmConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.raise_threshold) {
+            return Err(format!("alarm.raise_threshold must be between 0 and 1, got {}", self.raise_threshold));
+        }
+        if !(0.0..=self.raise_threshold).contains(&self.clear_threshold) {
+            return Err(format!(
+                "alarm.clear_threshold must be between 0 and raise_threshold ({}), got {}",
+                self.raise_threshold, self.clear_threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Sent to WebSocket clients when an alarm changes. Timestamps are in ms since the Unix epoch.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event")]
+pub enum AlarmEvent {
+    /// A UAV type stayed above the thresholds long enough to be trusted.
+    #[serde(rename = "alarm_raised")]
+    Raised { uav_type: String, sensor_id: String, center_freq: Option<u64>, score: f32, raised_at: u64 },
+    /// The alarm is still active, sent on every detection while it is.
+    #[serde(rename = "alarm_updated")]
+    Updated {
+        uav_type: String,
+        sensor_id: String,
+        center_freq: Option<u64>,
+        score: f32,
+        peak_score: f32,
+        raised_at: u64,
+    },
+    /// The UAV type wasn't seen again within the hang time.
+    #[serde(rename = "alarm_cleared")]
+    Cleared {
+        uav_type: String,
+        sensor_id: String,
+        center_freq: Option<u64>,
+        peak_score: f32,
+        raised_at: u64,
+        cleared_at: u64,
+    },
+}
+
+impl AlarmEvent {
+    pub fn uav_type(&self) -> &str {
+        match self {
+            AlarmEvent::Raised { uav_type, .. }
+            | AlarmEvent::Updated { uav_type, .. }
+            | AlarmEvent::Cleared { uav_type, .. } => uav_type,
+        }
+    }
+
+    pub fn sensor_id(&self) -> &str {
+        match self {
+            AlarmEvent::Raised { sensor_id, .. }
+            | AlarmEvent::Updated { sensor_id, .. }
+            | AlarmEvent::Cleared { sensor_id, .. } => sensor_id,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TrackState {
+    Idle,
+    /// Above the raise threshold since `since`, not trusted yet.
+    Candidate { since: u64 },
+    Confirmed,
+    /// Dropped below the clear threshold at `since`, the alarm is still active.
+    Lost { since: u64 },
+}
+
+#[derive(Debug)]
+struct Track {
+    state: TrackState,
+    raised_at: u64,
+    peak_score: f32,
+}
+
+/// Turns the stream of detections of one band into alarms, with one track per UAV type going
+/// idle → candidate → confirmed → lost → idle.
+pub struct AlarmTracker {
+    config: AlarmConfig,
+    sensor_id: String,
+    /// Center frequency of the band the detections come from, if known.
+    center_freq: Option<u64>,
+    tracks: HashMap<String, Track>,
+}
+
+impl AlarmTracker {
+    pub fn new(config: &AlarmConfig, sensor_id: &str, center_freq: Option<u64>) -> Self {
+        Self {
+            config: config.clone(),
+            sensor_id: sensor_id.into(),
+            center_freq,
+            tracks: HashMap::new(),
+        }
+    }
+
+    /// Feeds a detection. Every UAV type other than the detected one counts as a score of 0.
+    pub fn update(&mut self, detection: &DetectionInfo) -> Vec<AlarmEvent> {
+        let observed = (detection.uav_type() != "Unknown").then(|| (detection.uav_type(), detection.score()));
+        self.advance(detection.timestamp(), observed)
+    }
+
+    /// Advances the tracks when no detection could be made, so alarms still clear once the
+    /// samples stop.
+    pub fn tick(&mut self, now: u64) -> Vec<AlarmEvent> {
+        self.advance(now, None)
+    }
+
+    /// Ends every track at `now`, for when the band's detections stop for good. Active alarms are
+    /// cleared rather than silently dropped.
+    pub fn clear(&mut self, now: u64) -> Vec<AlarmEvent> {
+        self.tracks.drain()
+            .filter(|(_, track)| matches!(track.state, TrackState::Confirmed | TrackState::Lost { .. }))
+            .map(|(uav_type, track)| {
+                Transition::Cleared.into_alarm(&uav_type, &self.sensor_id, self.center_freq, 0.0, &track, now)
+            })
+            .collect()
+    }
+
+    fn advance(&mut self, now: u64, observed: Option<(&str, f32)>) -> Vec<AlarmEvent> {
+        if let Some((uav_type, _)) = observed {
+            self.tracks.entry(uav_type.into()).or_insert(Track { state: TrackState::Idle, raised_at: 0, peak_score: 0.0 });
+        }
+
+        let mut events = Vec::new();
+        for (uav_type, track) in &mut self.tracks {
+            let score = match observed {
+                Some((observed_type, score)) if observed_type == uav_type => score,
+                _ => 0.0,
+            };
+            let event = step(&self.config, track, now, score);
+            events.extend(event.map(|event| event.into_alarm(uav_type, &self.sensor_id, self.center_freq, score, track, now)));
+        }
+
+        self.tracks.retain(|_, track| !matches!(track.state, TrackState::Idle));
+        events
+    }
+}
+
+enum Transition {
+    Raised,
+    Updated,
+    Cleared,
+}
+
+impl Transition {
+    fn into_alarm(
+        self,
+        uav_type: &str,
+        sensor_id: &str,
+        center_freq: Option<u64>,
+        score: f32,
+        track: &Track,
+        now: u64,
+    ) -> AlarmEvent {
+        let (uav_type, sensor_id) = (uav_type.to_string(), sensor_id.to_string());
+        match self {
+            Transition::Raised => AlarmEvent::Raised { uav_type, sensor_id, center_freq, score, raised_at: track.raised_at },
+            Transition::Updated => AlarmEvent::Updated {
+                uav_type, sensor_id, center_freq, score, peak_score: track.peak_score, raised_at: track.raised_at,
+            },
+            Transition::Cleared => AlarmEvent::Cleared {
+                uav_type, sensor_id, center_freq, peak_score: track.peak_score, raised_at: track.raised_at, cleared_at: now,
+            },
+        }
+    }
+}
+
+fn step(config: &AlarmConfig, track: &mut Track, now: u64, score: f32) -> Option<Transition> {
+    let above_clear = score >= config.clear_threshold;
+
+    match track.state {
+        TrackState::Idle => {
+            if score >= config.raise_threshold {
+                track.state = TrackState::Candidate { since: now };
+                track.peak_score = score;
+            }
+            None
+        }
+        TrackState::Candidate { since } => {
+            if !above_clear {
+                track.state = TrackState::Idle;
+                return None;
+            }
+            track.peak_score = track.peak_score.max(score);
+            if now.saturating_sub(since) < config.min_dwell_ms {
+                return None;
+            }
+            track.state = TrackState::Confirmed;
+            track.raised_at = now;
+            Some(Transition::Raised)
+        }
+        TrackState::Confirmed | TrackState::Lost { .. } if above_clear => {
+            track.state = TrackState::Confirmed;
+            track.peak_score = track.peak_score.max(score);
+            Some(Transition::Updated)
+        }
+        TrackState::Confirmed => {
+            track.state = TrackState::Lost { since: now };
+            Some(Transition::Updated)
+        }
+        TrackState::Lost { since } => {
+            if now.saturating_sub(since) < config.hang_time_ms {
+                return None;
+            }
+            track.state = TrackState::Idle;
+            Some(Transition::Cleared)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: AlarmConfig = AlarmConfig {
+        raise_threshold: 0.7,
+        clear_threshold: 0.5,
+        min_dwell_ms: 1000,
+        hang_time_ms: 2000,
+    };
+
+    fn tracker() -> AlarmTracker {
+        AlarmTracker::new(&CONFIG, "sensor", Some(915_000_000))
+    }
+
+    fn names(events: &[AlarmEvent]) -> Vec<&'static str> {
+        events.iter()
+            .map(|event| match event {
+                AlarmEvent::Raised { .. } => "raised",
+                AlarmEvent::Updated { .. } => "updated",
+                AlarmEvent::Cleared { .. } => "cleared",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raises_after_staying_above_the_thresholds_for_min_dwell() {
+        let mut alarms = tracker();
+        // Below the raise threshold nothing starts, even above the clear threshold
+        assert!(alarms.advance(0, Some(("Drone 1", 0.6))).is_empty());
+        assert!(alarms.advance(2000, Some(("Drone 1", 0.6))).is_empty());
+
+        assert!(alarms.advance(3000, Some(("Drone 1", 0.8))).is_empty());
+        // Candidates only need to stay above the clear threshold
+        assert!(alarms.advance(3500, Some(("Drone 1", 0.55))).is_empty());
+        let events = alarms.advance(4000, Some(("Drone 1", 0.9)));
+        assert_eq!(names(&events), ["raised"]);
+        let AlarmEvent::Raised { uav_type, score, raised_at, .. } = &events[0] else { unreachable!() };
+        assert_eq!((uav_type.as_str(), *score, *raised_at), ("Drone 1", 0.9, 4000));
+    }
+
+    #[test]
+    fn candidates_falling_below_the_clear_threshold_are_dropped() {
+        let mut alarms = tracker();
+        alarms.advance(0, Some(("Drone 1", 0.8)));
+        assert!(alarms.advance(500, Some(("Drone 1", 0.4))).is_empty());
+        // The dwell starts over
+        assert!(alarms.advance(1000, Some(("Drone 1", 0.8))).is_empty());
+        assert!(alarms.advance(1500, Some(("Drone 1", 0.8))).is_empty());
+        assert_eq!(names(&alarms.advance(2000, Some(("Drone 1", 0.8)))), ["raised"]);
+
+        // Another type's detection counts as a score of 0
+        let mut alarms = tracker();
+        alarms.advance(0, Some(("Drone 1", 0.8)));
+        alarms.advance(500, Some(("Drone 2", 0.8)));
+        assert!(alarms.advance(1000, Some(("Drone 1", 0.8))).is_empty());
+    }
+
+    #[test]
+    fn hysteresis_keeps_the_alarm_between_the_thresholds() {
+        let mut alarms = tracker();
+        alarms.advance(0, Some(("Drone 1", 0.8)));
+        alarms.advance(1000, Some(("Drone 1", 0.8)));
+
+        let events = alarms.advance(1500, Some(("Drone 1", 0.6)));
+        assert_eq!(names(&events), ["updated"]);
+        let AlarmEvent::Updated { score, peak_score, raised_at, .. } = &events[0] else { unreachable!() };
+        assert_eq!((*score, *peak_score, *raised_at), (0.6, 0.8, 1000));
+
+        // Lost, but the alarm stays active and recovers above the clear threshold
+        assert_eq!(names(&alarms.advance(2000, Some(("Drone 1", 0.3)))), ["updated"]);
+        assert!(alarms.advance(3000, None).is_empty());
+        assert_eq!(names(&alarms.advance(3500, Some(("Drone 1", 0.5)))), ["updated"]);
+        assert_eq!(names(&alarms.advance(4000, None)), ["updated"]);
+        // The hang time runs from the last loss
+        assert!(alarms.advance(5900, None).is_empty());
+    }
+
+    #[test]
+    fn clears_after_the_hang_time() {
+        let mut alarms = tracker();
+        alarms.advance(0, Some(("Drone 1", 0.8)));
+        alarms.advance(1000, Some(("Drone 1", 0.95)));
+        assert_eq!(names(&alarms.advance(1500, None)), ["updated"]);
+        assert!(alarms.tick(3499).is_empty());
+
+        let events = alarms.tick(3500);
+        assert_eq!(names(&events), ["cleared"]);
+        let AlarmEvent::Cleared { peak_score, raised_at, cleared_at, .. } = &events[0] else { unreachable!() };
+        assert_eq!((*peak_score, *raised_at, *cleared_at), (0.95, 1000, 3500));
+
+        // The track is gone, a new one needs the full dwell again
+        assert!(alarms.tick(10_000).is_empty());
+        assert!(alarms.advance(11_000, Some(("Drone 1", 0.8))).is_empty());
+        assert!(alarms.advance(11_500, Some(("Drone 1", 0.8))).is_empty());
+    }
+
+    #[test]
+    fn clear_ends_only_active_alarms() {
+        let mut alarms = tracker();
+        alarms.advance(0, Some(("Drone 1", 0.8)));
+        alarms.advance(1000, Some(("Drone 1", 0.8)));
+        alarms.advance(1500, Some(("Drone 2", 0.8)));
+
+        let events = alarms.clear(2000);
+        assert_eq!(names(&events), ["cleared"]);
+        assert_eq!(events[0].uav_type(), "Drone 1");
+        assert!(alarms.tick(10_000).is_empty());
+    }
+}
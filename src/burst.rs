@@ -0,0 +1,328 @@
+use std::collections::VecDeque;
+
+use num_complex::Complex;
+use serde::{Serialize, Deserialize};
+
+use crate::utils::{compute_spectrum, parameter_score};
+
+/// Default of how far the envelope must rise above the noise to start a burst (in dB).
+pub const BURST_ON_DB: f32 = 10.0;
+/// Default of how far below `BURST_ON_DB` the envelope must fall to end a burst (in dB).
+pub const BURST_HYSTERESIS_DB: f32 = 3.0;
+/// Default number of samples the envelope is averaged over.
+pub const ENVELOPE_SAMPLES: usize = 32;
+/// Default of how far back bursts are kept for the features (in ms).
+pub const BURST_HISTORY_MS: u64 = 2000;
+/// Default relative deviation from a signature's burst parameters that still scores above 0.
+pub const BURST_TOLERANCE: f32 = 0.25;
+
+/// Weights of each new envelope value in the noise estimate, which only follows the envelope
+/// outside of bursts. It falls faster than it rises, so it settles near the quiet parts between
+/// bursts rather than on the average power.
+const NOISE_ALPHA_DOWN: f32 = 1e-3;
+const NOISE_ALPHA_UP: f32 = 1e-5;
+/// Lowest noise power the thresholds are relative to.
+const MIN_NOISE: f32 = 1e-12;
+/// Most samples of a burst kept to measure its bandwidth.
+const MAX_BURST_SAMPLES: usize = 8192;
+/// Fraction of a burst's power its bandwidth has to contain.
+const OCCUPIED_POWER: f32 = 0.9;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BurstConfig {
+    pub threshold_db: f32,
+    pub hysteresis_db: f32,
+    pub envelope_samples: usize,
+    pub history_ms: u64,
+}
+
+impl Default for BurstConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: BURST_ON_DB,
+            hysteresis_db: BURST_HYSTERESIS_DB,
+            envelope_samples: ENVELOPE_SAMPLES,
+            history_ms: BURST_HISTORY_MS,
+        }
+    }
+}
+
+impl BurstConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.threshold_db.is_finite() || self.threshold_db <= 0.0 {
+            return Err(format!("bursts.threshold_db must be greater than 0, got {}", self.threshold_db));
+        }
+        if !(0.0..=self.threshold_db).contains(&self.hysteresis_db) {
+            return Err(format!(
+                "bursts.hysteresis_db must be between 0 and threshold_db ({}), got {}",
+                self.threshold_db, self.hysteresis_db
+            ));
+        }
+        if self.envelope_samples == 0 {
+            return Err("bursts.envelope_samples must be greater than 0".into());
+        }
+        if self.history_ms == 0 {
+            return Err("bursts.history_ms must be greater than 0".into());
+        }
+        Ok(())
+    }
+}
+
+/// Burst parameters of a UAV type's downlinks, from the signature file. Parameters left out
+/// aren't compared.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BurstPattern {
+    pub duration_ms: Option<f32>,
+    pub pri_ms: Option<f32>,
+    pub duty_cycle: Option<f32>,
+    pub bandwidth_hz: Option<f32>,
+    /// Relative deviation from each parameter that still scores above 0.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f32,
+}
+
+fn default_tolerance() -> f32 {
+    BURST_TOLERANCE
+}
+
+impl BurstPattern {
+    fn parameters(&self) -> [Option<f32>; 4] {
+        [self.duration_ms, self.pri_ms, self.duty_cycle, self.bandwidth_hz]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.parameters().iter().all(Option::is_none) {
+            return Err("bursts needs at least one parameter".into());
+        }
+        if self.parameters().iter().flatten().any(|value| !value.is_finite() || *value <= 0.0) {
+            return Err("burst parameters must be greater than 0".into());
+        }
+        if !self.tolerance.is_finite() || self.tolerance <= 0.0 {
+            return Err(format!("bursts tolerance must be greater than 0, got {}", self.tolerance));
+        }
+        Ok(())
+    }
+
+    /// How close the features are to this pattern, see `parameter_score`.
+    pub fn score(&self, features: &BurstFeatures) -> f32 {
+        let measured = [Some(features.duration_ms), features.pri_ms, features.duty_cycle, features.bandwidth_hz];
+        parameter_score(&self.parameters(), &measured, self.tolerance)
+    }
+}
+
+/// Time-domain features of the recent bursts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BurstFeatures {
+    pub bursts: usize,
+    /// Median burst duration.
+    pub duration_ms: f32,
+    /// Median pulse repetition interval, i.e. time between the starts of two bursts. `None` with a
+    /// single burst.
+    pub pri_ms: Option<f32>,
+    /// Fraction of the time a burst is on, `None` with a single burst.
+    pub duty_cycle: Option<f32>,
+    /// Median bandwidth holding 90% of a burst's power.
+    pub bandwidth_hz: Option<f32>,
+}
+
+/// Times are sample indices.
+struct Burst {
+    start: u64,
+    duration: u64,
+    bandwidth: Option<f32>,
+}
+
+/// Finds bursts in the raw sample stream by thresholding its envelope against the noise, with
+/// hysteresis so a burst doesn't break up when it fades for a moment.
+pub struct BurstDetector {
+    config: BurstConfig,
+    sample_rate: u32,
+    /// Sample index of the next sample.
+    position: u64,
+    /// Power of the last `envelope_samples` samples and their sum.
+    window: VecDeque<f32>,
+    window_sum: f64,
+    /// Mean envelope outside of bursts.
+    noise: Option<f32>,
+    /// Start and samples of the burst in progress.
+    current: Option<(u64, Vec<Complex<f32>>)>,
+    /// Finished bursts within `history_ms`, oldest first.
+    bursts: VecDeque<Burst>,
+}
+
+impl BurstDetector {
+    pub fn new(config: &BurstConfig, sample_rate: u32) -> Self {
+        Self {
+            config: config.clone(),
+            sample_rate,
+            position: 0,
+            window: VecDeque::with_capacity(config.envelope_samples),
+            window_sum: 0.0,
+            noise: None,
+            current: None,
+            bursts: VecDeque::new(),
+        }
+    }
+
+    /// Forgets the bursts, for when the sample stream is interrupted. The noise estimate is kept.
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.window_sum = 0.0;
+        self.current = None;
+        self.bursts.clear();
+    }
+
+    /// Non-finite samples (e.g. from a broken sender) only advance the clock, so they can't poison
+    /// the envelope and noise estimate.
+    pub fn process(&mut self, samples: &[Complex<f32>]) {
+        let on = 10f32.powf(self.config.threshold_db / 10.0);
+        let off = 10f32.powf((self.config.threshold_db - self.config.hysteresis_db) / 10.0);
+
+        for &sample in samples {
+            self.position += 1;
+            let power = sample.norm_sqr();
+            if !power.is_finite() {
+                continue;
+            }
+            self.window.push_back(power);
+            self.window_sum += power as f64;
+            if self.window.len() > self.config.envelope_samples {
+                self.window_sum -= self.window.pop_front().unwrap_or(0.0) as f64;
+            }
+            if self.window.len() < self.config.envelope_samples {
+                continue;
+            }
+            let envelope = (self.window_sum.max(0.0) / self.window.len() as f64) as f32;
+            // Floored so silence (e.g. zero filled gaps) doesn't make every sample a burst
+            let noise = self.noise.get_or_insert(envelope).max(MIN_NOISE);
+
+            match &mut self.current {
+                Some((_, burst_samples)) if envelope >= off * noise => {
+                    if burst_samples.len() < MAX_BURST_SAMPLES {
+                        burst_samples.push(sample);
+                    }
+                }
+                Some(_) => self.finish_burst(),
+                None if envelope > on * noise => {
+                    self.current = Some((self.position - 1, vec![sample]));
+                }
+                None => {
+                    let alpha = if envelope < noise { NOISE_ALPHA_DOWN } else { NOISE_ALPHA_UP };
+                    self.noise = Some(noise + alpha * (envelope - noise));
+                }
+            }
+        }
+    }
+
+    fn finish_burst(&mut self) {
+        let Some((start, samples)) = self.current.take() else {
+            return;
+        };
+        // A strong burst crosses the threshold right away, but keeps the envelope up until it has
+        // left the averaging window
+        let end = self.position.saturating_sub(self.config.envelope_samples as u64);
+        // Shorter ones are noise spikes the envelope didn't smooth out
+        if end.saturating_sub(start) < self.config.envelope_samples as u64 {
+            return;
+        }
+        self.bursts.push_back(Burst {
+            start,
+            duration: end - start,
+            bandwidth: occupied_bandwidth(&samples, self.sample_rate),
+        });
+    }
+
+    /// Features of the bursts within `history_ms`, `None` if there were none.
+    pub fn features(&mut self) -> Option<BurstFeatures> {
+        let history = self.config.history_ms * self.sample_rate as u64 / 1000;
+        while self.bursts.front().is_some_and(|burst| burst.start + history < self.position) {
+            self.bursts.pop_front();
+        }
+        if self.bursts.is_empty() {
+            return None;
+        }
+
+        let ms = |samples: u64| 1000.0 * samples as f32 / self.sample_rate as f32;
+        let duration_ms = median(self.bursts.iter().map(|burst| ms(burst.duration)).collect());
+        let intervals: Vec<f32> = self.bursts.iter()
+            .zip(self.bursts.iter().skip(1))
+            .map(|(previous, next)| ms(next.start - previous.start))
+            .collect();
+        let pri_ms = (!intervals.is_empty()).then(|| median(intervals));
+        let bandwidths: Vec<f32> = self.bursts.iter().filter_map(|burst| burst.bandwidth).collect();
+
+        Some(BurstFeatures {
+            bursts: self.bursts.len(),
+            duration_ms,
+            pri_ms,
+            duty_cycle: pri_ms.filter(|pri| *pri > 0.0).map(|pri| (duration_ms / pri).min(1.0)),
+            bandwidth_hz: (!bandwidths.is_empty()).then(|| median(bandwidths)),
+        })
+    }
+}
+
+/// Width of the band holding `OCCUPIED_POWER` of the power, centered on the power's median.
+fn occupied_bandwidth(samples: &[Complex<f32>], sample_rate: u32) -> Option<f32> {
+    let spectrum = compute_spectrum(samples, sample_rate).ok()?;
+    let powers: Vec<(f32, f32)> = spectrum.data().iter()
+        .map(|(freq, magnitude)| (freq.val(), magnitude.val() * magnitude.val()))
+        .collect();
+    let total: f32 = powers.iter().map(|(_, power)| power).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let edge = (1.0 - OCCUPIED_POWER) / 2.0 * total;
+    let mut cumulative = 0.0;
+    let (mut low, mut high) = (None, None);
+    for (freq, power) in &powers {
+        cumulative += power;
+        if low.is_none() && cumulative >= edge {
+            low = Some(*freq);
+        }
+        if high.is_none() && cumulative >= total - edge {
+            high = Some(*freq);
+        }
+    }
+    Some(high? - low? + spectrum.frequency_resolution())
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 10_000;
+
+    /// 5 ms bursts every 20 ms over a weak noise-like background, 1 s long.
+    fn bursts() -> Vec<Complex<f32>> {
+        (0..SAMPLE_RATE as usize)
+            .map(|i| {
+                let background = 0.01 * Complex::new((i * 7919 % 13) as f32 - 6.0, (i * 104_729 % 11) as f32 - 5.0);
+                if i % 200 < 50 { Complex::new(1.0, 0.0) + background } else { background }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn non_finite_samples_dont_poison_the_detector() {
+        let mut detector = BurstDetector::new(&BurstConfig::default(), SAMPLE_RATE);
+        let mut samples = bursts();
+        samples[1234] = Complex::new(f32::NAN, 0.0);
+        samples[4321] = Complex::new(f32::INFINITY, f32::NEG_INFINITY);
+        detector.process(&samples);
+        detector.process(&[Complex::new(f32::NAN, f32::NAN); 100]);
+        detector.process(&bursts());
+
+        let features = detector.features().unwrap();
+        assert!(features.bursts >= 50, "{:?}", features);
+        assert!((features.duration_ms - 5.0).abs() < 0.5, "{:?}", features);
+        assert!((features.pri_ms.unwrap() - 20.0).abs() < 0.1, "{:?}", features);
+    }
+}
@@ -0,0 +1,290 @@
+use serde::{Serialize, Deserialize};
+
+use crate::psd::{density_to_db, Psd};
+
+/// Default number of cells right next to the cell under test left out of the noise estimate.
+pub const GUARD_CELLS: usize = 2;
+/// Default number of cells on each side the noise around the cell under test is estimated from.
+pub const TRAINING_CELLS: usize = 16;
+/// Default of how far above the local noise a cell must be to count as an emission (in dB).
+pub const THRESHOLD_DB: f32 = 10.0;
+/// Default percentile of the PSD bins taken as the noise floor.
+pub const NOISE_PERCENTILE: f32 = 0.25;
+/// Default weight of the newest noise floor estimate.
+pub const NOISE_ALPHA: f32 = 0.1;
+
+/// How the noise around the cell under test is estimated from its training cells.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CfarMethod {
+    /// Mean of the training cells (CA-CFAR). Best in uniform noise.
+    CellAveraging,
+    /// `os_rank` quantile of the training cells (OS-CFAR). Not thrown off by a strong neighbour.
+    OrderedStatistic,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CfarConfig {
+    pub method: CfarMethod,
+    pub guard_cells: usize,
+    pub training_cells: usize,
+    pub threshold_db: f32,
+    /// Quantile of the training cells used by OS-CFAR, from 0 to 1.
+    pub os_rank: f32,
+    /// Percentile of the PSD bins taken as the noise floor, from 0 to 1.
+    pub noise_percentile: f32,
+    /// Weight of the newest noise floor estimate, from above 0 up to 1.
+    pub noise_alpha: f32,
+}
+
+impl Default for CfarConfig {
+    fn default() -> Self {
+        Self {
+            method: CfarMethod::CellAveraging,
+            guard_cells: GUARD_CELLS,
+            training_cells: TRAINING_CELLS,
+            threshold_db: THRESHOLD_DB,
+            os_rank: 0.75,
+            noise_percentile: NOISE_PERCENTILE,
+            noise_alpha: NOISE_ALPHA,
+        }
+    }
+}
+
+impl CfarConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.training_cells == 0 {
+            return Err("cfar.training_cells must be greater than 0".into());
+        }
+        if !self.threshold_db.is_finite() {
+            return Err(format!("cfar.threshold_db must be a number, got {}", self.threshold_db));
+        }
+        if !(0.0..=1.0).contains(&self.os_rank) {
+            return Err(format!("cfar.os_rank must be between 0 and 1, got {}", self.os_rank));
+        }
+        if !(0.0..=1.0).contains(&self.noise_percentile) {
+            return Err(format!("cfar.noise_percentile must be between 0 and 1, got {}", self.noise_percentile));
+        }
+        if !(self.noise_alpha > 0.0 && self.noise_alpha <= 1.0) {
+            return Err(format!("cfar.noise_alpha must be above 0 and at most 1, got {}", self.noise_alpha));
+        }
+        Ok(())
+    }
+}
+
+/// A contiguous run of PSD bins above the CFAR threshold.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Emission {
+    /// Power weighted center, as an offset from the center frequency (in Hz).
+    pub center_freq: f32,
+    pub bandwidth: f32,
+    /// Peak above the noise floor (in dB).
+    pub snr_db: f32,
+    /// Strongest bin (in dBFS/Hz).
+    pub peak_dbfs: f32,
+}
+
+/// Emissions found in one PSD, and the noise floor they were measured against.
+pub struct CfarResult {
+    pub emissions: Vec<Emission>,
+    pub noise_floor_dbfs: f32,
+}
+
+/// Finds emissions in successive PSDs, tracking the noise floor over time.
+pub struct CfarDetector {
+    config: CfarConfig,
+    /// Linear noise floor density, averaged over the PSDs seen so far.
+    noise_floor: Option<f32>,
+}
+
+impl CfarDetector {
+    pub fn new(config: &CfarConfig) -> Self {
+        Self {
+            config: config.clone(),
+            noise_floor: None,
+        }
+    }
+
+    pub fn detect(&mut self, psd: &Psd) -> CfarResult {
+        let noise_floor = self.update_noise_floor(&psd.density);
+        let threshold = 10f32.powf(self.config.threshold_db / 10.0);
+        let detected: Vec<bool> = (0..psd.density.len())
+            .map(|cell| psd.density[cell] > threshold * self.local_noise(&psd.density, cell))
+            .collect();
+
+        let mut emissions = Vec::new();
+        let mut cell = 0;
+        while cell < detected.len() {
+            if !detected[cell] {
+                cell += 1;
+                continue;
+            }
+            let start = cell;
+            while cell < detected.len() && detected[cell] {
+                cell += 1;
+            }
+            emissions.push(emission(psd, start..cell, noise_floor));
+        }
+
+        CfarResult { emissions, noise_floor_dbfs: density_to_db(noise_floor) }
+    }
+
+    /// Takes a percentile of the PSD bins as this window's noise floor and averages it over time,
+    /// so a burst covering much of the band doesn't lift it at once.
+    fn update_noise_floor(&mut self, density: &[f32]) -> f32 {
+        let estimate = quantile(density.to_vec(), self.config.noise_percentile);
+        let floor = match self.noise_floor {
+            Some(floor) => floor + self.config.noise_alpha * (estimate - floor),
+            None => estimate,
+        };
+        self.noise_floor = Some(floor);
+        floor
+    }
+
+    /// Noise around `cell` estimated from the training cells on both sides, skipping the guard
+    /// cells. Near the edges only the cells that exist are used.
+    fn local_noise(&self, density: &[f32], cell: usize) -> f32 {
+        let (guard, training) = (self.config.guard_cells, self.config.training_cells);
+        let before = cell.saturating_sub(guard + training)..cell.saturating_sub(guard);
+        let after = (cell + guard + 1).min(density.len())..(cell + guard + training + 1).min(density.len());
+        let cells: Vec<f32> = density[before].iter().chain(&density[after]).copied().collect();
+        if cells.is_empty() {
+            return f32::INFINITY;
+        }
+
+        match self.config.method {
+            CfarMethod::CellAveraging => cells.iter().sum::<f32>() / cells.len() as f32,
+            CfarMethod::OrderedStatistic => quantile(cells, self.config.os_rank),
+        }
+    }
+}
+
+fn emission(psd: &Psd, cells: std::ops::Range<usize>, noise_floor: f32) -> Emission {
+    let density = &psd.density[cells.clone()];
+    let frequencies = &psd.frequencies[cells.clone()];
+    let total: f32 = density.iter().sum();
+    let peak = density.iter().copied().fold(0.0, f32::max);
+    let center_freq = if total > 0.0 {
+        frequencies.iter().zip(density).map(|(freq, density)| freq * density).sum::<f32>() / total
+    } else {
+        frequencies[frequencies.len() / 2]
+    };
+
+    Emission {
+        center_freq,
+        bandwidth: cells.len() as f32 * psd.resolution,
+        snr_db: density_to_db(peak) - density_to_db(noise_floor),
+        peak_dbfs: density_to_db(peak),
+    }
+}
+
+fn quantile(mut values: Vec<f32>, q: f32) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    values[((values.len() - 1) as f32 * q).round() as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::psd::tests::{tone, white_noise, SAMPLE_RATE};
+    use crate::psd::{Averaging, PsdEstimator, WindowFunction, FFT_SIZE, OVERLAP};
+
+    use super::*;
+
+    /// PSD with bins 1 Hz apart.
+    fn psd(density: Vec<f32>) -> Psd {
+        Psd {
+            resolution: 1.0,
+            frequencies: (0..density.len()).map(|i| i as f32).collect(),
+            density,
+            consumed: 0,
+        }
+    }
+
+    fn config(method: CfarMethod) -> CfarConfig {
+        CfarConfig { method, ..CfarConfig::default() }
+    }
+
+    #[test]
+    fn cell_averaging_threshold() {
+        let mut density = vec![1.0; 100];
+        density[30] = 10.5;
+        density[70] = 9.5;
+        let result = CfarDetector::new(&config(CfarMethod::CellAveraging)).detect(&psd(density));
+        // Only the cell more than 10 dB above its neighbours
+        assert_eq!(result.emissions.len(), 1);
+        assert_eq!(result.emissions[0].center_freq, 30.0);
+        assert_eq!(result.emissions[0].bandwidth, 1.0);
+        assert!((result.emissions[0].snr_db - density_to_db(10.5)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ordered_statistic_ignores_a_strong_neighbour() {
+        let mut density = vec![1.0; 100];
+        density[40] = 15.0;
+        density[50] = 1000.0;
+        // The neighbour lifts the training cells' mean about 15 dB
+        let result = CfarDetector::new(&config(CfarMethod::CellAveraging)).detect(&psd(density.clone()));
+        let centers: Vec<f32> = result.emissions.iter().map(|emission| emission.center_freq).collect();
+        assert_eq!(centers, [50.0]);
+
+        let result = CfarDetector::new(&config(CfarMethod::OrderedStatistic)).detect(&psd(density));
+        let centers: Vec<f32> = result.emissions.iter().map(|emission| emission.center_freq).collect();
+        assert_eq!(centers, [40.0, 50.0]);
+    }
+
+    #[test]
+    fn adjacent_cells_form_one_emission() {
+        let mut density = vec![1.0; 100];
+        density[20..25].copy_from_slice(&[100.0, 100.0, 300.0, 100.0, 100.0]);
+        let result = CfarDetector::new(&config(CfarMethod::CellAveraging)).detect(&psd(density));
+        assert_eq!(result.emissions.len(), 1);
+        let emission = &result.emissions[0];
+        assert_eq!(emission.bandwidth, 5.0);
+        assert!((emission.center_freq - 22.0).abs() < 1e-4);
+        assert!((emission.peak_dbfs - density_to_db(300.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn percentile_noise_floor_is_averaged_over_time() {
+        let mut detector = CfarDetector::new(&CfarConfig::default());
+        // The 25th percentile of 1..=100
+        let density: Vec<f32> = (1..=100).rev().map(|i| i as f32).collect();
+        let result = detector.detect(&psd(density.clone()));
+        assert!((result.noise_floor_dbfs - density_to_db(26.0)).abs() < 1e-4);
+
+        // A band four times louder only moves it by `noise_alpha`
+        let result = detector.detect(&psd(density.iter().map(|d| 4.0 * d).collect()));
+        assert!((result.noise_floor_dbfs - density_to_db(26.0 + 0.1 * (104.0 - 26.0))).abs() < 1e-4);
+    }
+
+    #[test]
+    fn detects_a_tone_in_noise_at_its_snr() {
+        let (noise_power, tone_power, tone_freq) = (1e-4, 1e-3, 5126.953);
+        let len = 64 * FFT_SIZE;
+        let samples: Vec<_> = white_noise(len, noise_power, 7).iter()
+            .zip(tone(len, tone_power, tone_freq))
+            .map(|(noise, tone)| noise + tone)
+            .collect();
+        let psd = PsdEstimator::new(FFT_SIZE, OVERLAP, WindowFunction::Hann, Averaging::Welch, SAMPLE_RATE)
+            .estimate(&samples)
+            .unwrap();
+
+        // The tone sits on a bin, where the Hann window spreads it over 1.5 bins
+        let noise_density = noise_power / SAMPLE_RATE as f32;
+        let tone_density = tone_power / (1.5 * psd.resolution);
+        let peak = psd.density.iter().copied().fold(0.0, f32::max);
+        assert!((density_to_db(peak) - density_to_db(tone_density + noise_density)).abs() < 0.5);
+
+        let result = CfarDetector::new(&CfarConfig::default()).detect(&psd);
+        assert!((result.noise_floor_dbfs - density_to_db(noise_density)).abs() < 1.0, "{}", result.noise_floor_dbfs);
+        assert_eq!(result.emissions.len(), 1, "{:?}", result.emissions);
+        let emission = &result.emissions[0];
+        assert!((emission.center_freq - tone_freq).abs() < psd.resolution);
+        let snr_db = density_to_db(tone_density) - density_to_db(noise_density);
+        assert!((emission.snr_db - snr_db).abs() < 1.0, "{} vs {}", emission.snr_db, snr_db);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use spectrum_analyzer::FrequencySpectrum;
+
+use crate::burst::BurstFeatures;
+use crate::hopping::HopEstimate;
+use crate::library::ReferenceLibrary;
+use crate::similarity::SimilarityMeasure;
+use crate::utils::classify_uav;
+
+/// A scored guess of which UAV type is in the spectrum.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Hypothesis {
+    /// Name of the classifier that produced it.
+    pub classifier: String,
+    pub uav_type: String,
+    pub score: f32,
+}
+
+/// What the classifiers get to see of one detection window.
+pub struct Observation<'a> {
+    pub spectrum: &'a FrequencySpectrum,
+    /// Time-domain features of the recent bursts, if there were any.
+    pub bursts: Option<&'a BurstFeatures>,
+    /// Hop pattern of the recent bursts, if they hop.
+    pub hopping: Option<&'a HopEstimate>,
+}
+
+/// Detection algorithm. Implementations score an observation against whatever they know about
+/// UAVs and return their hypotheses sorted best first.
+pub trait Classifier: Send {
+    fn name(&self) -> &str;
+
+    fn classify(&self, observation: &Observation, library: &ReferenceLibrary) -> Vec<Hypothesis>;
+
+    /// Whether the library has any signature this classifier can score against. One that has none
+    /// leaves the reported result to the next classifier.
+    fn has_signatures(&self, _library: &ReferenceLibrary) -> bool {
+        true
+    }
+}
+
+/// Default target frequencies of the band classifier. (These are the ones from the drill, where
+/// 71 kHz was mistyped as `71_0000.0`, i.e. 710 kHz)
+pub const DRONE_FREQS: &[f32] = &[
+    10_000.0, 20_000.0, 30_000.0, 40_000.0, 51_000.0, 61_000.0, 71_000.0, 81_000.0,
+    92_000.0, 102_000.0, 112_000.0, 122_000.0, 132_000.0
+];
+/// Default width (standard deviation, in Hz) of the band around each target frequency.
+pub const BANDWIDTH: f32 = 1_000.0;
+
+/// Which classifier to build, see `build_classifier`. In the config file:
+///
+/// ```toml
+/// [[processing.classifiers]]
+/// type = "similarity"
+/// measure = "log_cosine"
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClassifierKind {
+    /// Compares the spectrum with every reference spectrum of the library.
+    Similarity {
+        #[serde(default = "default_measure")]
+        measure: SimilarityMeasure,
+    },
+    /// Measures how much of the power sits around known drone frequencies.
+    Band {
+        #[serde(default = "default_target_freqs")]
+        target_freqs: Vec<f32>,
+        /// Standard deviation of the Gaussian weight around each target frequency (in Hz).
+        #[serde(default = "default_bandwidth")]
+        bandwidth: f32,
+    },
+    /// Compares the burst features with the burst patterns of the library.
+    Burst,
+    /// Compares the hop pattern with the hop patterns of the library.
+    Hopping,
+    /// Weighted average of several classifiers.
+    Ensemble { members: Vec<EnsembleMember> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EnsembleMember {
+    pub classifier: ClassifierKind,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_measure() -> SimilarityMeasure {
+    SimilarityMeasure::Cosine
+}
+
+fn default_target_freqs() -> Vec<f32> {
+    DRONE_FREQS.to_vec()
+}
+
+fn default_bandwidth() -> f32 {
+    BANDWIDTH
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// The similarity classifier decides if the library has reference recordings, else the hopping
+/// classifier if it has hop patterns, else the burst classifier. The band classifier runs alongside
+/// for comparison.
+pub fn default_classifiers() -> Vec<ClassifierKind> {
+    vec![
+        ClassifierKind::Similarity { measure: default_measure() },
+        ClassifierKind::Hopping,
+        ClassifierKind::Burst,
+        ClassifierKind::Band { target_freqs: default_target_freqs(), bandwidth: default_bandwidth() },
+    ]
+}
+
+impl ClassifierKind {
+    /// Sets the bandwidth of this classifier, and of its members if it's an ensemble.
+    pub fn set_bandwidth(&mut self, new_bandwidth: f32) {
+        match self {
+            ClassifierKind::Band { bandwidth, .. } => *bandwidth = new_bandwidth,
+            ClassifierKind::Ensemble { members } => {
+                for member in members {
+                    member.classifier.set_bandwidth(new_bandwidth);
+                }
+            }
+            ClassifierKind::Similarity { .. } | ClassifierKind::Burst | ClassifierKind::Hopping => (),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ClassifierKind::Similarity { .. } | ClassifierKind::Burst | ClassifierKind::Hopping => Ok(()),
+            ClassifierKind::Band { target_freqs, bandwidth } => {
+                if target_freqs.is_empty() {
+                    Err("band classifier needs at least one target frequency".into())
+                } else if !bandwidth.is_finite() || *bandwidth <= 0.0 {
+                    Err(format!("band classifier bandwidth must be greater than 0 Hz, got {}", bandwidth))
+                } else {
+                    Ok(())
+                }
+            }
+            ClassifierKind::Ensemble { members } => {
+                if members.is_empty() {
+                    return Err("ensemble needs at least one member".into());
+                }
+                for member in members {
+                    if !member.weight.is_finite() || member.weight < 0.0 {
+                        return Err(format!("ensemble weights must be finite and not negative, got {}", member.weight));
+                    }
+                    member.classifier.validate()?;
+                }
+                if members.iter().all(|member| member.weight == 0.0) {
+                    return Err("ensemble needs at least one member with a weight above 0".into());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn build_classifier(kind: &ClassifierKind) -> Box<dyn Classifier> {
+    match kind {
+        ClassifierKind::Similarity { measure } => Box::new(SimilarityClassifier { measure: *measure }),
+        ClassifierKind::Band { target_freqs, bandwidth } => Box::new(BandClassifier {
+            target_freqs: target_freqs.clone(),
+            bandwidth: *bandwidth,
+        }),
+        ClassifierKind::Burst => Box::new(BurstClassifier),
+        ClassifierKind::Hopping => Box::new(HoppingClassifier),
+        ClassifierKind::Ensemble { members } => Box::new(EnsembleClassifier {
+            members: members.iter()
+                .map(|member| (build_classifier(&member.classifier), member.weight))
+                .collect(),
+        }),
+    }
+}
+
+/// Matches the spectrum against the reference spectra using `classify_uav`.
+pub struct SimilarityClassifier {
+    pub measure: SimilarityMeasure,
+}
+
+impl Classifier for SimilarityClassifier {
+    fn name(&self) -> &str {
+        "similarity"
+    }
+
+    fn classify(&self, observation: &Observation, library: &ReferenceLibrary) -> Vec<Hypothesis> {
+        classify_uav(observation.spectrum, library.spectra(), self.measure)
+            .into_iter()
+            .map(|(uav_type, score)| Hypothesis { classifier: self.name().into(), uav_type, score })
+            .collect()
+    }
+
+    fn has_signatures(&self, library: &ReferenceLibrary) -> bool {
+        !library.spectra().is_empty()
+    }
+}
+
+/// Label of the hypothesis produced by `BandClassifier`, which can't tell UAV types apart.
+pub const BAND_LABEL: &str = "Drone";
+
+/// Fraction of the spectrum's power close to the target frequencies, each bin being weighted by a
+/// Gaussian around the nearest target.
+pub struct BandClassifier {
+    pub target_freqs: Vec<f32>,
+    pub bandwidth: f32,
+}
+
+impl Classifier for BandClassifier {
+    fn name(&self) -> &str {
+        "band"
+    }
+
+    fn classify(&self, observation: &Observation, _: &ReferenceLibrary) -> Vec<Hypothesis> {
+        let mut score = 0f32;
+        let mut total_power = 0f32;
+
+        for &(freq, power) in observation.spectrum.data() {
+            let mut max_weight = 0f32;
+
+            for &target_freq in &self.target_freqs {
+                let distance = (freq.val() - target_freq).abs();
+                let weight = (-distance.powi(2) / (2.0 * self.bandwidth.powi(2))).exp(); // Gaussian decay
+                // We only care about the freq with the max weight to ensure the most relevant frequency dominates
+                max_weight = max_weight.max(weight);
+            }
+
+            score += power.val() * max_weight;
+            total_power += power.val();
+        }
+
+        vec![Hypothesis {
+            classifier: self.name().into(),
+            uav_type: BAND_LABEL.into(),
+            score: if total_power > 0.0 { score / total_power } else { 0.0 },
+        }]
+    }
+}
+
+/// Scores the burst features against the burst pattern of every signature that has one.
+pub struct BurstClassifier;
+
+impl Classifier for BurstClassifier {
+    fn name(&self) -> &str {
+        "burst"
+    }
+
+    fn classify(&self, observation: &Observation, library: &ReferenceLibrary) -> Vec<Hypothesis> {
+        let Some(features) = observation.bursts else {
+            return Vec::new();
+        };
+        let mut hypotheses: Vec<Hypothesis> = library.burst_patterns().iter()
+            .map(|(uav_type, pattern)| Hypothesis {
+                classifier: self.name().into(),
+                uav_type: uav_type.clone(),
+                score: pattern.score(features),
+            })
+            .collect();
+        hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hypotheses
+    }
+
+    fn has_signatures(&self, library: &ReferenceLibrary) -> bool {
+        !library.burst_patterns().is_empty()
+    }
+}
+
+/// Scores the hop pattern against the hop pattern of every signature that has one.
+pub struct HoppingClassifier;
+
+impl Classifier for HoppingClassifier {
+    fn name(&self) -> &str {
+        "hopping"
+    }
+
+    fn classify(&self, observation: &Observation, library: &ReferenceLibrary) -> Vec<Hypothesis> {
+        let Some(estimate) = observation.hopping else {
+            return Vec::new();
+        };
+        let mut hypotheses: Vec<Hypothesis> = library.hop_patterns().iter()
+            .map(|(uav_type, pattern)| Hypothesis {
+                classifier: self.name().into(),
+                uav_type: uav_type.clone(),
+                score: pattern.score(estimate),
+            })
+            .collect();
+        hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hypotheses
+    }
+
+    fn has_signatures(&self, library: &ReferenceLibrary) -> bool {
+        !library.hop_patterns().is_empty()
+    }
+}
+
+/// Combines classifiers by averaging their scores per UAV type, weighted by the member's weight.
+/// A member that doesn't report a UAV type counts as a score of 0 for it.
+pub struct EnsembleClassifier {
+    pub members: Vec<(Box<dyn Classifier>, f32)>,
+}
+
+impl Classifier for EnsembleClassifier {
+    fn name(&self) -> &str {
+        "ensemble"
+    }
+
+    fn classify(&self, observation: &Observation, library: &ReferenceLibrary) -> Vec<Hypothesis> {
+        let total_weight: f32 = self.members.iter().map(|(_, weight)| weight).sum();
+        if total_weight <= 0.0 {
+            return Vec::new();
+        }
+
+        let mut scores: HashMap<String, f32> = HashMap::new();
+        for (member, weight) in &self.members {
+            for hypothesis in member.classify(observation, library) {
+                *scores.entry(hypothesis.uav_type).or_default() += hypothesis.score * weight / total_weight;
+            }
+        }
+
+        let mut hypotheses: Vec<Hypothesis> = scores.into_iter()
+            .map(|(uav_type, score)| Hypothesis { classifier: self.name().into(), uav_type, score })
+            .collect();
+        hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hypotheses
+    }
+
+    fn has_signatures(&self, library: &ReferenceLibrary) -> bool {
+        self.members.iter().any(|(member, _)| member.has_signatures(library))
+    }
+}
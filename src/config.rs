@@ -0,0 +1,426 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use clap::{ArgGroup, Parser};
+use serde::{Serialize, Deserialize};
+use wire::formats::SampleFormat;
+
+use crate::alarm::AlarmConfig;
+use crate::burst::BurstConfig;
+use crate::cfar::CfarConfig;
+use crate::classifier::{default_classifiers, ClassifierKind};
+use crate::hopping::HoppingConfig;
+use crate::history::HISTORY_CAPACITY;
+use crate::recording::RecordingConfig;
+use crate::replay::ReplaySpeed;
+use crate::pipelines::{MAX_SENSORS, SENSOR_IDLE_TIMEOUT_MS};
+use crate::psd::{Averaging, WindowFunction, FFT_SIZE, OVERLAP};
+use crate::processing::{
+    GapPolicy, DETECTION_INTERVAL_MS, LIBRARY_POLL_INTERVAL_MS, MAX_BANDS, SAMPLE_RATE, SENSOR_ID, UAV_DATA_PATH,
+    WINDOW_INTERVALS,
+};
+use crate::udp::{BUFFER_SIZE, PORT};
+use crate::utils::content_version;
+
+/// Command line of the server. Every option overrides the matching setting of the config file.
+#[derive(Parser, Debug)]
+#[command(version, about = "Drone detection server")]
+#[command(group(ArgGroup::new("recordings").args(["replay", "evaluate"])))]
+pub struct Cli {
+    /// TOML configuration file. Settings missing from it keep their default value.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration as TOML and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    /// Address of the HTTP/WebSocket server, e.g. 0.0.0.0:4001.
+    #[arg(long)]
+    pub http_bind: Option<String>,
+    /// Address the UDP listener binds to.
+    #[arg(long)]
+    pub udp_address: Option<String>,
+    /// UDP port samples are received on.
+    #[arg(long)]
+    pub udp_port: Option<u16>,
+    /// Size of the UDP receive buffer, i.e. the largest datagram accepted (in bytes).
+    #[arg(long)]
+    pub buffer_size: Option<usize>,
+    /// Format of datagrams without a packet header: cf32, cs16, cs8 or cu8.
+    #[arg(long)]
+    pub sample_format: Option<SampleFormat>,
+    /// Most samples buffered between two detections. Defaults to two detection intervals' worth.
+    #[arg(long)]
+    pub window_size: Option<usize>,
+    /// Number of samples per FFT segment.
+    #[arg(long)]
+    pub fft_size: Option<usize>,
+    /// Fraction of each FFT segment shared with the next one, from 0 up to (not including) 1.
+    #[arg(long)]
+    pub overlap: Option<f32>,
+    /// Window applied to each FFT segment: rectangular, hann, blackman_harris or flat_top.
+    #[arg(long, value_parser = parse_window_function)]
+    pub window_function: Option<WindowFunction>,
+    /// How often to run a detection (in ms).
+    #[arg(long)]
+    pub detection_interval_ms: Option<u64>,
+    /// Sample rate of the incoming I/Q stream (in samples per second).
+    #[arg(long)]
+    pub sample_rate: Option<u32>,
+    /// Bandwidth (in Hz) of every band classifier.
+    #[arg(long)]
+    pub bandwidth: Option<f32>,
+    /// UAV signature file.
+    #[arg(long)]
+    pub uav_data_path: Option<PathBuf>,
+    /// Run the detections on these recordings (SigMF, WAV or raw I/Q) instead of the UDP feed.
+    #[arg(long, num_args = 1..)]
+    pub replay: Vec<PathBuf>,
+    /// Run the detections on the labelled recordings in this directory, see `evaluation::LABELS_FILE`,
+    /// and write how well they match the labels to the report.
+    #[arg(long)]
+    pub evaluate: Option<PathBuf>,
+    /// Score a detection needs to count as a prediction in the evaluation. Defaults to the alarm
+    /// raise threshold.
+    #[arg(long, requires = "evaluate")]
+    pub threshold: Option<f32>,
+    /// Format of raw recordings whose extension doesn't name one. Defaults to the UDP sample format.
+    #[arg(long, requires = "recordings")]
+    pub replay_format: Option<SampleFormat>,
+    /// How fast recordings are replayed.
+    #[arg(long, value_enum, default_value_t, requires = "replay")]
+    pub replay_speed: ReplaySpeed,
+    /// Write the detections of the replay to this JSON file and exit, instead of serving them. An
+    /// evaluation writes its report here (evaluation.json by default), along with a Markdown
+    /// version.
+    #[arg(long, requires = "recordings")]
+    pub report: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: String,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self { bind: "127.0.0.1:4001".into() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdpConfig {
+    pub address: String,
+    pub port: u16,
+    pub buffer_size: usize,
+    /// Format of datagrams sent without a packet header.
+    pub sample_format: SampleFormat,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".into(),
+            port: PORT,
+            buffer_size: BUFFER_SIZE,
+            sample_format: SampleFormat::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessingConfig {
+    /// Most samples buffered between two detections. Older ones are dropped, and counted in the
+    /// pipeline's `dropped_samples`. Defaults to `WINDOW_INTERVALS` detection intervals' worth of
+    /// samples at `sample_rate`, see `window_capacity`.
+    pub window_size: Option<usize>,
+    pub fft_size: usize,
+    pub overlap: f32,
+    pub window_function: WindowFunction,
+    pub averaging: Averaging,
+    pub detection_interval_ms: u64,
+    pub sample_rate: u32,
+    pub gap_policy: GapPolicy,
+    /// Identifies replayed recordings in stored detections. Live senders are identified by the
+    /// sensor id in their packet header, or else by their address (IP and port).
+    pub sensor_id: String,
+    /// The first classifier with signatures in the library decides the reported result, the others
+    /// run alongside it.
+    pub classifiers: Vec<ClassifierKind>,
+    /// Most center frequencies analysed side by side, e.g. the bands of a sweeping sender.
+    pub max_bands: usize,
+    /// Most sensors processed at once, each in its own pipeline.
+    pub max_sensors: usize,
+    /// How long a sensor can go without sending samples before its pipeline is stopped and no
+    /// longer counts towards `max_sensors` (in ms). 0 keeps pipelines running.
+    pub sensor_idle_timeout_ms: u64,
+}
+
+impl ProcessingConfig {
+    /// Size of each band's window: `window_size` if set, otherwise enough for what arrives in
+    /// `WINDOW_INTERVALS` detection intervals, and never less than one FFT segment.
+    pub fn window_capacity(&self) -> usize {
+        let default = || {
+            let per_interval = self.sample_rate as u64 * self.detection_interval_ms / 1000;
+            (per_interval * WINDOW_INTERVALS) as usize
+        };
+        self.window_size.unwrap_or_else(default).max(self.fft_size)
+    }
+}
+
+impl Default for ProcessingConfig {
+    fn default() -> Self {
+        Self {
+            window_size: None,
+            fft_size: FFT_SIZE,
+            overlap: OVERLAP,
+            window_function: WindowFunction::Hann,
+            averaging: Averaging::Welch,
+            detection_interval_ms: DETECTION_INTERVAL_MS,
+            sample_rate: SAMPLE_RATE,
+            gap_policy: GapPolicy::ZeroFill,
+            sensor_id: SENSOR_ID.into(),
+            classifiers: default_classifiers(),
+            max_bands: MAX_BANDS,
+            max_sensors: MAX_SENSORS,
+            sensor_idle_timeout_ms: SENSOR_IDLE_TIMEOUT_MS,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    pub path: PathBuf,
+    /// How often to check `path` for changes (in ms).
+    pub poll_interval_ms: u64,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            path: UAV_DATA_PATH.into(),
+            poll_interval_ms: LIBRARY_POLL_INTERVAL_MS,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Number of recent detections served by `GET /api/detections`.
+    pub capacity: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { capacity: HISTORY_CAPACITY }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    /// Keep a durable log of the detections in an SQLite database.
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Also store the detections that found no UAV ("Unknown"). That's a row per band every
+    /// detection interval, around 172k a day for one band at the default interval. Without it the
+    /// history API only returns the Unknown detections still in the in-memory history.
+    pub keep_unknown: bool,
+    /// Delete detections older than this. No limit if unset.
+    pub max_age_hours: Option<u64>,
+    /// Delete the oldest detections beyond this count. No limit if unset.
+    pub max_records: Option<u64>,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "detections.db".into(),
+            keep_unknown: false,
+            max_age_hours: Some(30 * 24),
+            max_records: Some(1_000_000),
+        }
+    }
+}
+
+/// Effective server configuration. Every field has a default, so the file only needs the
+/// settings that differ from it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub http: HttpConfig,
+    pub udp: UdpConfig,
+    pub processing: ProcessingConfig,
+    pub library: LibraryConfig,
+    pub history: HistoryConfig,
+    pub store: StoreConfig,
+    pub alarm: AlarmConfig,
+    pub cfar: CfarConfig,
+    pub hopping: HoppingConfig,
+    pub bursts: BurstConfig,
+    pub recording: RecordingConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            ConfigError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the file given on the command line (if any) and the command
+    /// line overrides, and checks it.
+    pub fn from_cli(cli: &Cli) -> Result<Self, ConfigError> {
+        let mut config = match &cli.config {
+            Some(path) => Self::load(path)?,
+            None => Self::default(),
+        };
+        config.apply_overrides(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Hash of the effective configuration, recorded with every detection.
+    pub fn version(&self) -> String {
+        content_version(&toml::to_string(self).expect("configuration should serialise to TOML"))
+    }
+
+    fn apply_overrides(&mut self, cli: &Cli) {
+        if let Some(bind) = &cli.http_bind {
+            self.http.bind = bind.clone();
+        }
+        if let Some(address) = &cli.udp_address {
+            self.udp.address = address.clone();
+        }
+        if let Some(port) = cli.udp_port {
+            self.udp.port = port;
+        }
+        if let Some(buffer_size) = cli.buffer_size {
+            self.udp.buffer_size = buffer_size;
+        }
+        if let Some(sample_format) = cli.sample_format {
+            self.udp.sample_format = sample_format;
+        }
+        if let Some(window_size) = cli.window_size {
+            self.processing.window_size = Some(window_size);
+        }
+        if let Some(fft_size) = cli.fft_size {
+            self.processing.fft_size = fft_size;
+        }
+        if let Some(overlap) = cli.overlap {
+            self.processing.overlap = overlap;
+        }
+        if let Some(window_function) = cli.window_function {
+            self.processing.window_function = window_function;
+        }
+        if let Some(interval) = cli.detection_interval_ms {
+            self.processing.detection_interval_ms = interval;
+        }
+        if let Some(sample_rate) = cli.sample_rate {
+            self.processing.sample_rate = sample_rate;
+        }
+        if let Some(bandwidth) = cli.bandwidth {
+            for classifier in &mut self.processing.classifiers {
+                classifier.set_bandwidth(bandwidth);
+            }
+        }
+        if let Some(path) = &cli.uav_data_path {
+            self.library.path = path.clone();
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+
+        if self.http.bind.parse::<SocketAddr>().is_err() {
+            return invalid(format!("http.bind '{}' is not an address like 127.0.0.1:4001", self.http.bind));
+        }
+        if self.udp.address.parse::<IpAddr>().is_err() {
+            return invalid(format!("udp.address '{}' is not an IP address", self.udp.address));
+        }
+        if !(64..=65536).contains(&self.udp.buffer_size) {
+            return invalid(format!("udp.buffer_size must be between 64 and 65536 bytes, got {}", self.udp.buffer_size));
+        }
+        if self.processing.fft_size < 16 {
+            return invalid(format!("processing.fft_size must be at least 16 samples, got {}", self.processing.fft_size));
+        }
+        if let Some(window_size) = self.processing.window_size.filter(|size| *size < self.processing.fft_size) {
+            return invalid(format!(
+                "processing.window_size ({}) must be at least processing.fft_size ({})",
+                window_size, self.processing.fft_size
+            ));
+        }
+        if !(0.0..1.0).contains(&self.processing.overlap) {
+            return invalid(format!("processing.overlap must be at least 0 and below 1, got {}", self.processing.overlap));
+        }
+        if let Averaging::Exponential { alpha } = self.processing.averaging {
+            if !(alpha > 0.0 && alpha <= 1.0) {
+                return invalid(format!("processing.averaging alpha must be above 0 and at most 1, got {}", alpha));
+            }
+        }
+        if self.processing.detection_interval_ms == 0 {
+            return invalid("processing.detection_interval_ms must be greater than 0".into());
+        }
+        if self.processing.sample_rate == 0 {
+            return invalid("processing.sample_rate must be greater than 0".into());
+        }
+        if self.processing.max_bands == 0 {
+            return invalid("processing.max_bands must be greater than 0".into());
+        }
+        if self.processing.max_sensors == 0 {
+            return invalid("processing.max_sensors must be greater than 0".into());
+        }
+        if self.processing.classifiers.is_empty() {
+            return invalid("processing.classifiers needs at least one classifier".into());
+        }
+        for classifier in &self.processing.classifiers {
+            classifier.validate().map_err(|reason| ConfigError::Invalid(format!("processing.classifiers: {}", reason)))?;
+        }
+        if self.library.poll_interval_ms == 0 {
+            return invalid("library.poll_interval_ms must be greater than 0".into());
+        }
+        if self.history.capacity == 0 {
+            return invalid("history.capacity must be greater than 0".into());
+        }
+        if self.processing.sensor_id.trim().is_empty() {
+            return invalid("processing.sensor_id can't be empty".into());
+        }
+        if self.store.max_records == Some(0) {
+            return invalid("store.max_records must be greater than 0, or unset for no limit".into());
+        }
+        self.alarm.validate().map_err(ConfigError::Invalid)?;
+        self.cfar.validate().map_err(ConfigError::Invalid)?;
+        self.hopping.validate().map_err(ConfigError::Invalid)?;
+        self.bursts.validate().map_err(ConfigError::Invalid)?;
+        self.recording.validate().map_err(ConfigError::Invalid)?;
+        Ok(())
+    }
+}
+
+fn parse_window_function(value: &str) -> Result<WindowFunction, String> {
+    WindowFunction::deserialize(serde::de::value::StrDeserializer::<serde::de::value::Error>::new(value))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,501 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Serialize, Deserialize};
+use wire::formats::SampleFormat;
+
+use crate::replay::{open_recordings, Recording, RecordingReport, ReplayError, ReplayReport};
+
+/// Manifest of a labelled dataset, in the dataset directory. Maps file names to the UAV type
+/// heard throughout the file, `null` for no UAV, or a list of segments.
+pub const LABELS_FILE: &str = "labels.json";
+/// Label of the time no UAV is transmitting, and prediction of `Unknown` detections.
+pub const NO_UAV: &str = "none";
+
+/// Time a UAV type is heard in a recording. Open ended segments last until the end of the file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Segment {
+    pub uav_type: String,
+    #[serde(default)]
+    pub start_s: f64,
+    pub end_s: Option<f64>,
+}
+
+impl Segment {
+    fn contains(&self, offset_s: f64) -> bool {
+        offset_s >= self.start_s && self.end_s.is_none_or(|end_s| offset_s <= end_s)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Label {
+    File(String),
+    Segments(Vec<Segment>),
+}
+
+/// Recordings of a dataset directory and what they contain, in the same order.
+pub struct Dataset {
+    pub recordings: Vec<Recording>,
+    pub labels: Vec<Vec<Segment>>,
+}
+
+impl Dataset {
+    /// Opens every recording in `directory`. Labels come from `LABELS_FILE`, falling back on the
+    /// annotations of SigMF recordings. Recordings without either are skipped.
+    pub fn load(directory: &Path, raw_format: SampleFormat, raw_sample_rate: u32) -> Result<Self, ReplayError> {
+        let manifest_path = directory.join(LABELS_FILE);
+        let manifest: HashMap<String, Option<Label>> = match std::fs::read_to_string(&manifest_path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| ReplayError::Metadata(manifest_path.clone(), e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(ReplayError::Open(manifest_path, e)),
+        };
+
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(directory)
+            .map_err(|e| ReplayError::Open(directory.to_path_buf(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_recording(path))
+            .collect();
+        entries.sort();
+
+        let mut paths = Vec::new();
+        let mut labels = Vec::new();
+        for path in entries {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            let segments = match manifest.get(name) {
+                Some(Some(Label::File(uav_type))) => vec![Segment { uav_type: uav_type.clone(), start_s: 0.0, end_s: None }],
+                Some(Some(Label::Segments(segments))) => segments.clone(),
+                Some(None) => Vec::new(),
+                None => match sigmf_segments(&path)? {
+                    Some(segments) => segments,
+                    None => {
+                        warn!("Skipping {}, it isn't labelled in {}", path.display(), LABELS_FILE);
+                        continue;
+                    }
+                },
+            };
+            paths.push(path);
+            labels.push(segments);
+        }
+
+        Ok(Self { recordings: open_recordings(&paths, raw_format, raw_sample_rate)?, labels })
+    }
+}
+
+/// Files the replay can open. SigMF recordings are only listed by their metadata file.
+fn is_recording(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "sigmf-meta" | "wav" | "iq" | "raw" | "bin" => true,
+        other => other.parse::<SampleFormat>().is_ok(),
+    }
+}
+
+/// Segments of the annotations of a SigMF recording, or `None` if it isn't one or has none.
+fn sigmf_segments(path: &Path) -> Result<Option<Vec<Segment>>, ReplayError> {
+    if path.extension().is_none_or(|extension| extension != "sigmf-meta") {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path).map_err(|e| ReplayError::Open(path.to_path_buf(), e))?;
+    let meta: serde_json::Value = serde_json::from_str(&content)
+        .map_err(|e| ReplayError::Metadata(path.to_path_buf(), e.to_string()))?;
+    let sample_rate = meta["global"]["core:sample_rate"].as_f64().unwrap_or(0.0);
+    let Some(annotations) = meta["annotations"].as_array().filter(|annotations| !annotations.is_empty()) else {
+        return Ok(None);
+    };
+    if sample_rate <= 0.0 {
+        return Err(ReplayError::Metadata(path.to_path_buf(), "missing or invalid core:sample_rate".into()));
+    }
+
+    let segments = annotations.iter()
+        .filter_map(|annotation| {
+            let uav_type = annotation["core:label"].as_str()?;
+            let start = annotation["core:sample_start"].as_f64()?;
+            let count = annotation["core:sample_count"].as_f64();
+            Some(Segment {
+                uav_type: uav_type.into(),
+                start_s: start / sample_rate,
+                end_s: count.map(|count| (start + count) / sample_rate),
+            })
+        })
+        .collect();
+    Ok(Some(segments))
+}
+
+/// One detection, compared with the label at its time.
+struct Decision {
+    truth: String,
+    /// `NO_UAV` if the detection was `Unknown`.
+    predicted: String,
+    score: f32,
+}
+
+/// How well the detections match the labels of a dataset.
+#[derive(Serialize, Debug)]
+pub struct Evaluation {
+    /// Score a detection needs to count as predicting its UAV type.
+    pub threshold: f32,
+    pub recordings: usize,
+    pub detections: usize,
+    /// Number of detections by label, then by prediction.
+    pub confusion: BTreeMap<String, BTreeMap<String, usize>>,
+    pub uav_types: BTreeMap<String, TypeMetrics>,
+    /// Any UAV against no UAV.
+    pub presence: Roc,
+    /// Time from the start of every labelled segment until its UAV type was detected, by type.
+    pub latency: BTreeMap<String, Latency>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TypeMetrics {
+    /// Detections labelled with this type.
+    pub support: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    /// `None` when undefined, i.e. nothing was predicted or labelled as this type.
+    pub precision: Option<f32>,
+    pub recall: Option<f32>,
+    pub f1: Option<f32>,
+    /// This type against everything else.
+    pub roc: Roc,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Roc {
+    /// Area under the curve, `None` without both positive and negative detections.
+    pub auc: Option<f32>,
+    /// From the highest threshold to the lowest.
+    pub points: Vec<RocPoint>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RocPoint {
+    pub threshold: f32,
+    pub true_positive_rate: f32,
+    pub false_positive_rate: f32,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Latency {
+    pub segments: usize,
+    pub detected: usize,
+    pub mean_ms: Option<u64>,
+    pub median_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+}
+
+/// Compares the detections of a replay with the labels of its recordings, in the same order. Each
+/// detection is labelled with the segment covering the end of the interval it analysed.
+pub fn evaluate(report: &ReplayReport, labels: &[Vec<Segment>], threshold: f32) -> Evaluation {
+    let mut decisions = Vec::new();
+    let mut latencies: BTreeMap<String, (usize, Vec<u64>)> = BTreeMap::new();
+
+    for (recording, segments) in report.recordings.iter().zip(labels) {
+        let offsets: Vec<(f64, &str, f32)> = recording.detections.iter()
+            .map(|detection| (offset_s(recording, detection.timestamp()), detection.uav_type(), detection.score()))
+            .collect();
+
+        for &(offset, uav_type, score) in &offsets {
+            let truth = segments.iter()
+                .find(|segment| segment.contains(offset))
+                .map_or(NO_UAV, |segment| segment.uav_type.as_str());
+            let predicted = if uav_type == "Unknown" { NO_UAV } else { uav_type };
+            decisions.push(Decision { truth: truth.into(), predicted: predicted.into(), score });
+        }
+
+        for segment in segments {
+            let (count, detected) = latencies.entry(segment.uav_type.clone()).or_default();
+            *count += 1;
+            let first = offsets.iter().find(|(offset, uav_type, score)| {
+                segment.contains(*offset) && *uav_type == segment.uav_type && *score >= threshold
+            });
+            if let Some((offset, _, _)) = first {
+                detected.push(((offset - segment.start_s) * 1000.0).round() as u64);
+            }
+        }
+    }
+
+    let mut confusion: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for decision in &decisions {
+        let predicted = if decision.score >= threshold { &decision.predicted } else { NO_UAV };
+        *confusion.entry(decision.truth.clone()).or_default().entry(predicted.into()).or_default() += 1;
+    }
+
+    let mut types: Vec<&str> = decisions.iter()
+        .flat_map(|decision| [decision.truth.as_str(), decision.predicted.as_str()])
+        .filter(|uav_type| *uav_type != NO_UAV)
+        .collect();
+    types.sort();
+    types.dedup();
+
+    let uav_types = types.iter()
+        .map(|&uav_type| {
+            let count = |truth: bool, predicted: bool| confusion.iter()
+                .filter(|(label, _)| (label.as_str() == uav_type) == truth)
+                .flat_map(|(_, row)| row.iter())
+                .filter(|(label, _)| (label.as_str() == uav_type) == predicted)
+                .map(|(_, count)| count)
+                .sum::<usize>();
+            let (tp, fp, fn_) = (count(true, true), count(false, true), count(true, false));
+            let precision = ratio(tp, tp + fp);
+            let recall = ratio(tp, tp + fn_);
+            let f1 = precision.zip(recall)
+                .filter(|(precision, recall)| precision + recall > 0.0)
+                .map(|(precision, recall)| 2.0 * precision * recall / (precision + recall));
+            let scored: Vec<(bool, f32)> = decisions.iter()
+                .map(|decision| (decision.truth == uav_type, if decision.predicted == uav_type { decision.score } else { 0.0 }))
+                .collect();
+            let metrics = TypeMetrics {
+                support: tp + fn_,
+                true_positives: tp,
+                false_positives: fp,
+                false_negatives: fn_,
+                precision,
+                recall,
+                f1,
+                roc: roc(&scored),
+            };
+            (uav_type.to_string(), metrics)
+        })
+        .collect();
+
+    let presence: Vec<(bool, f32)> = decisions.iter()
+        .map(|decision| (decision.truth != NO_UAV, if decision.predicted != NO_UAV { decision.score } else { 0.0 }))
+        .collect();
+
+    let latency = latencies.into_iter()
+        .map(|(uav_type, (segments, mut detected))| {
+            detected.sort();
+            let latency = Latency {
+                segments,
+                detected: detected.len(),
+                mean_ms: (!detected.is_empty()).then(|| detected.iter().sum::<u64>() / detected.len() as u64),
+                median_ms: detected.get(detected.len() / 2).copied(),
+                max_ms: detected.last().copied(),
+            };
+            (uav_type, latency)
+        })
+        .collect();
+
+    Evaluation {
+        threshold,
+        recordings: report.recordings.len(),
+        detections: decisions.len(),
+        confusion,
+        uav_types,
+        presence: roc(&presence),
+        latency,
+    }
+}
+
+/// Time into the recording of a detection (in s).
+fn offset_s(recording: &RecordingReport, timestamp: u64) -> f64 {
+    timestamp.saturating_sub(recording.started_at) as f64 / 1000.0
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f32> {
+    (denominator > 0).then(|| numerator as f32 / denominator as f32)
+}
+
+/// ROC curve of `(positive, score)` pairs, with a point at every distinct score.
+fn roc(scored: &[(bool, f32)]) -> Roc {
+    let positives = scored.iter().filter(|(positive, _)| *positive).count();
+    let negatives = scored.len() - positives;
+    let mut sorted = scored.to_vec();
+    sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut points = Vec::new();
+    let (mut tp, mut fp) = (0, 0);
+    for (i, (positive, score)) in sorted.iter().enumerate() {
+        if *positive { tp += 1 } else { fp += 1 }
+        if sorted.get(i + 1).is_some_and(|(_, next)| next == score) {
+            continue;
+        }
+        points.push(RocPoint {
+            threshold: *score,
+            true_positive_rate: ratio(tp, positives).unwrap_or(0.0),
+            false_positive_rate: ratio(fp, negatives).unwrap_or(0.0),
+        });
+    }
+
+    let auc = (positives > 0 && negatives > 0).then(|| {
+        let mut previous = (0.0, 0.0);
+        points.iter().fold(0.0, |area, point| {
+            let (fpr, tpr) = (point.false_positive_rate, point.true_positive_rate);
+            let area = area + (fpr - previous.0) * (tpr + previous.1) / 2.0;
+            previous = (fpr, tpr);
+            area
+        })
+    });
+    Roc { auc, points }
+}
+
+impl Evaluation {
+    /// Human readable summary, without the ROC points.
+    pub fn to_markdown(&self) -> String {
+        let percent = |value: Option<f32>| value.map_or("–".to_string(), |value| format!("{:.1}%", value * 100.0));
+        let number = |value: Option<f32>| value.map_or("–".to_string(), |value| format!("{:.3}", value));
+        let ms = |value: Option<u64>| value.map_or("–".to_string(), |value| format!("{} ms", value));
+
+        let mut md = String::new();
+        writeln!(md, "# Detection evaluation\n").unwrap();
+        writeln!(
+            md, "{} detections over {} recordings, score threshold {}.\n",
+            self.detections, self.recordings, self.threshold
+        ).unwrap();
+        writeln!(md, "UAV presence AUC: {}\n", number(self.presence.auc)).unwrap();
+
+        writeln!(md, "## Per UAV type\n").unwrap();
+        writeln!(md, "| UAV type | Support | Precision | Recall | F1 | AUC |").unwrap();
+        writeln!(md, "|---|---:|---:|---:|---:|---:|").unwrap();
+        for (uav_type, metrics) in &self.uav_types {
+            writeln!(
+                md, "| {} | {} | {} | {} | {} | {} |",
+                uav_type, metrics.support, percent(metrics.precision), percent(metrics.recall),
+                number(metrics.f1), number(metrics.roc.auc)
+            ).unwrap();
+        }
+
+        let mut predicted: Vec<&String> = self.confusion.values().flat_map(|row| row.keys()).collect();
+        predicted.sort();
+        predicted.dedup();
+        writeln!(md, "\n## Confusion matrix\n").unwrap();
+        writeln!(md, "Rows are labels, columns predictions.\n").unwrap();
+        writeln!(md, "| | {} |", predicted.iter().map(|label| label.as_str()).collect::<Vec<_>>().join(" | ")).unwrap();
+        writeln!(md, "|---|{}", "---:|".repeat(predicted.len())).unwrap();
+        for (truth, row) in &self.confusion {
+            let counts: Vec<String> = predicted.iter().map(|label| row.get(*label).copied().unwrap_or(0).to_string()).collect();
+            writeln!(md, "| {} | {} |", truth, counts.join(" | ")).unwrap();
+        }
+
+        writeln!(md, "\n## Detection latency\n").unwrap();
+        writeln!(md, "| UAV type | Segments | Detected | Mean | Median | Max |").unwrap();
+        writeln!(md, "|---|---:|---:|---:|---:|---:|").unwrap();
+        for (uav_type, latency) in &self.latency {
+            writeln!(
+                md, "| {} | {} | {} | {} | {} | {} |",
+                uav_type, latency.segments, latency.detected, ms(latency.mean_ms), ms(latency.median_ms), ms(latency.max_ms)
+            ).unwrap();
+        }
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::DetectionInfo;
+
+    const STARTED_AT: u64 = 1_000_000;
+
+    fn detection(offset_ms: u64, uav_type: &str, score: f32) -> DetectionInfo {
+        serde_json::from_value(serde_json::json!({
+            "score": score,
+            "timestamp": STARTED_AT + offset_ms,
+            "uav_type": uav_type,
+            "hypotheses": [],
+            "snr_db": null,
+            "noise_floor_dbfs": -100.0,
+            "emissions": [],
+            "hopping": null,
+            "bursts": null,
+            "sensor_id": "sensor-1",
+            "center_freq": null,
+            "config_version": "test",
+            "library_version": "test",
+        }))
+        .unwrap()
+    }
+
+    fn report(detections: Vec<DetectionInfo>) -> ReplayReport {
+        ReplayReport {
+            recordings: vec![RecordingReport {
+                path: "test.cf32".into(),
+                sample_rate: 62_500,
+                center_freq: None,
+                started_at: STARTED_AT,
+                duration_s: 10.0,
+                summary: BTreeMap::new(),
+                detections,
+            }],
+        }
+    }
+
+    fn segment(uav_type: &str, start_s: f64, end_s: f64) -> Segment {
+        Segment { uav_type: uav_type.into(), start_s, end_s: Some(end_s) }
+    }
+
+    #[test]
+    fn roc_of_perfect_separation() {
+        let roc = roc(&[(true, 0.9), (true, 0.8), (false, 0.3), (false, 0.1)]);
+        assert_eq!(roc.auc, Some(1.0));
+        assert_eq!(roc.points.len(), 4);
+        let last = roc.points.last().unwrap();
+        assert_eq!((last.true_positive_rate, last.false_positive_rate), (1.0, 1.0));
+    }
+
+    #[test]
+    fn roc_of_inverted_scores() {
+        let roc = roc(&[(false, 0.9), (true, 0.1)]);
+        assert_eq!(roc.auc, Some(0.0));
+    }
+
+    #[test]
+    fn roc_groups_tied_scores() {
+        let roc = roc(&[(true, 0.5), (false, 0.5), (true, 0.2)]);
+        assert_eq!(roc.points.len(), 2);
+        assert_eq!(roc.points[0].threshold, 0.5);
+        assert_eq!((roc.points[0].true_positive_rate, roc.points[0].false_positive_rate), (0.5, 1.0));
+        assert_eq!(roc.auc, Some(0.25));
+    }
+
+    #[test]
+    fn roc_without_negatives_has_no_auc() {
+        let roc = roc(&[(true, 0.9), (true, 0.4)]);
+        assert_eq!(roc.auc, None);
+        assert_eq!(roc.points.len(), 2);
+    }
+
+    #[test]
+    fn evaluate_counts_matches_and_confusions() {
+        let report = report(vec![
+            detection(1000, "Unknown", 0.0),
+            detection(2500, "Drone 1", 0.9),
+            detection(3000, "Drone 1", 0.8),
+            detection(5000, "Drone 1", 0.9),
+        ]);
+        let evaluation = evaluate(&report, &[vec![segment("Drone 1", 2.0, 4.0)]], 0.7);
+
+        assert_eq!((evaluation.recordings, evaluation.detections), (1, 4));
+        assert_eq!(evaluation.confusion["none"]["none"], 1);
+        assert_eq!(evaluation.confusion["none"]["Drone 1"], 1);
+        assert_eq!(evaluation.confusion["Drone 1"]["Drone 1"], 2);
+
+        let metrics = &evaluation.uav_types["Drone 1"];
+        assert_eq!((metrics.true_positives, metrics.false_positives, metrics.false_negatives), (2, 1, 0));
+        assert_eq!(metrics.precision, Some(2.0 / 3.0));
+        assert_eq!(metrics.recall, Some(1.0));
+
+        let latency = &evaluation.latency["Drone 1"];
+        assert_eq!((latency.segments, latency.detected), (1, 1));
+        assert_eq!(latency.median_ms, Some(500));
+    }
+
+    #[test]
+    fn evaluate_ignores_predictions_below_threshold() {
+        let report = report(vec![detection(2500, "Drone 1", 0.5), detection(3000, "Drone 2", 0.9)]);
+        let evaluation = evaluate(&report, &[vec![segment("Drone 1", 2.0, 4.0)]], 0.7);
+
+        assert_eq!(evaluation.confusion["Drone 1"]["none"], 1);
+        assert_eq!(evaluation.confusion["Drone 1"]["Drone 2"], 1);
+        let metrics = &evaluation.uav_types["Drone 1"];
+        assert_eq!((metrics.true_positives, metrics.false_negatives), (0, 2));
+        assert_eq!(metrics.precision, None);
+        assert_eq!(evaluation.latency["Drone 1"].detected, 0);
+    }
+}
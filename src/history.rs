@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::Deserialize;
+
+use crate::processing::DetectionInfo;
+
+/// Default number of detections kept in memory.
+pub const HISTORY_CAPACITY: usize = 1000;
+
+/// Most recent detections, oldest first. Written by the processing actor only and read by the
+/// REST API.
+pub struct DetectionHistory {
+    entries: VecDeque<DetectionInfo>,
+    capacity: usize,
+}
+
+/// Filters of `GET /api/detections`. Timestamps are in ms since the Unix epoch and inclusive.
+#[derive(Deserialize, Debug, Default)]
+pub struct DetectionQuery {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub uav_type: Option<String>,
+    pub min_score: Option<f32>,
+    pub sensor_id: Option<String>,
+    /// Only detections of the band at this center frequency (in Hz).
+    pub center_freq: Option<u64>,
+    /// Only return the most recent `limit` matches.
+    pub limit: Option<usize>,
+}
+
+impl DetectionQuery {
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since > until {
+                return Err("since is after until".into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, detection: &DetectionInfo) -> bool {
+        self.since.is_none_or(|since| detection.timestamp() >= since)
+            && self.until.is_none_or(|until| detection.timestamp() <= until)
+            && self.uav_type.as_ref().is_none_or(|uav_type| detection.uav_type() == uav_type)
+            && self.min_score.is_none_or(|min_score| detection.score() >= min_score)
+            && self.sensor_id.as_ref().is_none_or(|sensor_id| detection.sensor_id() == sensor_id)
+            && self.center_freq.is_none_or(|center_freq| detection.center_freq() == Some(center_freq))
+    }
+}
+
+impl DetectionHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, detection: DetectionInfo) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(detection);
+    }
+
+    pub fn latest(&self) -> Option<&DetectionInfo> {
+        self.entries.back()
+    }
+
+    /// Most recent detection of every band of every sensor still in the history, sorted by sensor
+    /// and center frequency.
+    pub fn latest_per_band(&self) -> Vec<DetectionInfo> {
+        let mut bands = BTreeMap::new();
+        for detection in self.entries.iter().rev() {
+            bands.entry((detection.sensor_id(), detection.center_freq())).or_insert_with(|| detection.clone());
+        }
+        bands.into_values().collect()
+    }
+
+    /// Detections matching `query`, oldest first.
+    pub fn query(&self, query: &DetectionQuery) -> Vec<DetectionInfo> {
+        let mut matches: Vec<DetectionInfo> = self.entries.iter()
+            .rev()
+            .filter(|detection| query.matches(detection))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        matches.reverse();
+        matches
+    }
+
+    /// Like `query`, but `None` unless the answer is complete, i.e. detections the history already
+    /// dropped couldn't have matched: `since` is within the history, or `limit` newer ones matched.
+    pub fn query_recent(&self, query: &DetectionQuery) -> Option<Vec<DetectionInfo>> {
+        let oldest = self.entries.front()?.timestamp();
+        let matches = self.query(query);
+        let complete = query.since.is_some_and(|since| since >= oldest)
+            || query.limit.is_some_and(|limit| matches.len() >= limit);
+        complete.then_some(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detection(timestamp: u64, uav_type: &str) -> DetectionInfo {
+        serde_json::from_value(serde_json::json!({
+            "score": 0.9,
+            "timestamp": timestamp,
+            "uav_type": uav_type,
+            "hypotheses": [],
+            "snr_db": null,
+            "noise_floor_dbfs": -100.0,
+            "emissions": [],
+            "hopping": null,
+            "bursts": null,
+            "sensor_id": "sensor-1",
+            "center_freq": null,
+            "config_version": "test",
+            "library_version": "test",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn recent_queries_are_answered_only_if_complete() {
+        let mut history = DetectionHistory::new(3);
+        assert!(history.query_recent(&DetectionQuery { limit: Some(1), ..Default::default() }).is_none());
+        for (timestamp, uav_type) in [(100, "Drone 1"), (200, "Unknown"), (300, "Unknown"), (400, "Drone 1")] {
+            history.push(detection(timestamp, uav_type));
+        }
+
+        let timestamps = |detections: Option<Vec<DetectionInfo>>| {
+            detections.map(|detections| detections.iter().map(DetectionInfo::timestamp).collect::<Vec<_>>())
+        };
+        // The detection at 100 was dropped, so only queries starting later are complete
+        let since = |since| DetectionQuery { since: Some(since), ..Default::default() };
+        assert_eq!(timestamps(history.query_recent(&since(200))), Some(vec![200, 300, 400]));
+        assert_eq!(timestamps(history.query_recent(&since(100))), None);
+        assert_eq!(timestamps(history.query_recent(&DetectionQuery::default())), None);
+
+        let unknown = |limit| DetectionQuery { uav_type: Some("Unknown".into()), limit: Some(limit), ..Default::default() };
+        assert_eq!(timestamps(history.query_recent(&unknown(2))), Some(vec![200, 300]));
+        assert_eq!(timestamps(history.query_recent(&unknown(3))), None);
+    }
+}
@@ -0,0 +1,605 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Serialize, Deserialize};
+
+use crate::psd::{fftshift, WindowFunction};
+use crate::utils::parameter_score;
+
+/// Default of how far above the noise a bin of a single segment must be to be part of a burst
+/// (in dB).
+pub const BURST_THRESHOLD_DB: f32 = 10.0;
+/// Default of how far back hops are kept for the estimate (in ms).
+pub const HOP_HISTORY_MS: u64 = 2000;
+/// Default number of hops needed before a pattern is estimated.
+pub const MIN_HOPS: usize = 6;
+/// Default relative deviation from a signature's hop parameters that still scores above 0.
+pub const HOP_TOLERANCE: f32 = 0.25;
+/// Default number of samples per FFT segment. Much shorter than the PSD's, so at the default
+/// sample rate a segment starts about every ms, fine enough to time dwells of a few ms.
+pub const HOP_FFT_SIZE: usize = 128;
+
+/// Bins more than this below the strongest bin of a segment are ignored, so the window's
+/// sidelobes around a strong burst aren't taken for bursts of their own (in dB).
+const DYNAMIC_RANGE_DB: f32 = 30.0;
+
+/// Fewest segments a burst must be seen in at half its peak power or more, so the shortest dwell
+/// measured is 3 half FFTs (about 3 ms at the defaults). Shorter ones are mostly noise.
+const MIN_BURST_SEGMENTS: usize = 3;
+
+/// A burst's bandwidth counts the bins within this much of its strongest, so it doesn't grow with
+/// the SNR as the window's skirts rise above the noise (in dB).
+const WIDTH_DB: f32 = 10.0;
+
+/// Runs of bins above the threshold at most this many bins apart belong to the same burst, e.g.
+/// the two tones of an FSK channel.
+const MERGE_BINS: usize = 2;
+
+/// Bursts whose centers are at most this many bins apart are on the same channel.
+const CHANNEL_BINS: f32 = 2.0;
+
+/// Fewest distinct channels for a pattern to count as hopping rather than a carrier switching on
+/// and off.
+const MIN_CHANNELS: usize = 3;
+
+/// Fewest bursts on a channel for it to be part of the hop set. A hopper keeps coming back to its
+/// channels, other emitters' bursts landing on a frequency now and then don't.
+const MIN_VISITS: usize = 3;
+
+/// Channels with more than this many times the visits of the median channel belong to another
+/// emitter, e.g. a video downlink or Wi-Fi switching on and off in place.
+const MAX_VISITS_RATIO: f32 = 3.0;
+
+/// Relative deviation from the median of the dwells on a channel, or of the intervals between
+/// hops, that still belongs to the hop set. Outliers are other emitters' bursts, or hops that were
+/// missed or merged.
+const TIMING_TOLERANCE: f32 = 0.25;
+
+/// Quantile of a segment's bins taken as its noise, and the ratio of that quantile to the mean
+/// of noise power, which is exponentially distributed: -ln(1 - 0.25).
+const NOISE_QUANTILE: f32 = 0.25;
+const NOISE_QUANTILE_TO_MEAN: f32 = 0.287_682;
+
+/// Weight of each segment's noise estimate.
+const NOISE_ALPHA: f32 = 0.05;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HoppingConfig {
+    pub threshold_db: f32,
+    pub history_ms: u64,
+    pub min_hops: usize,
+    /// Samples per FFT segment of the hop tracker, which steps by half of it.
+    pub fft_size: usize,
+}
+
+impl Default for HoppingConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: BURST_THRESHOLD_DB,
+            history_ms: HOP_HISTORY_MS,
+            min_hops: MIN_HOPS,
+            fft_size: HOP_FFT_SIZE,
+        }
+    }
+}
+
+impl HoppingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.threshold_db.is_finite() {
+            return Err(format!("hopping.threshold_db must be a number, got {}", self.threshold_db));
+        }
+        if self.history_ms == 0 {
+            return Err("hopping.history_ms must be greater than 0".into());
+        }
+        if self.min_hops < 2 {
+            return Err(format!("hopping.min_hops must be at least 2, got {}", self.min_hops));
+        }
+        if self.fft_size < 16 {
+            return Err(format!("hopping.fft_size must be at least 16, got {}", self.fft_size));
+        }
+        Ok(())
+    }
+}
+
+/// Hop parameters of a UAV type's control link, from the signature file. Parameters left out
+/// aren't compared.
+///
+/// ```json
+/// { "name": "Drone 1", "audio_path": "drone1.wav",
+///   "hopping": { "hop_rate_hz": 100, "channel_spacing_hz": 2000, "tolerance": 0.2 } }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HopPattern {
+    pub hop_rate_hz: Option<f32>,
+    pub dwell_ms: Option<f32>,
+    pub channel_spacing_hz: Option<f32>,
+    pub occupied_bandwidth_hz: Option<f32>,
+    /// Relative deviation from each parameter that still scores above 0.
+    #[serde(default = "default_tolerance")]
+    pub tolerance: f32,
+}
+
+fn default_tolerance() -> f32 {
+    HOP_TOLERANCE
+}
+
+impl HopPattern {
+    fn parameters(&self) -> [Option<f32>; 4] {
+        [self.hop_rate_hz, self.dwell_ms, self.channel_spacing_hz, self.occupied_bandwidth_hz]
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.parameters().iter().all(Option::is_none) {
+            return Err("hopping needs at least one parameter".into());
+        }
+        if self.parameters().iter().flatten().any(|value| !value.is_finite() || *value <= 0.0) {
+            return Err("hopping parameters must be greater than 0".into());
+        }
+        if !self.tolerance.is_finite() || self.tolerance <= 0.0 {
+            return Err(format!("hopping tolerance must be greater than 0, got {}", self.tolerance));
+        }
+        Ok(())
+    }
+
+    /// How close the estimate is to this pattern, see `parameter_score`.
+    pub fn score(&self, estimate: &HopEstimate) -> f32 {
+        let estimated = [
+            Some(estimate.hop_rate_hz),
+            Some(estimate.dwell_ms),
+            estimate.channel_spacing_hz,
+            Some(estimate.occupied_bandwidth_hz),
+        ];
+        parameter_score(&self.parameters(), &estimated, self.tolerance)
+    }
+}
+
+/// Hop parameters measured from the recent bursts.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HopEstimate {
+    pub hop_rate_hz: f32,
+    /// Typical time spent on a channel.
+    pub dwell_ms: f32,
+    /// Median distance between neighbouring channels, `None` if it can't be told apart from the
+    /// frequency resolution.
+    pub channel_spacing_hz: Option<f32>,
+    /// Distance between the lowest and highest channel.
+    pub occupied_bandwidth_hz: f32,
+    pub channels: usize,
+}
+
+/// Bins above the threshold in one segment, gaps of up to `MERGE_BINS` included.
+struct Run {
+    bins: (usize, usize),
+    power: f32,
+    /// Sum of each bin's frequency times its power.
+    weighted: f32,
+    /// Bins within `WIDTH_DB` of the run's strongest.
+    width: usize,
+}
+
+/// Burst still present in the latest segment. Times are sample indices of segment centers.
+struct OpenBurst {
+    /// Lowest and highest bin it covered so far.
+    bins: (usize, usize),
+    /// Center and power of every segment it was seen in. Emptied once it's longer than the
+    /// history, as it can't be a hop then.
+    segments: Vec<(u64, f32)>,
+    too_long: bool,
+    power: f32,
+    weighted: f32,
+    /// Sum of its runs' widths.
+    widths: usize,
+}
+
+impl OpenBurst {
+    fn new(run: Run, time: u64) -> Self {
+        Self {
+            bins: run.bins,
+            segments: vec![(time, run.power)],
+            too_long: false,
+            power: run.power,
+            weighted: run.weighted,
+            widths: run.width,
+        }
+    }
+
+    fn is_near(&self, run: &Run) -> bool {
+        run.bins.0 <= self.bins.1 + MERGE_BINS + 1 && self.bins.0 <= run.bins.1 + MERGE_BINS + 1
+    }
+
+    fn extend(&mut self, run: Run, time: u64, max_segments: usize) {
+        self.bins = (self.bins.0.min(run.bins.0), self.bins.1.max(run.bins.1));
+        self.power += run.power;
+        self.weighted += run.weighted;
+        self.widths += run.width;
+        if let Some((_, power)) = self.segments.last_mut().filter(|(last, _)| *last == time) {
+            *power += run.power;
+        } else if self.segments.len() >= max_segments {
+            self.segments = Vec::new();
+            self.too_long = true;
+        } else if !self.too_long {
+            self.segments.push((time, run.power));
+        }
+    }
+}
+
+struct Hop {
+    freq: f32,
+    /// Mean width of the burst's runs.
+    bandwidth: f32,
+    start: u64,
+    dwell: u64,
+}
+
+/// Follows short bursts across the segments of a short-time FFT of the sample stream, and
+/// estimates the hop pattern they form. Runs on its own FFT rather than the PSD's, whose segments
+/// are too long to time the dwells of most hoppers.
+pub struct HopTracker {
+    config: HoppingConfig,
+    sample_rate: u32,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    /// Samples not analysed yet, the first one at sample index `position`.
+    pending: Vec<Complex<f32>>,
+    position: u64,
+    /// Mean noise power of a bin.
+    noise: Option<f32>,
+    open: Vec<OpenBurst>,
+    /// Closed bursts within `history_ms`, sorted by start. Long bursts close after shorter ones
+    /// that started later, so they're inserted in order rather than appended.
+    hops: VecDeque<Hop>,
+}
+
+impl HopTracker {
+    pub fn new(config: &HoppingConfig, sample_rate: u32) -> Self {
+        Self {
+            config: config.clone(),
+            sample_rate,
+            fft: FftPlanner::new().plan_fft_forward(config.fft_size),
+            window: WindowFunction::Hann.coefficients(config.fft_size),
+            buffer: vec![Complex::new(0.0, 0.0); config.fft_size],
+            pending: Vec::new(),
+            position: 0,
+            noise: None,
+            open: Vec::new(),
+            hops: VecDeque::new(),
+        }
+    }
+
+    /// Forgets the bursts, for when the sample stream is interrupted. The noise estimate is kept.
+    pub fn reset(&mut self) {
+        self.position += self.pending.len() as u64;
+        self.pending.clear();
+        self.open.clear();
+        self.hops.clear();
+    }
+
+    fn resolution(&self) -> f32 {
+        self.sample_rate as f32 / self.config.fft_size as f32
+    }
+
+    fn history(&self) -> u64 {
+        self.config.history_ms * self.sample_rate as u64 / 1000
+    }
+
+    /// Analyses every complete segment of the samples received so far. NaN or infinite samples
+    /// count as zeros.
+    pub fn process(&mut self, samples: &[Complex<f32>]) {
+        self.pending.extend(
+            samples.iter().map(|sample| if sample.is_finite() { *sample } else { Complex::new(0.0, 0.0) }),
+        );
+        let (n, hop) = (self.config.fft_size, self.config.fft_size / 2);
+        let mut start = 0;
+        while start + n <= self.pending.len() {
+            for (i, (sample, w)) in self.pending[start..start + n].iter().zip(&self.window).enumerate() {
+                self.buffer[i] = sample * w;
+            }
+            self.fft.process(&mut self.buffer);
+            let powers = fftshift(&self.buffer.iter().map(|bin| bin.norm_sqr()).collect::<Vec<f32>>());
+            self.segment(&powers, self.position + (start + n / 2) as u64);
+            start += hop;
+        }
+        self.pending.drain(..start);
+        self.position += start as u64;
+    }
+
+    /// Follows the bursts into the segment centered on sample `time`, closing those that ended.
+    fn segment(&mut self, powers: &[f32], time: u64) {
+        let mut sorted = powers.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let estimate = sorted[((sorted.len() - 1) as f32 * NOISE_QUANTILE) as usize] / NOISE_QUANTILE_TO_MEAN;
+        let noise = match self.noise {
+            Some(noise) => noise + NOISE_ALPHA * (estimate - noise),
+            None => estimate,
+        };
+        self.noise = Some(noise);
+
+        let peak = sorted[sorted.len() - 1];
+        let threshold = (noise * 10f32.powf(self.config.threshold_db / 10.0))
+            .max(peak * 10f32.powf(-DYNAMIC_RANGE_DB / 10.0));
+        let runs = runs(powers, threshold, self.resolution());
+
+        let max_segments = (self.history() / (self.config.fft_size / 2) as u64) as usize;
+        let mut seen = vec![false; self.open.len()];
+        for run in runs {
+            match self.open.iter().position(|burst| burst.is_near(&run)) {
+                Some(open) => {
+                    self.open[open].extend(run, time, max_segments);
+                    seen[open] = true;
+                }
+                None => {
+                    self.open.push(OpenBurst::new(run, time));
+                    seen.push(true);
+                }
+            }
+        }
+
+        // Bursts missing from this segment have ended
+        for (open, was_seen) in seen.into_iter().enumerate().rev() {
+            if !was_seen {
+                let burst = self.open.remove(open);
+                self.close(burst);
+            }
+        }
+    }
+
+    /// Keeps a burst as a hop if it was long enough. Its dwell runs between the segments at half
+    /// its peak power, where a segment is centered on the burst's edge.
+    fn close(&mut self, burst: OpenBurst) {
+        let peak = burst.segments.iter().map(|(_, power)| *power).fold(0.0, f32::max);
+        let strong: Vec<u64> = burst.segments.iter()
+            .filter(|(_, power)| *power >= peak / 2.0)
+            .map(|(time, _)| *time)
+            .collect();
+        if burst.too_long || strong.len() < MIN_BURST_SEGMENTS {
+            return;
+        }
+
+        let hop = Hop {
+            freq: burst.weighted / burst.power,
+            bandwidth: burst.widths as f32 / burst.segments.len() as f32 * self.resolution(),
+            start: strong[0],
+            dwell: strong[strong.len() - 1] - strong[0] + (self.config.fft_size / 2) as u64,
+        };
+        let index = self.hops.partition_point(|other| other.start <= hop.start);
+        self.hops.insert(index, hop);
+    }
+
+    /// Hop pattern of the hops within `history_ms`, `None` until enough hops over enough
+    /// revisited channels were seen.
+    pub fn estimate(&mut self) -> Option<HopEstimate> {
+        let now = self.position + self.pending.len() as u64;
+        let history = self.history();
+        while self.hops.front().is_some_and(|hop| hop.start + history < now) {
+            self.hops.pop_front();
+        }
+
+        // Neighbouring hops close enough in frequency share a channel
+        let same_channel = CHANNEL_BINS * self.resolution();
+        let mut by_freq: Vec<&Hop> = self.hops.iter().collect();
+        by_freq.sort_by(|a, b| a.freq.total_cmp(&b.freq));
+        let mut channels: Vec<Vec<&Hop>> = Vec::new();
+        for hop in by_freq {
+            match channels.last_mut() {
+                Some(channel) if hop.freq - channel[channel.len() - 1].freq <= same_channel => channel.push(hop),
+                _ => channels.push(vec![hop]),
+            }
+        }
+
+        channels.retain(|channel| channel.len() >= MIN_VISITS);
+        if channels.is_empty() {
+            return None;
+        }
+        // A hopper dwells about as long on every channel
+        let visits = median(channels.iter().map(|channel| channel.len() as f32).collect());
+        let dwell = |channel: &Vec<&Hop>| median(channel.iter().map(|hop| hop.dwell as f32).collect());
+        let dwells = median(channels.iter().map(dwell).collect());
+        channels.retain(|channel| {
+            channel.len() as f32 <= MAX_VISITS_RATIO * visits
+                && (dwell(channel) - dwells).abs() <= TIMING_TOLERANCE * dwells
+        });
+
+        // Channels of a hop set don't overlap. Overlapping ones are the bursts of a wideband
+        // emitter, whose centers are all over its band.
+        let center = |channel: &Vec<&Hop>| channel.iter().map(|hop| hop.freq).sum::<f32>() / channel.len() as f32;
+        let bandwidth = |channel: &Vec<&Hop>| median(channel.iter().map(|hop| hop.bandwidth).collect());
+        let overlap = |low: &Vec<&Hop>, high: &Vec<&Hop>| {
+            center(high) - center(low) < (bandwidth(low) + bandwidth(high)) / 2.0
+        };
+        let keep: Vec<bool> = (0..channels.len())
+            .map(|i| {
+                let below = i > 0 && overlap(&channels[i - 1], &channels[i]);
+                let above = i + 1 < channels.len() && overlap(&channels[i], &channels[i + 1]);
+                !below && !above
+            })
+            .collect();
+        let channels: Vec<Vec<&Hop>> = channels.into_iter()
+            .zip(keep)
+            .filter_map(|(channel, keep)| keep.then_some(channel))
+            .collect();
+        if channels.len() < MIN_CHANNELS {
+            return None;
+        }
+
+        let mut hops: Vec<&Hop> = channels.iter().flatten().copied().collect();
+        if hops.len() < self.config.min_hops {
+            return None;
+        }
+        hops.sort_by_key(|hop| hop.start);
+
+        let seconds = |samples: u64| samples as f32 / self.sample_rate as f32;
+        let intervals: Vec<f32> = hops.windows(2)
+            .map(|pair| seconds(pair[1].start - pair[0].start))
+            .filter(|interval| *interval > 0.0)
+            .collect();
+        if intervals.is_empty() {
+            return None;
+        }
+        let centers: Vec<f32> = channels.iter().map(center).collect();
+        let spacings: Vec<f32> = centers.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+        Some(HopEstimate {
+            hop_rate_hz: 1.0 / step(intervals),
+            dwell_ms: 1000.0 * typical(hops.iter().map(|hop| seconds(hop.dwell)).collect()),
+            channel_spacing_hz: Some(step(spacings)).filter(|spacing| *spacing > same_channel),
+            occupied_bandwidth_hz: centers[centers.len() - 1] - centers[0],
+            channels: centers.len(),
+        })
+    }
+}
+
+/// Runs of bins above `threshold`, with their power weighted frequencies. Bin 0 is at
+/// `-sample_rate / 2`.
+fn runs(powers: &[f32], threshold: f32, resolution: f32) -> Vec<Run> {
+    let first = -((powers.len() / 2) as f32) * resolution;
+    let mut runs: Vec<Run> = Vec::new();
+    for (bin, &power) in powers.iter().enumerate() {
+        if power <= threshold {
+            continue;
+        }
+        let weighted = power * (first + bin as f32 * resolution);
+        match runs.last_mut() {
+            Some(run) if bin - run.bins.1 <= MERGE_BINS + 1 => {
+                run.bins.1 = bin;
+                run.power += power;
+                run.weighted += weighted;
+            }
+            _ => runs.push(Run { bins: (bin, bin), power, weighted, width: 0 }),
+        }
+    }
+    for run in &mut runs {
+        let bins = &powers[run.bins.0..=run.bins.1];
+        let peak = bins.iter().copied().fold(0.0, f32::max);
+        run.width = bins.iter().filter(|power| **power >= peak * 10f32.powf(-WIDTH_DB / 10.0)).count();
+    }
+    runs
+}
+
+/// Mean of the values within `TIMING_TOLERANCE` of the median. Averages away the segment steps
+/// times are measured in, without the outliers.
+fn typical(values: Vec<f32>) -> f32 {
+    let median = median(values.clone());
+    let near: Vec<f32> = values.into_iter()
+        .filter(|value| (value - median).abs() <= TIMING_TOLERANCE * median)
+        .collect();
+    near.iter().sum::<f32>() / near.len().max(1) as f32
+}
+
+/// Typical step of values that are mostly whole multiples of it, like the intervals between hops
+/// or the spacings between channels once some channels were dropped for overlapping an
+/// interferer. The step is taken from the lower quartile, as at least that many of the values are
+/// single steps unless most of the hop set is missing.
+fn step(mut values: Vec<f32>) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    let step = values[values.len() / 4];
+    typical(values.into_iter().map(|value| value / (value / step).round().max(1.0)).collect())
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use simulator::scenario::Scenario;
+    use simulator::signals::Simulator;
+
+    use super::*;
+
+    /// 8 channels 6 kHz apart, a hop every 10 ms with an 8 ms dwell.
+    const FHSS: &str = r#"
+[[emitters]]
+kind = "fhss"
+snr_db = 10
+channels = 8
+channel_spacing_hz = 6000
+hop_interval_ms = 10
+dwell_ms = 8
+symbol_rate = 2000
+deviation_hz = 1000
+"#;
+
+    /// A video downlink and Wi-Fi, switching on and off in place.
+    const INTERFERERS: &str = r#"
+[[emitters]]
+kind = "ofdm"
+snr_db = 12
+freq_offset_hz = -12000
+bandwidth_hz = 10000
+subcarriers = 32
+burst_ms = 5
+period_ms = 20
+
+[[emitters]]
+kind = "wifi"
+snr_db = 8
+freq_offset_hz = 18000
+bandwidth_hz = 16000
+min_frame_ms = 0.5
+max_frame_ms = 3
+duty_cycle = 0.3
+"#;
+
+    /// Estimate at the end of a 3 s scenario with `emitters`, fed in blocks like a sender's
+    /// datagrams.
+    fn estimate(emitters: &str) -> Option<HopEstimate> {
+        let scenario = format!("sample_rate = 62500\ncenter_freq = 915000000\nduration_s = 3\nseed = 1\n{}", emitters);
+        let scenario: Scenario = toml::from_str(&scenario).unwrap();
+        scenario.validate().unwrap();
+        let mut simulator = Simulator::new(&scenario);
+        let mut tracker = HopTracker::new(&HoppingConfig::default(), scenario.sample_rate);
+        loop {
+            let block = simulator.next_block(1000);
+            if block.is_empty() {
+                return tracker.estimate();
+            }
+            tracker.process(&block);
+        }
+    }
+
+    fn assert_fhss(estimate: &HopEstimate, channels: usize) {
+        assert!((estimate.hop_rate_hz - 100.0).abs() < 3.0, "{:?}", estimate);
+        assert!((estimate.dwell_ms - 8.0).abs() < 0.5, "{:?}", estimate);
+        // Within a bin of the hop tracker's FFT
+        assert!((estimate.channel_spacing_hz.unwrap() - 6000.0).abs() < 488.0, "{:?}", estimate);
+        assert_eq!(estimate.channels, channels, "{:?}", estimate);
+    }
+
+    #[test]
+    fn measures_a_simulated_hopper() {
+        let estimate = estimate(FHSS).unwrap();
+        assert_fhss(&estimate, 8);
+        assert!((estimate.occupied_bandwidth_hz - 42_000.0).abs() < 488.0, "{:?}", estimate);
+    }
+
+    #[test]
+    fn ignores_carriers_switching_on_and_off_in_place() {
+        assert!(estimate(INTERFERERS).is_none());
+
+        // The interferers drown out or blur the channels they overlap
+        let estimate = estimate(&format!("{}{}", FHSS, INTERFERERS)).unwrap();
+        assert!((estimate.hop_rate_hz - 100.0).abs() < 3.0, "{:?}", estimate);
+        assert!((estimate.dwell_ms - 8.0).abs() < 0.5, "{:?}", estimate);
+        assert!(estimate.channels >= 4, "{:?}", estimate);
+    }
+
+    #[test]
+    fn hops_closing_out_of_order() {
+        // The tone starts before hops that end before it does, and isn't a channel of its own
+        let tone = "[[emitters]]\nkind = \"tone\"\nsnr_db = 10\nfreq_offset_hz = 28000\nstart_s = 1.5\nend_s = 2.5\n";
+        let estimate = estimate(&format!("{}{}", FHSS, tone)).unwrap();
+        assert_fhss(&estimate, 8);
+    }
+}
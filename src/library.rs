@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use log::{error, info, warn};
+use serde::{Serialize, Deserialize};
+use spectrum_analyzer::error::SpectrumAnalyzerError;
+use spectrum_analyzer::FrequencySpectrum;
+
+use crate::burst::BurstPattern;
+use crate::hopping::HopPattern;
+use crate::utils::{compute_spectrum, content_version, wav_to_signal};
+
+/// Entry of the UAV signature file (`UAV_DATA_PATH`).
+#[derive(Serialize, Deserialize)]
+pub struct UAVInfo {
+    pub name: String,
+    /// Reference recording, compared by the similarity classifier. Relative paths are resolved
+    /// against the signature file's directory. May be left out if the UAV has a hop or burst
+    /// pattern.
+    #[serde(default)]
+    pub audio_path: String,
+    /// Hop pattern of the UAV's control link, if it hops.
+    #[serde(default)]
+    pub hopping: Option<HopPattern>,
+    /// Burst pattern of the UAV's telemetry or video downlink, if it's bursty.
+    #[serde(default)]
+    pub bursts: Option<BurstPattern>,
+}
+
+#[derive(Debug)]
+pub enum LibraryError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    EmptyName(usize),
+    DuplicateName(String),
+    NoSignature(String),
+    OpenAudio(String, PathBuf, std::io::Error),
+    DecodeAudio(String, PathBuf, String),
+    Spectrum(String, SpectrumAnalyzerError),
+    InvalidHopPattern(String, String),
+    InvalidBurstPattern(String, String),
+}
+
+impl fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibraryError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            LibraryError::Parse(path, e) => write!(f, "could not parse {}: {}", path.display(), e),
+            LibraryError::EmptyName(index) => write!(f, "entry #{} has an empty name", index),
+            LibraryError::DuplicateName(name) => write!(f, "'{}' is defined more than once", name),
+            LibraryError::NoSignature(name) => {
+                write!(f, "'{}' needs an audio_path, a hop pattern or a burst pattern", name)
+            }
+            LibraryError::OpenAudio(name, path, e) => {
+                write!(f, "could not open {} for '{}': {}", path.display(), name, e)
+            }
+            LibraryError::DecodeAudio(name, path, e) => {
+                write!(f, "could not decode {} for '{}': {}", path.display(), name, e)
+            }
+            LibraryError::Spectrum(name, e) => write!(f, "could not compute spectrum for '{}': {:?}", name, e),
+            LibraryError::InvalidHopPattern(name, e) => write!(f, "invalid hop pattern for '{}': {}", name, e),
+            LibraryError::InvalidBurstPattern(name, e) => write!(f, "invalid burst pattern for '{}': {}", name, e),
+        }
+    }
+}
+
+/// UAV signatures, loaded and turned into spectra once so every detection tick can reuse them.
+pub struct ReferenceLibrary {
+    /// Hash of the signature file's content, so clients can tell which signatures produced a
+    /// detection.
+    version: String,
+    spectra: HashMap<String, FrequencySpectrum>,
+    hop_patterns: HashMap<String, HopPattern>,
+    burst_patterns: HashMap<String, BurstPattern>,
+    names: HashSet<String>,
+    /// Why each invalid entry of the signature file was left out.
+    skipped: Vec<String>,
+}
+
+impl ReferenceLibrary {
+    pub fn empty() -> Self {
+        Self {
+            version: "empty".into(),
+            spectra: HashMap::new(),
+            hop_patterns: HashMap::new(),
+            burst_patterns: HashMap::new(),
+            names: HashSet::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Like `load`, but falls back to an empty library (every detection reporting Unknown) if the
+    /// file is invalid.
+    pub fn load_or_empty(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Ok(library) => {
+                info!(
+                    "Loaded {} UAV signatures from {}, skipped {} invalid",
+                    library.len(), path.display(), library.skipped().len(),
+                );
+                library
+            }
+            Err(e) => {
+                error!("Invalid UAV library {}: {}. Detections will report Unknown", path.display(), e);
+                Self::empty()
+            }
+        }
+    }
+
+    /// Reads the signature file at `path` and computes the spectrum of every reference recording.
+    /// Invalid entries are logged and left out, so one broken signature doesn't take the others
+    /// down with it. Only an unreadable file fails the whole library.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LibraryError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| LibraryError::Read(path.to_path_buf(), e))?;
+        let entries: Vec<UAVInfo> = serde_json::from_str(&content)
+            .map_err(|e| LibraryError::Parse(path.to_path_buf(), e))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+
+        let mut library = Self { version: content_version(&content), ..Self::empty() };
+        for (index, uav) in entries.into_iter().enumerate() {
+            if let Err(e) = library.add(index, uav, base_dir) {
+                warn!("Skipping invalid UAV signature in {}: {}", path.display(), e);
+                library.skipped.push(e.to_string());
+            }
+        }
+        Ok(library)
+    }
+
+    /// Validates an entry of the signature file and adds it. Nothing is added if it's invalid.
+    fn add(&mut self, index: usize, uav: UAVInfo, base_dir: &Path) -> Result<(), LibraryError> {
+        if uav.name.trim().is_empty() {
+            return Err(LibraryError::EmptyName(index));
+        }
+        if self.names.contains(&uav.name) {
+            return Err(LibraryError::DuplicateName(uav.name));
+        }
+        if uav.audio_path.trim().is_empty() && uav.hopping.is_none() && uav.bursts.is_none() {
+            return Err(LibraryError::NoSignature(uav.name));
+        }
+
+        if let Some(pattern) = &uav.hopping {
+            pattern.validate().map_err(|e| LibraryError::InvalidHopPattern(uav.name.clone(), e))?;
+        }
+        if let Some(pattern) = &uav.bursts {
+            pattern.validate().map_err(|e| LibraryError::InvalidBurstPattern(uav.name.clone(), e))?;
+        }
+
+        if !uav.audio_path.trim().is_empty() {
+            let audio_path = base_dir.join(&uav.audio_path);
+            let file = File::open(&audio_path)
+                .map_err(|e| LibraryError::OpenAudio(uav.name.clone(), audio_path.clone(), e))?;
+            let (sample_rate, signal) = wav_to_signal(file)
+                .map_err(|e| LibraryError::DecodeAudio(uav.name.clone(), audio_path.clone(), e))?;
+            let spectrum = compute_spectrum(&signal, sample_rate)
+                .map_err(|e| LibraryError::Spectrum(uav.name.clone(), e))?;
+            self.spectra.insert(uav.name.clone(), spectrum);
+        }
+        if let Some(pattern) = uav.hopping {
+            self.hop_patterns.insert(uav.name.clone(), pattern);
+        }
+        if let Some(pattern) = uav.bursts {
+            self.burst_patterns.insert(uav.name.clone(), pattern);
+        }
+        self.names.insert(uav.name);
+        Ok(())
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn spectra(&self) -> &HashMap<String, FrequencySpectrum> {
+        &self.spectra
+    }
+
+    pub fn hop_patterns(&self) -> &HashMap<String, HopPattern> {
+        &self.hop_patterns
+    }
+
+    pub fn burst_patterns(&self) -> &HashMap<String, BurstPattern> {
+        &self.burst_patterns
+    }
+
+    /// Number of signatures, with a reference recording or only hop or burst patterns.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Errors of the entries of the signature file that were invalid and left out.
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+}
+
+/// Sent to WebSocket clients when the signature file changes.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LibraryEvent {
+    LibraryReloaded { version: String, signatures: usize },
+    /// The new file was unreadable or had invalid entries, and `version` is still in use.
+    LibraryReloadFailed { version: String, error: String },
+}
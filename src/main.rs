@@ -1,75 +1,332 @@
-use std::{collections::HashSet, sync::{Arc, Mutex}};
-use actix::{Actor, Addr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use actix::{Addr, Supervisor};
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
-use log::info;
-use processing::{ProcessingActor, DetectionInfo, SignalWindow};
-use udp::UdpListenerActor;
-use websockets::WsActor;
+use clap::Parser;
+use config::{Cli, Config};
+use evaluation::{evaluate, Dataset};
+use history::{DetectionHistory, DetectionQuery};
+use library::ReferenceLibrary;
+use log::{error, info, warn};
+use pipelines::{topology, GetSensors, PipelineRegistry, ReloadLibrary};
+use processing::ProcessingActor;
+use recording::start_clip_writer;
+use replay::{open_recordings, replay, ReplaySpeed};
+use serde::Deserialize;
+use spectrum::SpectrumOptions;
+use store::{query_detections, start_store, StoreActor};
+use udp::{GetSourceStats, Subscribe, UdpListenerActor};
+use websockets::{WsActor, WsServices};
 
+mod alarm;
+mod config;
+mod evaluation;
 mod udp;
+mod packet;
+mod library;
+mod burst;
+mod cfar;
+mod classifier;
+mod history;
+mod hopping;
+mod pipelines;
 mod processing;
+mod protocol;
+mod psd;
+mod recording;
+mod replay;
+mod similarity;
+mod spectrum;
+mod store;
 mod websockets;
 mod utils;
 
 struct AppState {
-    processing_actor: Addr<ProcessingActor>,
-    udp_listener_actor: Addr<UdpListenerActor>,
-    latest_score: Arc<Mutex<DetectionInfo>>,
+    pipelines: Addr<PipelineRegistry>,
+    /// `None` when replaying recordings.
+    udp_listener_actor: Option<Addr<UdpListenerActor>>,
+    history: Arc<Mutex<DetectionHistory>>,
+    store: Option<Addr<StoreActor>>,
+    config: Arc<Config>,
 }
 
 impl AppState {
     fn new(
-        udp_listener_actor: Addr<UdpListenerActor>,
-        processing_actor: Addr<ProcessingActor>,
+        udp_listener_actor: Option<Addr<UdpListenerActor>>,
+        pipelines: Addr<PipelineRegistry>,
+        history: Arc<Mutex<DetectionHistory>>,
+        store: Option<Addr<StoreActor>>,
+        config: Arc<Config>,
     ) -> Self {
         Self {
-            processing_actor,
+            pipelines,
             udp_listener_actor,
-            latest_score: Arc::new(Mutex::new(DetectionInfo::new())),
+            history,
+            store,
+            config,
         }
     }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    let mut config = match Config::from_cli(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config).expect("configuration should serialise to TOML"));
+        return Ok(());
+    }
+
     env_logger::Builder::from_default_env()
         .filter_level(log::LevelFilter::Info)
         .init();
 
     info!("Starting server");
 
+    // Recordings replace the UDP feed, and set the sample rate the detections run at
+    let raw_format = cli.replay_format.unwrap_or(config.udp.sample_format);
+    let (recordings, labels) = if let Some(directory) = &cli.evaluate {
+        let dataset = Dataset::load(directory, raw_format, config.processing.sample_rate)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        info!("Evaluating {} labelled recordings in {}", dataset.recordings.len(), directory.display());
+        (Some(dataset.recordings), Some(dataset.labels))
+    } else if !cli.replay.is_empty() {
+        let recordings = open_recordings(&cli.replay, raw_format, config.processing.sample_rate)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        (Some(recordings), None)
+    } else {
+        (None, None)
+    };
+    if let Some(first) = recordings.as_ref().and_then(|recordings| recordings.first()) {
+        if first.sample_rate != config.processing.sample_rate {
+            warn!(
+                "Processing at {} samples/s, the sample rate of {}, instead of the configured {}",
+                first.sample_rate, first.path.display(), config.processing.sample_rate,
+            );
+        }
+        config.processing.sample_rate = first.sample_rate;
+    }
+    if recordings.is_some() {
+        // Keep replays and evaluations out of the detection log and the alarm recordings
+        config.store.enabled = false;
+        config.recording.enabled = false;
+    }
+
     // Start the UdpListenerActor and store its Addr
-    let udp_listener_actor = UdpListenerActor::new().await.start();
-    info!("UDP listener actor started");
+    let udp_listener_actor = if recordings.is_none() {
+        let udp_listener_actor = UdpListenerActor::new(&config.udp, config.processing.sample_rate).await?;
+        let udp_listener_actor = Supervisor::start(|_| udp_listener_actor);
+        info!(
+            "UDP listener actor started on {}:{} ({} samples)",
+            config.udp.address, config.udp.port, config.udp.sample_format
+        );
+        Some(udp_listener_actor)
+    } else {
+        None
+    };
+
+    // Shared between the processing actors, which record detections, and the REST API
+    let history = Arc::new(Mutex::new(DetectionHistory::new(config.history.capacity)));
+
+    // Durable detection log, queried by the REST API when enabled
+    let store = if config.store.enabled {
+        let store = start_store(&config.store).map_err(|e| {
+            std::io::Error::other(format!("could not open detection store {}: {}", config.store.path.display(), e))
+        })?;
+        Some(store)
+    } else {
+        None
+    };
+
+    // Writes the samples around raised alarms to disk when enabled
+    let clip_writer = if config.recording.enabled {
+        let writer = start_clip_writer(&config.recording.directory).map_err(|e| {
+            std::io::Error::other(format!(
+                "could not create recording directory {}: {}", config.recording.directory.display(), e
+            ))
+        })?;
+        Some(writer)
+    } else {
+        None
+    };
+
+    // Live senders get a pipeline each when they first send samples. A replay feeds a single one
+    // and decides when its detections run.
+    let library = Arc::new(ReferenceLibrary::load_or_empty(&config.library.path));
+    let registry = PipelineRegistry::new(&config, library.clone(), history.clone(), store.clone(), clip_writer.clone());
+    let (pipelines, replay_pipeline) = match &recordings {
+        Some(_) => {
+            let sensor_id = &config.processing.sensor_id;
+            let processing = ProcessingActor::new(&config, sensor_id, library, history.clone(), store.clone(), clip_writer)
+                .with_external_clock();
+            let processing = Supervisor::start(|_| processing);
+            let registry = registry.with_pipeline(sensor_id, processing.clone());
+            (Supervisor::start(|_| registry), Some(processing))
+        }
+        None => (Supervisor::start(|_| registry), None),
+    };
+    info!("Pipeline registry started");
 
-    // Start ProcessingActor and store its Addr
-    let processing_actor = ProcessingActor::new().start();
-    info!("Processing actor started");
+    // Without this the listener receives samples but has nowhere to send them
+    if let Some(udp) = &udp_listener_actor {
+        udp.do_send(Subscribe(pipelines.clone()));
+    }
+
+    if let (Some(recordings), Some(processing_actor)) = (recordings, replay_pipeline) {
+        let detection_interval = Duration::from_millis(config.processing.detection_interval_ms);
+        let speed = if labels.is_some() { ReplaySpeed::Fast } else { cli.replay_speed };
+        let replay = replay(recordings, processing_actor.clone(), detection_interval, speed);
+
+        if let Some(labels) = labels {
+            let report = replay.await.map_err(|e| std::io::Error::other(e.to_string()))?;
+            let evaluation = evaluate(&report, &labels, cli.threshold.unwrap_or(config.alarm.raise_threshold));
+            let path = cli.report.clone().unwrap_or_else(|| "evaluation.json".into());
+            let json = serde_json::to_string_pretty(&evaluation).expect("evaluations should serialise to JSON");
+            std::fs::write(&path, json)?;
+            std::fs::write(path.with_extension("md"), evaluation.to_markdown())?;
+            info!("Wrote evaluation to {} and {}", path.display(), path.with_extension("md").display());
+            return Ok(());
+        }
+
+        // Either write the detections to a report and stop there, or serve them while replaying
+        if let Some(path) = &cli.report {
+            let report = replay.await.map_err(|e| std::io::Error::other(e.to_string()))?;
+            let json = serde_json::to_string_pretty(&report).expect("replay reports should serialise to JSON");
+            std::fs::write(path, json)?;
+            info!("Wrote replay report to {}", path.display());
+            return Ok(());
+        }
+        actix_web::rt::spawn(async move {
+            match replay.await {
+                Ok(report) => info!("Replay finished, {} recordings", report.recordings.len()),
+                Err(e) => error!("Replay failed: {}", e),
+            }
+        });
+    }
 
+    let bind = config.http.bind.clone();
+    let config = Arc::new(config);
     HttpServer::new(move || {
         App::new()
             // Share DetectionActor's address via app data, accessible through web::Data
             .app_data(web::Data::new(AppState::new(
-                udp_listener_actor.clone(), processing_actor.clone()
+                udp_listener_actor.clone(), pipelines.clone(), history.clone(), store.clone(), config.clone()
             )))
             .route("/", web::get().to(|| async { HttpResponse::Ok().finish() }))
             .route("/ws", web::get().to(ws_route))
+            .route("/api/sources", web::get().to(sources_route))
+            .route("/api/sensors", web::get().to(sensors_route))
+            .route("/api/pipelines", web::get().to(pipelines_route))
+            .route("/api/library/reload", web::post().to(reload_library_route))
+            .route("/api/detections", web::get().to(detections_route))
+            .route("/api/detections/latest", web::get().to(latest_detection_route))
+            .route("/api/bands", web::get().to(bands_route))
     })
-    .bind(("127.0.0.1", 4001))?
+    .bind(bind.as_str())?
     .run()
     .await
 }
 
+/// `/ws` query parameters besides the `SpectrumOptions`.
+#[derive(Deserialize)]
+struct WsQuery {
+    /// Subscribes to the spectrum channel right away.
+    #[serde(default)]
+    spectrum: bool,
+}
+
+/// WebSocket speaking the JSON protocol in `protocol`. Clients subscribe to the channels they
+/// want; `?spectrum=true` subscribes to the spectrum channel with the options in the query string.
 async fn ws_route(
     req: HttpRequest,
     stream: web::Payload,
-    data: web::Data<AppState>
+    data: web::Data<AppState>,
+    query: web::Query<WsQuery>,
+    options: web::Query<SpectrumOptions>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    let ws_actor = WsActor {
-        detection_addr: data.processing_actor.clone(),
+    let options = options.into_inner();
+    if let Err(e) = options.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+    let services = WsServices {
+        pipelines: data.pipelines.clone(),
+        udp: data.udp_listener_actor.clone(),
+        history: data.history.clone(),
+        store: data.store.clone(),
+        config: data.config.clone(),
     };
+    let ws_actor = WsActor::new(services, query.spectrum.then_some(options));
     info!("Starting WS Actor");
     // WS Actor, unlike the other two, is started once we receive a request from the client.
     ws::start(ws_actor, &req, stream)
 }
+
+/// Packet and loss counters for every source that has sent samples to the UDP listener. Empty when
+/// replaying recordings.
+async fn sources_route(data: web::Data<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let stats = match &data.udp_listener_actor {
+        Some(udp) => udp.send(GetSourceStats).await.map_err(actix_web::error::ErrorInternalServerError)?,
+        None => Vec::new(),
+    };
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// Sensors with a running pipeline and when they last sent samples.
+async fn sensors_route(data: web::Data<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let sensors = data.pipelines.send(GetSensors).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(sensors))
+}
+
+/// Which sources feed which pipeline, and whether the UDP listener and each pipeline still answer.
+async fn pipelines_route(data: web::Data<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let topology = topology(data.udp_listener_actor.clone(), data.pipelines.clone()).await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(topology))
+}
+
+/// Reloads the UAV signature file in every pipeline without restarting. A rejected file leaves the
+/// current library in place and returns the reason.
+async fn reload_library_route(data: web::Data<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let result = data.pipelines.send(ReloadLibrary).await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(match result {
+        Ok(version) => HttpResponse::Ok().json(serde_json::json!({ "version": version })),
+        Err(error) => HttpResponse::UnprocessableEntity().json(serde_json::json!({ "error": error })),
+    })
+}
+
+/// Most recent detection, or 404 if no detection has run yet.
+async fn latest_detection_route(data: web::Data<AppState>) -> HttpResponse {
+    match data.history.lock().unwrap_or_else(PoisonError::into_inner).latest() {
+        Some(detection) => HttpResponse::Ok().json(detection),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "no detection yet" })),
+    }
+}
+
+/// Most recent detection of every band, e.g. of each band a sweeping sender visits.
+async fn bands_route(data: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.history.lock().unwrap_or_else(PoisonError::into_inner).latest_per_band())
+}
+
+/// Detections matching the `since`, `until`, `uav_type`, `min_score`, `sensor_id`, `center_freq`
+/// and `limit` query parameters, oldest first, see `query_detections`. Unknown detections older
+/// than the in-memory history are only kept with `store.keep_unknown`.
+async fn detections_route(
+    data: web::Data<AppState>,
+    query: web::Query<DetectionQuery>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(e) = query.validate() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": e })));
+    }
+
+    let detections = query_detections(data.store.clone(), data.history.clone(), query.into_inner()).await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(detections))
+}
@@ -0,0 +1,203 @@
+use log::warn;
+use serde::Serialize;
+use wire::packet::PacketHeader;
+
+/// A sequence number this far behind the last one means the sender was restarted. Senders count
+/// from 0, so going back to 0 is a restart too, however few datagrams were sent before.
+const RESTART_THRESHOLD: u64 = 1024;
+
+/// What to do with a datagram after looking at its sequence number.
+#[derive(Debug, PartialEq)]
+pub enum Arrival {
+    /// Contiguous with the previous datagram (or the first one from this source).
+    InOrder,
+    /// Samples were skipped, either because datagrams were lost or the sender dropped them.
+    Gap { missing_samples: u64 },
+    /// Arrived after a later datagram was already processed, so it's too late to use.
+    Late,
+    Duplicate,
+    /// The sender started counting from scratch, so the stream is not continuous.
+    Restart,
+}
+
+/// Per-source packet accounting, exposed to clients through `GET /api/sources`.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct SourceStats {
+    pub source: String,
+    /// Pipeline the samples go to, see `PacketHeader::sensor_id`.
+    pub sensor_id: String,
+    pub packets: u64,
+    pub samples: u64,
+    pub lost: u64,
+    pub late: u64,
+    pub duplicated: u64,
+    pub malformed: u64,
+    /// Datagrams dropped for a sample rate other than the server's.
+    pub wrong_sample_rate: u64,
+    pub last_header: Option<PacketHeader>,
+    #[serde(skip)]
+    next_timestamp: Option<u64>,
+}
+
+impl SourceStats {
+    pub fn new(source: String, sensor_id: String) -> Self {
+        Self { source, sensor_id, ..Default::default() }
+    }
+
+    /// Records a datagram carrying `samples` samples and classifies its arrival.
+    pub fn record(&mut self, header: &PacketHeader, samples: u64) -> Arrival {
+        if let Some(last) = &self.last_header {
+            if header.sequence == last.sequence {
+                self.duplicated += 1;
+                return Arrival::Duplicate;
+            }
+            if header.sequence == 0 || header.sequence + RESTART_THRESHOLD < last.sequence {
+                self.last_header = Some(header.clone());
+                self.next_timestamp = Some(header.timestamp + samples);
+                self.packets += 1;
+                self.samples += samples;
+                return Arrival::Restart;
+            }
+            if header.sequence < last.sequence {
+                // It was counted as lost when the gap was detected.
+                self.lost = self.lost.saturating_sub(1);
+                self.late += 1;
+                return Arrival::Late;
+            }
+            self.lost += header.sequence - last.sequence - 1;
+        }
+
+        // The sample clock also catches overflows on the sender, where no datagram was lost.
+        let missing_samples = self.next_timestamp
+            .map_or(0, |expected| header.timestamp.saturating_sub(expected));
+
+        self.packets += 1;
+        self.samples += samples;
+        self.last_header = Some(header.clone());
+        self.next_timestamp = Some(header.timestamp + samples);
+
+        if missing_samples > 0 {
+            Arrival::Gap { missing_samples }
+        } else {
+            Arrival::InOrder
+        }
+    }
+
+    /// Whether the datagram is at the server's `sample_rate`. Others would be analysed with the
+    /// wrong frequency axis and timing, so they're counted and dropped, with a warning for the
+    /// first one.
+    pub fn check_sample_rate(&mut self, header: &PacketHeader, sample_rate: u32) -> bool {
+        if header.sample_rate == sample_rate {
+            return true;
+        }
+        if self.wrong_sample_rate == 0 {
+            warn!(
+                "Dropping datagrams from {} at {} samples/s, the server expects {}",
+                self.source, header.sample_rate, sample_rate,
+            );
+        }
+        self.wrong_sample_rate += 1;
+        false
+    }
+
+    /// Records a datagram without a header, which can't be checked for loss.
+    pub fn record_raw(&mut self, samples: u64) {
+        self.packets += 1;
+        self.samples += samples;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::formats::SampleFormat;
+
+    fn header(sequence: u64, timestamp: u64) -> PacketHeader {
+        PacketHeader {
+            sequence,
+            timestamp,
+            center_freq: 2_400_000_000,
+            sample_rate: 62_500,
+            format: SampleFormat::Cs16,
+            sensor_id: None,
+        }
+    }
+
+    #[test]
+    fn record_in_order() {
+        let mut stats = SourceStats::default();
+        assert_eq!(stats.record(&header(0, 0), 100), Arrival::InOrder);
+        assert_eq!(stats.record(&header(1, 100), 100), Arrival::InOrder);
+        assert_eq!((stats.packets, stats.samples, stats.lost), (2, 200, 0));
+    }
+
+    #[test]
+    fn record_gap() {
+        let mut stats = SourceStats::default();
+        stats.record(&header(0, 0), 100);
+        assert_eq!(stats.record(&header(3, 300), 100), Arrival::Gap { missing_samples: 200 });
+        assert_eq!(stats.lost, 2);
+    }
+
+    #[test]
+    fn record_sender_overflow() {
+        // No datagram lost, but the sender skipped samples
+        let mut stats = SourceStats::default();
+        stats.record(&header(0, 0), 100);
+        assert_eq!(stats.record(&header(1, 150), 100), Arrival::Gap { missing_samples: 50 });
+        assert_eq!(stats.lost, 0);
+    }
+
+    #[test]
+    fn record_duplicate() {
+        let mut stats = SourceStats::default();
+        stats.record(&header(0, 0), 100);
+        stats.record(&header(1, 100), 100);
+        assert_eq!(stats.record(&header(1, 100), 100), Arrival::Duplicate);
+        assert_eq!((stats.packets, stats.duplicated), (2, 1));
+    }
+
+    #[test]
+    fn record_reorder() {
+        let mut stats = SourceStats::default();
+        stats.record(&header(1, 100), 100);
+        stats.record(&header(2, 200), 100);
+        stats.record(&header(4, 400), 100);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.record(&header(3, 300), 100), Arrival::Late);
+        assert_eq!((stats.lost, stats.late), (0, 1));
+        // The stream carries on from the latest datagram
+        assert_eq!(stats.record(&header(5, 500), 100), Arrival::InOrder);
+    }
+
+    #[test]
+    fn record_restart() {
+        let mut stats = SourceStats::default();
+        stats.record(&header(5000, 500_000), 100);
+        assert_eq!(stats.record(&header(10, 1000), 100), Arrival::Restart);
+        assert_eq!(stats.record(&header(11, 1100), 100), Arrival::InOrder);
+        assert_eq!(stats.lost, 0);
+    }
+
+    #[test]
+    fn record_early_restart() {
+        // Restarted before reaching RESTART_THRESHOLD datagrams
+        let mut stats = SourceStats::default();
+        for sequence in 0..10 {
+            stats.record(&header(sequence, sequence * 100), 100);
+        }
+        assert_eq!(stats.record(&header(0, 0), 100), Arrival::Restart);
+        assert_eq!(stats.record(&header(1, 100), 100), Arrival::InOrder);
+        assert_eq!((stats.packets, stats.lost, stats.late), (12, 0, 0));
+    }
+
+    #[test]
+    fn check_sample_rate() {
+        let mut stats = SourceStats::default();
+        assert!(stats.check_sample_rate(&header(0, 0), 62_500));
+        let faster = PacketHeader { sample_rate: 2_000_000, ..header(1, 100) };
+        assert!(!stats.check_sample_rate(&faster, 62_500));
+        assert!(!stats.check_sample_rate(&faster, 62_500));
+        assert_eq!((stats.wrong_sample_rate, stats.packets), (2, 0));
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use actix::prelude::*;
+use actix_web::rt::time::{timeout, Instant};
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::history::DetectionHistory;
+use crate::library::{LibraryEvent, ReferenceLibrary};
+use crate::processing::{
+    now_ms, AddSamples, Discontinuity, GetStatus, PipelineStatus, ProcessingActor, Retire, SetLibrary, Subscribe,
+    Unsubscribe,
+};
+use crate::recording::ClipWriter;
+use crate::store::StoreActor;
+use crate::udp::{GetListenerStatus, ListenerStatus, UdpListenerActor};
+use crate::websockets::{LibraryMsg, WsActor};
+
+/// Default of the most sensors processed at once.
+pub const MAX_SENSORS: usize = 16;
+/// Default of how long a sensor can go without sending samples before its pipeline is stopped (in ms).
+pub const SENSOR_IDLE_TIMEOUT_MS: u64 = 60_000;
+/// How often to look for idle pipelines.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the UDP listener and the pipelines have to answer before they are reported dead.
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One processing pipeline per sensor, started when the sensor first sends samples, so several
+/// SDRs or sites can feed the server without their samples ending up in the same window. The
+/// UDP listener forwards samples here once subscribed with `udp::Subscribe`, and every pipeline
+/// runs under a supervisor that restarts it if a detection fails. Pipelines of sensors that went
+/// quiet are stopped to make room for others. The registry is supervised too, and keeps its
+/// pipelines when restarted.
+pub struct PipelineRegistry {
+    /// Settings new pipelines are started with.
+    config: Config,
+    /// Loaded once and shared by every pipeline, reloaded when the signature file changes.
+    library: Arc<ReferenceLibrary>,
+    /// Modification time of the signature file when it was last read.
+    library_modified: Option<SystemTime>,
+    history: Arc<Mutex<DetectionHistory>>,
+    store: Option<Addr<StoreActor>>,
+    clip_writer: Option<Addr<ClipWriter>>,
+    pipelines: HashMap<String, Pipeline>,
+    /// WebSocket clients, subscribed to every pipeline including the ones started later.
+    subscribers: HashSet<Addr<WsActor>>,
+    max_sensors: usize,
+    /// `None` to keep idle pipelines running.
+    idle_timeout: Option<u64>,
+    /// Sensors turned away for going over `max_sensors`, so each is only logged once.
+    rejected: HashSet<String>,
+}
+
+struct Pipeline {
+    processing: Addr<ProcessingActor>,
+    /// When the pipeline was started (in ms since the Unix epoch).
+    started_at: u64,
+    /// When the sensor last sent samples (in ms since the Unix epoch).
+    last_seen: Option<u64>,
+    /// Started elsewhere, e.g. for a replay, and kept running when idle.
+    pinned: bool,
+}
+
+/// Body of `GET /api/pipelines`: what feeds what, and whether each part still answers.
+#[derive(Serialize, Debug)]
+pub struct Topology {
+    /// `None` when replaying recordings.
+    pub udp_listener: Option<ListenerTopology>,
+    pub pipelines: Vec<PipelineTopology>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListenerTopology {
+    pub alive: bool,
+    /// `None` if it didn't answer.
+    pub status: Option<ListenerStatus>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PipelineTopology {
+    pub sensor_id: String,
+    pub started_at: u64,
+    /// UDP peers whose samples go to this pipeline.
+    pub sources: Vec<String>,
+    pub alive: bool,
+    /// `None` if it didn't answer.
+    pub status: Option<PipelineStatus>,
+}
+
+/// Entry of `GET /api/sensors`.
+#[derive(Serialize, Clone, Debug)]
+pub struct SensorInfo {
+    pub sensor_id: String,
+    pub started_at: u64,
+    pub last_seen: Option<u64>,
+}
+
+impl PipelineRegistry {
+    pub fn new(
+        config: &Config,
+        library: Arc<ReferenceLibrary>,
+        history: Arc<Mutex<DetectionHistory>>,
+        store: Option<Addr<StoreActor>>,
+        clip_writer: Option<Addr<ClipWriter>>,
+    ) -> Self {
+        Self {
+            config: config.clone(),
+            library,
+            library_modified: modified_time(&config.library.path),
+            history,
+            store,
+            clip_writer,
+            pipelines: HashMap::new(),
+            subscribers: HashSet::new(),
+            max_sensors: config.processing.max_sensors,
+            idle_timeout: (config.processing.sensor_idle_timeout_ms > 0).then_some(config.processing.sensor_idle_timeout_ms),
+            rejected: HashSet::new(),
+        }
+    }
+
+    /// Registers a pipeline started elsewhere, e.g. the one a replay feeds.
+    pub fn with_pipeline(mut self, sensor_id: &str, processing: Addr<ProcessingActor>) -> Self {
+        self.pipelines.insert(sensor_id.into(), Pipeline { processing, started_at: now_ms(), last_seen: None, pinned: true });
+        self
+    }
+
+    /// Pipeline of `sensor_id`, started on first use. `None` if there are already `max_sensors`.
+    fn pipeline(&mut self, sensor_id: &str) -> Option<&mut Pipeline> {
+        if !self.pipelines.contains_key(sensor_id) {
+            if self.pipelines.len() >= self.max_sensors {
+                if self.rejected.insert(sensor_id.into()) {
+                    warn!("Ignoring sensor {}, already processing {} sensors", sensor_id, self.max_sensors);
+                }
+                return None;
+            }
+
+            info!("Starting the pipeline of sensor {}", sensor_id);
+            let pipeline = ProcessingActor::new(
+                &self.config,
+                sensor_id,
+                self.library.clone(),
+                self.history.clone(),
+                self.store.clone(),
+                self.clip_writer.clone(),
+            );
+            let processing = Supervisor::start(|_| pipeline);
+            for subscriber in &self.subscribers {
+                processing.do_send(Subscribe(subscriber.clone()));
+            }
+            self.rejected.remove(sensor_id);
+            let pipeline = Pipeline { processing, started_at: now_ms(), last_seen: None, pinned: false };
+            self.pipelines.insert(sensor_id.into(), pipeline);
+        }
+        self.pipelines.get_mut(sensor_id)
+    }
+
+    /// Stops the pipelines of sensors that sent nothing for `idle_timeout`, clearing their alarms.
+    fn evict_idle(&mut self, now: u64) {
+        let Some(idle_timeout) = self.idle_timeout else { return };
+        self.pipelines.retain(|sensor_id, pipeline| {
+            let idle = !pipeline.pinned
+                && now.saturating_sub(pipeline.last_seen.unwrap_or(pipeline.started_at)) >= idle_timeout;
+            if idle {
+                info!("Stopping the pipeline of sensor {}, idle for {} ms", sensor_id, idle_timeout);
+                pipeline.processing.do_send(Retire);
+            }
+            !idle
+        });
+    }
+
+    /// Loads the signature file again and hands it to every pipeline if all its entries are valid.
+    /// The current library is kept otherwise, so a typo in one entry doesn't drop a signature in
+    /// use. Either way subscribers are told about the outcome.
+    fn reload_library(&mut self) -> Result<String, String> {
+        let path = &self.config.library.path;
+        self.library_modified = modified_time(path);
+
+        let loaded = ReferenceLibrary::load(path).map_err(|e| e.to_string()).and_then(|library| {
+            match library.skipped() {
+                [] => Ok(library),
+                errors => Err(errors.join("; ")),
+            }
+        });
+        let (result, event) = match loaded {
+            Ok(library) => {
+                info!("Reloaded {} UAV signatures, version {}", library.len(), library.version());
+                self.library = Arc::new(library);
+                for pipeline in self.pipelines.values() {
+                    pipeline.processing.do_send(SetLibrary(self.library.clone()));
+                }
+                let event = LibraryEvent::LibraryReloaded {
+                    version: self.library.version().into(),
+                    signatures: self.library.len(),
+                };
+                (Ok(self.library.version().into()), event)
+            }
+            Err(e) => {
+                error!("Rejected UAV library {}: {}. Keeping version {}", path.display(), e, self.library.version());
+                let event = LibraryEvent::LibraryReloadFailed {
+                    version: self.library.version().into(),
+                    error: e.clone(),
+                };
+                (Err(e), event)
+            }
+        };
+
+        for subscriber in &self.subscribers {
+            subscriber.do_send(LibraryMsg(event.clone()));
+        }
+        result
+    }
+
+    fn sensors(&self) -> Vec<SensorInfo> {
+        let mut sensors: Vec<SensorInfo> = self.pipelines.iter()
+            .map(|(sensor_id, pipeline)| SensorInfo {
+                sensor_id: sensor_id.clone(),
+                started_at: pipeline.started_at,
+                last_seen: pipeline.last_seen,
+            })
+            .collect();
+        sensors.sort_by(|a, b| a.sensor_id.cmp(&b.sensor_id));
+        sensors
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl Actor for PipelineRegistry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Duration::from_millis(self.config.library.poll_interval_ms), |act, _| {
+            let modified = modified_time(&act.config.library.path);
+            if modified.is_some() && modified != act.library_modified {
+                let _ = act.reload_library();
+            }
+        });
+
+        if self.idle_timeout.is_some() {
+            ctx.run_interval(IDLE_CHECK_INTERVAL, |act, _| act.evict_idle(now_ms()));
+        }
+    }
+}
+
+/// Samples of one sensor, for its pipeline.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SensorSamples {
+    pub sensor_id: String,
+    pub samples: AddSamples,
+}
+
+/// Gap in the samples of one sensor, for its pipeline.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SensorGap {
+    pub sensor_id: String,
+    pub gap: Discontinuity,
+}
+
+/// Sensors with a running pipeline, sorted by id.
+#[derive(Message)]
+#[rtype(result = "Vec<SensorInfo>")]
+pub struct GetSensors;
+
+/// Reloads the UAV signature file. Returns the new library version, or why it was rejected.
+#[derive(Message)]
+#[rtype(result = "Result<String, String>")]
+pub struct ReloadLibrary;
+
+/// Every pipeline with its address, sorted by sensor id.
+#[derive(Message)]
+#[rtype(result = "Vec<(SensorInfo, Addr<ProcessingActor>)>")]
+struct GetPipelines;
+
+impl Supervised for PipelineRegistry {
+    fn restarting(&mut self, _: &mut Self::Context) {
+        warn!("Restarting the pipeline registry with its {} pipelines", self.pipelines.len());
+    }
+}
+
+impl PipelineRegistry {
+    /// Runs `f`, but a panic stops the registry instead of taking the server down, and its
+    /// supervisor restarts it.
+    fn guarded(&mut self, ctx: &mut Context<Self>, f: impl FnOnce(&mut Self)) {
+        if panic::catch_unwind(AssertUnwindSafe(|| f(self))).is_err() {
+            error!("The pipeline registry panicked, restarting it");
+            ctx.stop();
+        }
+    }
+}
+
+impl Handler<SensorSamples> for PipelineRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: SensorSamples, ctx: &mut Self::Context) {
+        self.guarded(ctx, |registry| {
+            if let Some(pipeline) = registry.pipeline(&msg.sensor_id) {
+                pipeline.last_seen = Some(now_ms());
+                pipeline.processing.do_send(msg.samples);
+            }
+        });
+    }
+}
+
+impl Handler<SensorGap> for PipelineRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: SensorGap, ctx: &mut Self::Context) {
+        self.guarded(ctx, |registry| {
+            if let Some(pipeline) = registry.pipelines.get(&msg.sensor_id) {
+                pipeline.processing.do_send(msg.gap);
+            }
+        });
+    }
+}
+
+impl Handler<Subscribe> for PipelineRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) {
+        for pipeline in self.pipelines.values() {
+            pipeline.processing.do_send(Subscribe(msg.0.clone()));
+        }
+        self.subscribers.insert(msg.0);
+    }
+}
+
+impl Handler<Unsubscribe> for PipelineRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, _: &mut Self::Context) {
+        for pipeline in self.pipelines.values() {
+            pipeline.processing.do_send(Unsubscribe(msg.0.clone()));
+        }
+        self.subscribers.remove(&msg.0);
+    }
+}
+
+impl Handler<ReloadLibrary> for PipelineRegistry {
+    type Result = Result<String, String>;
+
+    fn handle(&mut self, _: ReloadLibrary, _: &mut Self::Context) -> Self::Result {
+        self.reload_library()
+    }
+}
+
+impl Handler<GetSensors> for PipelineRegistry {
+    type Result = Vec<SensorInfo>;
+
+    fn handle(&mut self, _: GetSensors, _: &mut Self::Context) -> Self::Result {
+        self.sensors()
+    }
+}
+
+impl Handler<GetPipelines> for PipelineRegistry {
+    type Result = Vec<(SensorInfo, Addr<ProcessingActor>)>;
+
+    fn handle(&mut self, _: GetPipelines, _: &mut Self::Context) -> Self::Result {
+        self.sensors().into_iter()
+            .map(|sensor| {
+                let processing = self.pipelines[&sensor.sensor_id].processing.clone();
+                (sensor, processing)
+            })
+            .collect()
+    }
+}
+
+/// Asks the UDP listener and every pipeline how they are doing. Parts that don't answer within
+/// `LIVENESS_TIMEOUT` are reported dead rather than holding up the answer.
+pub async fn topology(
+    udp: Option<Addr<UdpListenerActor>>,
+    registry: Addr<PipelineRegistry>,
+) -> Result<Topology, MailboxError> {
+    let deadline = Instant::now() + LIVENESS_TIMEOUT;
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+
+    // Ask everyone first, so the answers are awaited side by side
+    let listener = udp.map(|udp| udp.send(GetListenerStatus));
+    let pipelines = registry.send(GetPipelines).await?;
+    let requests: Vec<_> = pipelines.into_iter()
+        .map(|(sensor, processing)| (sensor, processing.send(GetStatus)))
+        .collect();
+
+    let udp_listener = match listener {
+        Some(request) => {
+            let status = timeout(remaining(), request).await.ok().and_then(Result::ok);
+            Some(ListenerTopology { alive: status.is_some(), status })
+        }
+        None => None,
+    };
+    let sources = |sensor_id: &str| -> Vec<String> {
+        udp_listener.as_ref()
+            .and_then(|listener| listener.status.as_ref())
+            .map(|status| status.sources.iter()
+                .filter(|source| source.sensor_id == sensor_id)
+                .map(|source| source.source.clone())
+                .collect())
+            .unwrap_or_default()
+    };
+
+    let mut pipelines = Vec::new();
+    for (sensor, request) in requests {
+        let status = timeout(remaining(), request).await.ok().and_then(Result::ok);
+        pipelines.push(PipelineTopology {
+            sources: sources(&sensor.sensor_id),
+            sensor_id: sensor.sensor_id,
+            started_at: sensor.started_at,
+            alive: status.is_some(),
+            status,
+        });
+    }
+    Ok(Topology { udp_listener, pipelines })
+}
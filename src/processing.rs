@@ -1,81 +1,491 @@
 use actix::prelude::*;
 use log::{error, warn, info};
+use num_complex::Complex;
 use serde::{Serialize, Deserialize};
 use spectrum_analyzer::FrequencySpectrum;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::collections::{BTreeMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::{Duration, SystemTime};
 use std::{collections::VecDeque};
-use std::sync::{Arc, Mutex};
 
-use crate::websockets::{WsActor, InfoMsg};
-use crate::utils::{classify_uav, compute_spectrum, wav_to_signal};
-
-/// Window size for our signal analysis
-pub const WINDOW_SIZE: usize = 1000;
-/// How often to compute and send drone detection probability (in ms).
+use crate::alarm::{AlarmEvent, AlarmTracker};
+use crate::burst::{BurstDetector, BurstFeatures};
+use crate::cfar::{CfarDetector, CfarResult, Emission};
+use crate::classifier::{build_classifier, Classifier, Hypothesis, Observation};
+use crate::config::Config;
+use crate::history::DetectionHistory;
+use crate::hopping::{HopEstimate, HopTracker};
+use crate::store::{StoreActor, StoreDetection};
+use crate::library::ReferenceLibrary;
+use crate::spectrum::SpectrumFrame;
+use crate::websockets::{WsActor, InfoMsg, AlarmMsg, SpectrumMsg};
+use crate::psd::PsdEstimator;
+use crate::recording::{ClipWriter, Recorder};
+
+/// By default the window holds the samples of this many detection intervals, so a tick that
+/// runs late doesn't lose any.
+pub const WINDOW_INTERVALS: u64 = 2;
+/// Default of how often to compute and send drone detection probability (in ms).
 pub const DETECTION_INTERVAL_MS: u64 = 500;
-/// How many samples are collected per second.
+/// Default of how many samples are collected per second.
 pub const SAMPLE_RATE: u32 = 62_500; // This will probably be much higher
-// Expected dominant frequency emitted by the drone. (These are the ones from the drill)
-// pub const DRONE_FREQS: &[f32] = &[
-//     10_000.0, 20_000.0, 30_000.0, 40_000.0, 51_000.0, 61_000.0, 71_0000.0, 81_000.0,
-//     92_000.0, 102_000.0, 112_000.0, 122_000.0, 132_000.0
-// ];
+
+/// Default of the most center frequencies analysed side by side.
+pub const MAX_BANDS: usize = 16;
+
+/// Default identifier of this sensor.
+pub const SENSOR_ID: &str = "sensor-1";
 
 pub const UAV_DATA_PATH: &str = "drone_types.json";
+/// Default of how often to check the UAV signature file for changes (in ms).
+pub const LIBRARY_POLL_INTERVAL_MS: u64 = 2000;
+
+/// How to handle gaps in the sample stream reported by the UDP listener.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GapPolicy {
+    /// Replace the missing samples with zeros, keeping the timing of the window intact.
+    ZeroFill,
+    /// Throw away the samples collected so far.
+    Reset,
+}
+
+/// Samples and detection state of one center frequency. A sweeping sender visits several bands in
+/// turn, and each one is analysed on its own so their spectra, hops and alarms don't mix.
+struct Band {
+    signal_window: SignalWindow,
+    psd: PsdEstimator,
+    cfar: CfarDetector,
+    hops: HopTracker,
+    bursts: BurstDetector,
+    alarms: AlarmTracker,
+    /// Samples were added since the last detection.
+    fed: bool,
+    /// Samples received by the actor when this band last got some, to evict the stalest band.
+    last_fed: u64,
+}
+
+impl Band {
+    fn new(config: &Config, sensor_id: &str, center_freq: Option<u64>) -> Self {
+        Self {
+            signal_window: SignalWindow::new(config.processing.window_capacity()),
+            psd: PsdEstimator::new(
+                config.processing.fft_size,
+                config.processing.overlap,
+                config.processing.window_function,
+                config.processing.averaging,
+                config.processing.sample_rate,
+            ),
+            cfar: CfarDetector::new(&config.cfar),
+            hops: HopTracker::new(&config.hopping, config.processing.sample_rate),
+            bursts: BurstDetector::new(&config.bursts, config.processing.sample_rate),
+            alarms: AlarmTracker::new(&config.alarm, sensor_id, center_freq),
+            fed: false,
+            last_fed: 0,
+        }
+    }
 
-pub const BANDWIDTH: f32 = 0.0; // TODO define
+    fn get_samples(&self) -> Vec<Complex<f32>> {
+        self.signal_window.samples.clone().into()
+    }
+
+    /// Forgets the samples of the previous visit, which aren't contiguous with the next ones.
+    fn restart(&mut self) {
+        self.signal_window.samples.clear();
+        self.hops.reset();
+        self.bursts.reset();
+    }
+}
 
 pub struct ProcessingActor {
-    signal_window: SignalWindow,
+    /// Keyed by center frequency, `None` for samples sent without a header.
+    bands: BTreeMap<Option<u64>, Band>,
+    /// Band of the latest samples.
+    current_band: Option<u64>,
+    max_bands: usize,
+    /// Samples added so far, across all bands.
+    samples_received: u64,
+    /// Settings new bands are set up with.
+    config: Config,
     subscribers: HashSet<Addr<WsActor>>,
+    classifiers: Vec<Box<dyn Classifier>>,
+    /// Shared by every pipeline, swapped by the registry when the signature file changes.
+    library: Arc<ReferenceLibrary>,
+    sample_rate: u32,
+    detection_interval: Duration,
+    gap_policy: GapPolicy,
+    /// Recent detections served by the REST API. This actor is the only writer.
+    history: Arc<Mutex<DetectionHistory>>,
+    store: Option<Addr<StoreActor>>,
+    sensor_id: String,
+    config_version: String,
+    /// Detections are run on `RunDetection` messages instead of every `detection_interval`.
+    external_clock: bool,
+    /// Captures the samples around raised alarms, if recording is enabled.
+    recorder: Option<Recorder>,
+    /// Times the supervisor restarted this pipeline after a failed detection.
+    restarts: u32,
+    /// Stopped for good by `Retire`, so it isn't started over if the supervisor restarts it.
+    retired: bool,
+    /// Samples that overflowed a band's window before they could be analysed.
+    dropped_samples: u64,
+    /// Zeros standing in for samples that never arrived, see `GapPolicy::ZeroFill`.
+    zero_filled_samples: u64,
+    /// When samples last arrived and a detection last succeeded (in ms since the Unix epoch).
+    last_samples_at: Option<u64>,
+    last_detection_at: Option<u64>,
+}
+
+/// Liveness of a pipeline, see `GET /api/pipelines`.
+#[derive(Serialize, Clone, Debug)]
+pub struct PipelineStatus {
+    pub sensor_id: String,
+    /// Center frequencies being analysed, `null` for samples sent without a header.
+    pub bands: Vec<Option<u64>>,
+    /// WebSocket clients receiving its results.
+    pub subscribers: usize,
+    pub restarts: u32,
+    /// Samples dropped because they arrived faster than the detections analysed them, see
+    /// `processing.window_size`.
+    pub dropped_samples: u64,
+    /// Zeros inserted for samples lost on the way, see `processing.gap_policy`.
+    pub zero_filled_samples: u64,
+    /// Detections are driven by a replay rather than a timer.
+    pub external_clock: bool,
+    pub last_samples_at: Option<u64>,
+    pub last_detection_at: Option<u64>,
 }
 
 impl ProcessingActor {
-    pub fn new() -> Self {
+    /// Pipeline for the samples of the sensor `sensor_id`.
+    pub fn new(
+        config: &Config,
+        sensor_id: &str,
+        library: Arc<ReferenceLibrary>,
+        history: Arc<Mutex<DetectionHistory>>,
+        store: Option<Addr<StoreActor>>,
+        clip_writer: Option<Addr<ClipWriter>>,
+    ) -> Self {
         Self {
-            signal_window: SignalWindow::new(WINDOW_SIZE),
+            bands: BTreeMap::new(),
+            current_band: None,
+            max_bands: config.processing.max_bands,
+            samples_received: 0,
+            config: config.clone(),
             subscribers: HashSet::new(),
+            classifiers: config.processing.classifiers.iter().map(build_classifier).collect(),
+            library,
+            sample_rate: config.processing.sample_rate,
+            detection_interval: Duration::from_millis(config.processing.detection_interval_ms),
+            gap_policy: config.processing.gap_policy,
+            history,
+            store,
+            sensor_id: sensor_id.into(),
+            config_version: config.version(),
+            external_clock: false,
+            recorder: clip_writer.map(|writer| {
+                Recorder::new(&config.recording, sensor_id, config.processing.sample_rate, writer)
+            }),
+            restarts: 0,
+            retired: false,
+            dropped_samples: 0,
+            zero_filled_samples: 0,
+            last_samples_at: None,
+            last_detection_at: None,
         }
     }
 
-    pub fn get_samples(&self) -> Vec<f32> {
-        self.signal_window.samples.clone().into()
+    /// Leaves running detections to `RunDetection` messages, so a replay can go faster than real
+    /// time. The replay sends one detection interval of samples before each, which the window has
+    /// room for unless `processing.window_size` is set lower.
+    pub fn with_external_clock(mut self) -> Self {
+        self.external_clock = true;
+        self
     }
 
-    pub fn clear_samples(&mut self) {
-        self.signal_window.samples.clear();
+    /// State of the band at `center_freq`, set up on first use. Beyond `max_bands` the band that
+    /// went the longest without samples makes room, and its active alarms are cleared.
+    fn band(&mut self, center_freq: Option<u64>) -> &mut Band {
+        if !self.bands.contains_key(&center_freq) && self.bands.len() >= self.max_bands {
+            let stalest = self.bands.iter().min_by_key(|(_, band)| band.last_fed).map(|(freq, _)| *freq);
+            if let Some(mut band) = stalest.and_then(|stalest| self.bands.remove(&stalest)) {
+                warn!("More than {} bands, forgetting the one at {:?} Hz", self.max_bands, stalest.flatten());
+                let events = band.alarms.clear(self.clock());
+                self.send_alarms(events);
+            }
+        }
+        let (config, sensor_id) = (&self.config, &self.sensor_id);
+        self.bands.entry(center_freq).or_insert_with(|| {
+            info!("Analysing a new band of {} at {:?} Hz", sensor_id, center_freq);
+            Band::new(config, sensor_id, center_freq)
+        })
+    }
+
+    /// Time of events that don't come with a detection, in ms since the Unix epoch. Under an
+    /// external clock that's the time of the last detection, as the wall clock isn't the replay's.
+    fn clock(&self) -> u64 {
+        match self.last_detection_at {
+            Some(last_detection_at) if self.external_clock => last_detection_at,
+            _ => now_ms(),
+        }
+    }
+}
+
+impl ProcessingActor {
+    /// Classifies the spectrum, unless CFAR found nothing in it that stands out of the noise.
+    fn detect(
+        &self,
+        spectrum: &FrequencySpectrum,
+        cfar: CfarResult,
+        hopping: Option<HopEstimate>,
+        bursts: Option<BurstFeatures>,
+        center_freq: Option<u64>,
+        timestamp: u64,
+    ) -> DetectionInfo {
+        // Classify detected signal
+        let observation = Observation { spectrum, bursts: bursts.as_ref(), hopping: hopping.as_ref() };
+        let hypotheses: Vec<Hypothesis> = if cfar.emissions.is_empty() {
+            Vec::new()
+        } else {
+            self.classifiers.iter()
+                .filter_map(|classifier| classifier.classify(&observation, &self.library).into_iter().next())
+                .collect()
+        };
+
+        // The first classifier with signatures to go by decides
+        let decider = self.classifiers.iter().find(|classifier| classifier.has_signatures(&self.library));
+        let best = decider.and_then(|decider| {
+            hypotheses.iter().find(|hypothesis| hypothesis.classifier == decider.name())
+        });
+        let (uav_type, score) = match best {
+            Some(best) if best.score > 0.0 => {
+                (best.uav_type.clone(), best.score)
+            }
+            _ => ("Unknown".into(), 0.0),
+        };
+
+        DetectionInfo {
+            score,
+            timestamp,
+            uav_type,
+            hypotheses,
+            snr_db: cfar.emissions.iter().map(|emission| emission.snr_db).reduce(f32::max),
+            noise_floor_dbfs: cfar.noise_floor_dbfs,
+            emissions: cfar.emissions,
+            hopping,
+            bursts,
+            sensor_id: self.sensor_id.clone(),
+            center_freq,
+            config_version: self.config_version.clone(),
+            library_version: self.library.version().into(),
+        }
+    }
+
+    /// Analyses the samples every band collected since the last run and sends the results to
+    /// everyone interested. `now` is the time of the detection, in ms since the Unix epoch.
+    fn run_detection(&mut self, now: u64) -> Vec<DetectionInfo> {
+        let fed: Vec<Option<u64>> = self.bands.iter()
+            .filter(|(_, band)| band.fed)
+            .map(|(center_freq, _)| *center_freq)
+            .collect();
+
+        // Bands a sweep hasn't come back to yet keep their alarms as they are, but once the
+        // samples stop altogether every alarm has to be able to clear.
+        if fed.is_empty() {
+            let events: Vec<AlarmEvent> = self.bands.values_mut().flat_map(|band| band.alarms.tick(now)).collect();
+            self.send_alarms(events);
+            return Vec::new();
+        }
+
+        fed.into_iter()
+            .filter_map(|center_freq| {
+                let mut band = self.bands.remove(&center_freq)?;
+                let detection_info = self.run_band_detection(&mut band, center_freq, now);
+                self.bands.insert(center_freq, band);
+                detection_info
+            })
+            .collect()
+    }
+
+    /// Runs `f`, but a panic stops the actor instead of taking the server down, and its supervisor
+    /// restarts it with fresh band state. `None` if `f` panicked.
+    fn guarded<R>(&mut self, ctx: &mut Context<Self>, what: &str, f: impl FnOnce(&mut Self) -> R) -> Option<R> {
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                error!("{} of sensor {} panicked, restarting its pipeline", what, self.sensor_id);
+                ctx.stop();
+                None
+            }
+        }
+    }
+
+    fn run_detection_guarded(&mut self, now: u64, ctx: &mut Context<Self>) -> Vec<DetectionInfo> {
+        let detections = self.guarded(ctx, "Detection", |act| act.run_detection(now)).unwrap_or_default();
+        if !detections.is_empty() {
+            self.last_detection_at = Some(now);
+        }
+        detections
+    }
+
+    fn run_band_detection(&mut self, band: &mut Band, center_freq: Option<u64>, now: u64) -> Option<DetectionInfo> {
+        band.fed = false;
+        let overflow = band.signal_window.take_overflow();
+        if overflow > 0 {
+            warn!(
+                "Dropped {} samples of {} at {:?} Hz that arrived faster than they were analysed, \
+                 consider raising processing.window_size",
+                overflow, self.sensor_id, center_freq,
+            );
+            self.dropped_samples += overflow;
+        }
+        let samples = band.get_samples();
+        let estimate = band.psd.estimate(&samples).and_then(|psd| {
+            band.signal_window.drain(psd.consumed);
+            let spectrum = psd.to_spectrum()?;
+            Ok((psd, spectrum))
+        });
+
+        match estimate {
+            Ok((psd, spectrum)) => {
+                let frame = Arc::new(SpectrumFrame {
+                    sensor_id: self.sensor_id.clone(),
+                    timestamp: now,
+                    center_freq,
+                    sample_rate: self.sample_rate,
+                    dbfs: psd.to_dbfs(),
+                });
+                for subscriber in &self.subscribers {
+                    subscriber.do_send(SpectrumMsg(frame.clone()));
+                }
+
+                let cfar = band.cfar.detect(&psd);
+                let hopping = band.hops.estimate();
+                let bursts = band.bursts.features();
+                let detection_info = self.detect(&spectrum, cfar, hopping, bursts, center_freq, now);
+
+                // Notify all subscribers
+                for subscriber in &self.subscribers {
+                    subscriber.do_send(InfoMsg(detection_info.clone()));
+                }
+
+                info!("Detection info sent to all subscribers: {:?}", detection_info);
+                let events = band.alarms.update(&detection_info);
+                self.send_alarms(events);
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.annotate(&detection_info, samples.len());
+                }
+                if let Some(store) = &self.store {
+                    store.do_send(StoreDetection(detection_info.clone()));
+                }
+                self.history.lock().unwrap_or_else(PoisonError::into_inner).push(detection_info.clone());
+                Some(detection_info)
+            },
+            Err(err) => {
+                warn!("{} at {:?} Hz. No problem, retrying on next interval", err, center_freq);
+                // Alarms still have to clear if the band's samples never add up to a spectrum
+                let events = band.alarms.tick(now);
+                self.send_alarms(events);
+                None
+            }
+        }
+    }
+
+    /// Adds samples to the band they were received on.
+    fn add_samples(&mut self, msg: AddSamples) {
+        self.last_samples_at = Some(now_ms());
+        let center_freq = msg.center_freq.or(self.current_band);
+        let retuned = center_freq != self.current_band;
+        self.current_band = center_freq;
+        self.samples_received += msg.samples.len() as u64;
+
+        let samples_received = self.samples_received;
+        let band = self.band(center_freq);
+        if retuned {
+            band.restart();
+        }
+        band.fed = true;
+        band.last_fed = samples_received;
+        band.hops.process(&msg.samples);
+        band.bursts.process(&msg.samples);
+        band.signal_window.add_samples(&msg.samples);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.push(&msg.samples, center_freq);
+        }
+    }
+
+    /// Keeps a gap in the samples out of the band's window and estimates.
+    fn discontinuity(&mut self, msg: Discontinuity) {
+        let gap_policy = self.gap_policy;
+        if let Some(band) = self.bands.get_mut(&msg.center_freq.or(self.current_band)) {
+            match (gap_policy, msg.missing_samples) {
+                (GapPolicy::ZeroFill, Some(missing)) => {
+                    self.zero_filled_samples += band.signal_window.fill_gap(missing);
+                }
+                _ => band.signal_window.samples.clear(),
+            }
+            band.hops.reset();
+            band.bursts.reset();
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.gap(msg.missing_samples.filter(|_| matches!(gap_policy, GapPolicy::ZeroFill)));
+        }
+    }
+
+    /// Drops every band, clearing their active alarms at `now`.
+    fn clear_bands(&mut self, now: u64) {
+        let events: Vec<AlarmEvent> = self.bands.values_mut().flat_map(|band| band.alarms.clear(now)).collect();
+        self.send_alarms(events);
+        self.bands.clear();
+        self.current_band = None;
     }
+
+    fn send_alarms(&mut self, events: Vec<AlarmEvent>) {
+        for event in events {
+            if !matches!(event, AlarmEvent::Updated { .. }) {
+                info!("{:?}", event);
+            }
+            if let (AlarmEvent::Raised { uav_type, raised_at, center_freq, .. }, Some(recorder)) = (&event, &mut self.recorder) {
+                recorder.trigger(uav_type, *raised_at, *center_freq);
+            }
+            for subscriber in &self.subscribers {
+                subscriber.do_send(AlarmMsg(event.clone()));
+            }
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_millis() as u64
 }
 
 impl Actor for ProcessingActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(Duration::from_millis(DETECTION_INTERVAL_MS), |act, _| {
-            match compute_spectrum(&act.get_samples(), SAMPLE_RATE) {
-                Ok(spectrum) => {
-                    let detection_info = DetectionInfo::calculate(spectrum, UAV_DATA_PATH, BANDWIDTH);
-
-                    // Notify all subscribers
-                    for subscriber in &act.subscribers {
-                        subscriber.do_send(InfoMsg(detection_info.clone()));
-                    }
-
-                    info!("Detection info sent to all subscribers: {:?}", detection_info);
-
-                    act.clear_samples();
-                },
-                Err(err) => {
-                    warn!("{:?}. No problem, retrying on next interval", err);
-                }
-            }
-        });
+        if !self.external_clock && !self.retired {
+            ctx.run_interval(self.detection_interval, |act, ctx| {
+                act.run_detection_guarded(now_ms(), ctx);
+            });
+        }
     }
 }
 
 
+impl Supervised for ProcessingActor {
+    // The failed handler may have left a band half updated, so start them all over. Subscribers
+    // and the library are kept.
+    fn restarting(&mut self, _: &mut Self::Context) {
+        self.restarts += 1;
+        self.clear_bands(self.clock());
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Subscribe(pub Addr<WsActor>);
@@ -88,9 +498,48 @@ pub struct Unsubscribe(pub Addr<WsActor>);
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct AddSamples {
-    pub samples: Vec<f32>
+    pub samples: Vec<Complex<f32>>,
+    /// From the packet header, if the sender included one.
+    pub center_freq: Option<u64>,
 }
 
+/// Swaps in a newly loaded UAV signature library.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetLibrary(pub Arc<ReferenceLibrary>);
+
+/// Stops the pipeline for good, clearing its active alarms. The sender of this drops its address
+/// right after, so the supervisor lets it go instead of restarting it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Retire;
+
+/// Runs a detection on the samples each band got since the last one, as if it happened at
+/// `timestamp` (in ms since the Unix epoch). Returns a detection per band that had enough samples.
+#[derive(Message)]
+#[rtype(result = "Vec<DetectionInfo>")]
+pub struct RunDetection {
+    pub timestamp: u64,
+}
+
+/// Sent when the sample stream is not continuous, so the window doesn't splice unrelated data.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Discontinuity {
+    /// Number of samples skipped, or `None` if unknown (e.g. the sender was restarted).
+    pub missing_samples: Option<u64>,
+    /// Band of the samples after the gap, if known. The latest band otherwise.
+    pub center_freq: Option<u64>,
+}
+
+/// Starts over with no band state at all, e.g. between the recordings of a replay so the noise
+/// estimates and alarms of one don't carry over to the next. Active alarms are cleared at
+/// `timestamp` (in ms since the Unix epoch).
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Reset {
+    pub timestamp: u64,
+}
 
 impl Handler<Subscribe> for ProcessingActor {
     type Result = ();
@@ -110,21 +559,72 @@ impl Handler<Unsubscribe> for ProcessingActor {
 
 impl Handler<AddSamples> for ProcessingActor {
     type Result = ();
-    fn handle(&mut self, msg: AddSamples, _: &mut Self::Context) {
-        self.signal_window.add_samples(&msg.samples);
+    fn handle(&mut self, msg: AddSamples, ctx: &mut Self::Context) {
+        self.guarded(ctx, "Adding samples", |act| act.add_samples(msg));
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct UAVInfo {
-    name: String,
-    audio_path: String,
+impl Handler<SetLibrary> for ProcessingActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetLibrary, _: &mut Self::Context) {
+        self.library = msg.0;
+    }
 }
 
-impl UAVInfo {
-    pub fn load_uav_reference_data(file_path: &str) -> Vec<Self> {
-        let file_content = std::fs::read_to_string(file_path).expect("Failed to read UAV data file");
-        serde_json::from_str(&file_content).expect("Failed to parse UAV JSON data")
+impl Handler<RunDetection> for ProcessingActor {
+    type Result = Vec<DetectionInfo>;
+
+    fn handle(&mut self, msg: RunDetection, ctx: &mut Self::Context) -> Self::Result {
+        self.run_detection_guarded(msg.timestamp, ctx)
+    }
+}
+
+impl Handler<Discontinuity> for ProcessingActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Discontinuity, ctx: &mut Self::Context) {
+        self.guarded(ctx, "Handling a gap", |act| act.discontinuity(msg));
+    }
+}
+
+impl Handler<Reset> for ProcessingActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Reset, _: &mut Self::Context) {
+        self.clear_bands(msg.timestamp);
+    }
+}
+
+impl Handler<Retire> for ProcessingActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Retire, ctx: &mut Self::Context) {
+        self.clear_bands(self.clock());
+        self.retired = true;
+        ctx.stop();
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "PipelineStatus")]
+pub struct GetStatus;
+
+impl Handler<GetStatus> for ProcessingActor {
+    type Result = MessageResult<GetStatus>;
+
+    fn handle(&mut self, _: GetStatus, _: &mut Self::Context) -> Self::Result {
+        MessageResult(PipelineStatus {
+            sensor_id: self.sensor_id.clone(),
+            bands: self.bands.keys().copied().collect(),
+            subscribers: self.subscribers.len(),
+            restarts: self.restarts,
+            dropped_samples: self.dropped_samples,
+            zero_filled_samples: self.zero_filled_samples,
+            external_clock: self.external_clock,
+            last_samples_at: self.last_samples_at,
+            last_detection_at: self.last_detection_at,
+        })
     }
 }
 
@@ -135,105 +635,110 @@ pub struct DetectionInfo {
     score: f32,
     timestamp: u64,
     uav_type: String,
+    /// Best hypothesis of every classifier that ran, the first one being the reported result.
+    hypotheses: Vec<Hypothesis>,
+    /// SNR of the strongest emission (in dB), `None` if there was none.
+    snr_db: Option<f32>,
+    noise_floor_dbfs: f32,
+    /// Emissions CFAR found in the window. The classifiers only run if there is at least one.
+    emissions: Vec<Emission>,
+    /// Hop pattern of the recent bursts, if they form one.
+    hopping: Option<HopEstimate>,
+    /// Time-domain features of the recent bursts, if there were any.
+    bursts: Option<BurstFeatures>,
+    sensor_id: String,
+    /// Center frequency of the analysed samples (in Hz), if known.
+    center_freq: Option<u64>,
+    /// Versions of the configuration and signature library that produced this detection.
+    config_version: String,
+    library_version: String,
 }
 
 impl DetectionInfo {
-    pub fn new() -> Self {
-        Self {
-            score: 0.0,
-            timestamp: 0,
-            uav_type: "Unknown".into(),
-        }
+    pub fn score(&self) -> f32 {
+        self.score
     }
 
-    fn calculate(
-        spectrum: FrequencySpectrum,
-        uav_data_path: &str,
-        bandwidth: f32
-    ) -> Self {
-        let uav_data = UAVInfo::load_uav_reference_data(uav_data_path);
-
-        // Process stored UAV RF data into frequency spectra
-        let mut uav_type_to_spectrum: HashMap<String, FrequencySpectrum> = HashMap::new();
-        for uav in &uav_data {
-            let signal = wav_to_signal(File::open(&uav.audio_path).expect("Could not open file"))
-                .expect("Could not convert WAV to signal");
-            let spectrum = compute_spectrum(&signal, SAMPLE_RATE).expect("Could not get spectrum");
-            uav_type_to_spectrum.insert(uav.name.clone(), spectrum);
-        }
-
-        // Classify detected signal
-        let (uav_type, score) = classify_uav(spectrum, &uav_type_to_spectrum);
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
 
-        DetectionInfo {
-            score,
-            timestamp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap_or(Duration::from_secs(0))
-                .as_millis() as u64,
-            uav_type,
-        }
+    pub fn uav_type(&self) -> &str {
+        &self.uav_type
     }
 
-//     fn calculate(
-//         spectrum: FrequencySpectrum,
-//         target_freqs: &[f32],
-//         bandwidth: f32,
-//     ) -> Self {
-//         let mut score = 0f32;
-//         let mut total_power = 0f32;
+    pub fn sensor_id(&self) -> &str {
+        &self.sensor_id
+    }
 
-//         for &(freq, power) in spectrum.data() {
-//             let mut max_weight = 0f32;
+    pub fn center_freq(&self) -> Option<u64> {
+        self.center_freq
+    }
 
-//             for &target_freq in target_freqs {
-//                 let distance = (freq.val() - target_freq).abs();
-//                 let weight = (-distance.powi(2) / (2.0 * bandwidth.powi(2))).exp(); // Gaussian decay
-//                 // We only care about the freq with the max weight to ensure the most relevant frequency dominates
-//                 max_weight = max_weight.max(weight);
-//             }
+    pub fn emissions(&self) -> &[Emission] {
+        &self.emissions
+    }
 
-//             score += power.val() * max_weight;
-//             total_power += power.val();
-//         }
+    pub fn config_version(&self) -> &str {
+        &self.config_version
+    }
 
-//         Self {
-//             score: if total_power > 0.0 { score / total_power } else { 0.0 },
-//             timestamp: std::time::SystemTime::now()
-//                 .duration_since(std::time::UNIX_EPOCH)
-//                 .unwrap_or(Duration::from_secs(0))
-//                 .as_millis() as u64,
-//             uav_type
-//         }
-//     }
+    pub fn library_version(&self) -> &str {
+        &self.library_version
+    }
 }
 
-/// Holds our RF signal data window. Includes the samples currently recorded and max size
-/// of the buffer.
+/// Holds our RF signal data window. Includes the complex I/Q samples currently recorded and max
+/// size of the buffer.
 pub struct SignalWindow {
-    samples: VecDeque<f32>,
+    samples: VecDeque<Complex<f32>>,
     max_size: usize,
+    /// Samples pushed out by newer ones since the last `take_overflow`.
+    overflow: u64,
 }
 
 impl SignalWindow {
     fn new(max_size: usize) -> Self {
         Self {
             samples: VecDeque::with_capacity(max_size),
-            max_size
+            max_size,
+            overflow: 0,
         }
     }
 
-    // Add a single sample
-    fn add_sample(&mut self, sample: f32) {
+    // Add a single sample. NaN or infinite samples are stored as zeros, as one of them would
+    // spoil every averaged spectrum after it.
+    fn add_sample(&mut self, sample: Complex<f32>) {
         if self.samples.len() >= self.max_size {
             self.samples.pop_front();
+            self.overflow += 1;
         }
-        self.samples.push_back(sample);
+        self.samples.push_back(if sample.is_finite() { sample } else { Complex::new(0.0, 0.0) });
     }
 
-    fn add_samples(&mut self, samples: &[f32]) {
+    fn add_samples(&mut self, samples: &[Complex<f32>]) {
         for sample in samples {
             self.add_sample(*sample);
         }
     }
+
+    // Forget samples the PSD estimator is done with
+    fn drain(&mut self, consumed: usize) {
+        self.samples.drain(..consumed.min(self.samples.len()));
+    }
+
+    // Number of samples lost to overflows since the last call
+    fn take_overflow(&mut self) -> u64 {
+        std::mem::take(&mut self.overflow)
+    }
+
+    // Pad with zeros for samples that never arrived and return how many were added. The samples
+    // they push out aren't overflows, as they would be stale after the gap anyway.
+    fn fill_gap(&mut self, missing: u64) -> u64 {
+        let missing = missing.min(self.max_size as u64);
+        let stale = (self.samples.len() + missing as usize).saturating_sub(self.max_size);
+        self.samples.drain(..stale);
+        self.samples.extend(std::iter::repeat_n(Complex::new(0.0, 0.0), missing as usize));
+        missing
+    }
 }
@@ -0,0 +1,139 @@
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::history::DetectionQuery;
+use crate::processing::DetectionInfo;
+use crate::spectrum::SpectrumOptions;
+
+/// Version of the WebSocket protocol, sent in every envelope as `v`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Stream of server messages a client can subscribe to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Detections,
+    Alarms,
+    /// Binary spectrum frames, see `spectrum::SpectrumSubscription`.
+    Spectrum,
+    /// Periodic packet and loss counters of the UDP sources.
+    Health,
+    /// Signature library reloads.
+    Library,
+}
+
+/// Client request, e.g. `{"v": 1, "id": 7, "command": "subscribe", "channels": ["detections"]}`.
+/// `id` is any JSON value and is echoed in the response.
+#[derive(Deserialize, Debug)]
+pub struct Request {
+    pub v: Option<u32>,
+    pub id: Option<Value>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Subscribe { channels: Vec<Channel> },
+    Unsubscribe { channels: Vec<Channel> },
+    /// Replaces the filter of the detections and alarms channels.
+    SetFilter(Filter),
+    /// Replaces the resolution and rate of the spectrum channel.
+    ConfigureSpectrum(SpectrumOptions),
+    GetConfig,
+    /// Past detections, filtered like `GET /api/detections`.
+    GetHistory(DetectionQuery),
+    Ping,
+}
+
+/// What a client wants to hear about. Unset fields let everything through.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    pub min_score: Option<f32>,
+    pub uav_types: Option<Vec<String>>,
+    pub sensor_ids: Option<Vec<String>>,
+}
+
+impl Filter {
+    pub fn matches_uav_type(&self, uav_type: &str) -> bool {
+        self.uav_types.as_ref().is_none_or(|uav_types| uav_types.iter().any(|allowed| allowed == uav_type))
+    }
+
+    pub fn matches_sensor(&self, sensor_id: &str) -> bool {
+        self.sensor_ids.as_ref().is_none_or(|sensor_ids| sensor_ids.iter().any(|allowed| allowed == sensor_id))
+    }
+
+    pub fn matches(&self, detection: &DetectionInfo) -> bool {
+        self.matches_uav_type(detection.uav_type())
+            && self.matches_sensor(detection.sensor_id())
+            && self.min_score.is_none_or(|min_score| detection.score() >= min_score)
+    }
+}
+
+/// Everything the server sends as text frames.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Envelope {
+    Response { v: u32, id: Option<Value>, data: Value },
+    Error { v: u32, id: Option<Value>, error: ProtocolError },
+    Event { v: u32, channel: Channel, data: Value },
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Not valid JSON or not a known command.
+    BadRequest,
+    UnsupportedVersion,
+    /// The command was understood but couldn't be carried out.
+    Failed,
+}
+
+impl Envelope {
+    pub fn response(id: Option<Value>, data: impl Serialize) -> Self {
+        Envelope::Response { v: PROTOCOL_VERSION, id, data: to_value(data) }
+    }
+
+    pub fn error(id: Option<Value>, code: ErrorCode, message: impl Into<String>) -> Self {
+        Envelope::Error { v: PROTOCOL_VERSION, id, error: ProtocolError { code, message: message.into() } }
+    }
+
+    pub fn event(channel: Channel, data: impl Serialize) -> Self {
+        Envelope::Event { v: PROTOCOL_VERSION, channel, data: to_value(data) }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("envelopes should serialise to JSON")
+    }
+}
+
+fn to_value(data: impl Serialize) -> Value {
+    serde_json::to_value(data).expect("messages should serialise to JSON")
+}
+
+/// Parses a client's text frame. On failure returns the error envelope to send back, with the
+/// request's id if it had one.
+pub fn parse_request(text: &str) -> Result<(Option<Value>, Command), Envelope> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| Envelope::error(None, ErrorCode::BadRequest, format!("invalid JSON: {}", e)))?;
+    let id = value.get("id").cloned();
+    let request: Request = serde_json::from_value(value)
+        .map_err(|e| Envelope::error(id.clone(), ErrorCode::BadRequest, e.to_string()))?;
+
+    match request.v {
+        Some(v) if v != PROTOCOL_VERSION => Err(Envelope::error(
+            request.id,
+            ErrorCode::UnsupportedVersion,
+            format!("protocol version {} is not supported, use {}", v, PROTOCOL_VERSION),
+        )),
+        _ => Ok((request.id, request.command)),
+    }
+}
@@ -0,0 +1,299 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Serialize, Deserialize};
+use spectrum_analyzer::{error::SpectrumAnalyzerError, Frequency, FrequencySpectrum, FrequencyValue};
+
+/// Default number of samples per FFT segment.
+pub const FFT_SIZE: usize = 1024;
+/// Default fraction of each segment shared with the next one.
+pub const OVERLAP: f32 = 0.5;
+/// Default weight of the newest segment with exponential averaging.
+pub const EXPONENTIAL_ALPHA: f32 = 0.2;
+
+/// Smallest density reported, so empty bins come out as a finite number of dB.
+const MIN_DENSITY: f32 = 1e-20;
+
+/// Taper applied to each segment before the FFT, trading frequency resolution for leakage.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    /// 4-term Blackman-Harris, for weak signals next to strong ones.
+    BlackmanHarris,
+    /// For accurate amplitudes of narrowband signals.
+    FlatTop,
+}
+
+impl WindowFunction {
+    /// Periodic window of `n` coefficients.
+    pub fn coefficients(self, n: usize) -> Vec<f32> {
+        let terms: &[f32] = match self {
+            WindowFunction::Rectangular => &[1.0],
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            WindowFunction::FlatTop => &[0.21557895, 0.41663158, 0.27726316, 0.08357895, 0.006947368],
+        };
+        (0..n)
+            .map(|i| {
+                let phase = 2.0 * PI * i as f32 / n as f32;
+                terms.iter().enumerate()
+                    .map(|(k, a)| {
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        sign * a * (k as f32 * phase).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+/// How the periodograms of successive segments are combined.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Averaging {
+    /// Mean of the segments analysed in one detection interval (Welch's method).
+    Welch,
+    /// Running average over every segment so far, the newest one weighted by `alpha`.
+    Exponential {
+        #[serde(default = "default_alpha")]
+        alpha: f32,
+    },
+}
+
+fn default_alpha() -> f32 {
+    EXPONENTIAL_ALPHA
+}
+
+#[derive(Debug)]
+pub enum PsdError {
+    TooFewSamples { have: usize, need: usize },
+    Spectrum(SpectrumAnalyzerError),
+}
+
+impl fmt::Display for PsdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PsdError::TooFewSamples { have, need } => write!(f, "{} samples collected, {} needed for a PSD", have, need),
+            PsdError::Spectrum(e) => write!(f, "could not build spectrum: {:?}", e),
+        }
+    }
+}
+
+/// Power spectral density estimate. Frequencies are offsets from the center frequency, going from
+/// `-sample_rate / 2` to `+sample_rate / 2`.
+pub struct Psd {
+    pub resolution: f32,
+    pub frequencies: Vec<f32>,
+    /// Linear density in full scale² per Hz, a full scale sample having a magnitude of 1.
+    pub density: Vec<f32>,
+    /// Samples the next estimate doesn't need any more. The rest overlaps the next segment.
+    pub consumed: usize,
+}
+
+impl Psd {
+    pub fn to_dbfs(&self) -> Vec<f32> {
+        self.density.iter().map(|&density| density_to_db(density)).collect()
+    }
+
+    /// Amplitude spectral density, the scale the reference spectra and classifiers work in.
+    pub fn to_spectrum(&self) -> Result<FrequencySpectrum, PsdError> {
+        let data: Vec<(Frequency, FrequencyValue)> = self.frequencies.iter()
+            .zip(&self.density)
+            .map(|(&freq, density)| (Frequency::from(freq), FrequencyValue::from(density.sqrt())))
+            .collect();
+        if data.iter().any(|(_, value)| !value.val().is_finite()) {
+            return Err(PsdError::Spectrum(SpectrumAnalyzerError::NaNValuesNotSupported));
+        }
+        let mut working_buffer = data.clone();
+        Ok(FrequencySpectrum::new(data, self.resolution, self.frequencies.len() as u32, &mut working_buffer))
+    }
+}
+
+/// Converts a linear density to dB, e.g. full scale² per Hz to dBFS/Hz.
+pub fn density_to_db(density: f32) -> f32 {
+    10.0 * density.max(MIN_DENSITY).log10()
+}
+
+/// Splits the sample stream into overlapping, windowed segments and averages their periodograms.
+pub struct PsdEstimator {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Sum of the squared window coefficients, to normalise the window's power away.
+    window_power: f32,
+    hop: usize,
+    averaging: Averaging,
+    sample_rate: u32,
+    /// Running average kept between calls with exponential averaging.
+    average: Option<Vec<f32>>,
+}
+
+impl PsdEstimator {
+    pub fn new(fft_size: usize, overlap: f32, window: WindowFunction, averaging: Averaging, sample_rate: u32) -> Self {
+        let window = window.coefficients(fft_size);
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(fft_size),
+            window_power: window.iter().map(|w| w * w).sum(),
+            window,
+            hop: ((fft_size as f32 * (1.0 - overlap)).round() as usize).max(1),
+            averaging,
+            sample_rate,
+            average: None,
+        }
+    }
+
+    fn fft_size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Estimates the PSD of every complete segment in `samples`.
+    pub fn estimate(&mut self, samples: &[Complex<f32>]) -> Result<Psd, PsdError> {
+        let n = self.fft_size();
+        if samples.len() < n {
+            return Err(PsdError::TooFewSamples { have: samples.len(), need: n });
+        }
+
+        let segments = (samples.len() - n) / self.hop + 1;
+        let scale = 1.0 / (self.sample_rate as f32 * self.window_power);
+        let resolution = self.sample_rate as f32 / n as f32;
+        let mut welch = vec![0f32; n];
+        let mut buffer = vec![Complex::new(0.0, 0.0); n];
+
+        for segment in 0..segments {
+            let start = segment * self.hop;
+            for (i, (sample, w)) in samples[start..start + n].iter().zip(&self.window).enumerate() {
+                buffer[i] = sample * w;
+            }
+            self.fft.process(&mut buffer);
+            let periodogram: Vec<f32> = buffer.iter().map(|bin| bin.norm_sqr() * scale).collect();
+
+            match self.averaging {
+                Averaging::Welch => {
+                    for (sum, power) in welch.iter_mut().zip(&periodogram) {
+                        *sum += power / segments as f32;
+                    }
+                }
+                Averaging::Exponential { alpha } => match &mut self.average {
+                    Some(average) => {
+                        for (average, power) in average.iter_mut().zip(&periodogram) {
+                            *average += alpha * (power - *average);
+                        }
+                    }
+                    None => self.average = Some(periodogram),
+                },
+            }
+        }
+
+        let averaged = match self.averaging {
+            Averaging::Welch => welch,
+            Averaging::Exponential { .. } => self.average.clone().unwrap_or(welch),
+        };
+
+        Ok(Psd {
+            resolution,
+            frequencies: (0..n).map(|i| (i as f32 - (n / 2) as f32) * resolution).collect(),
+            density: fftshift(&averaged),
+            consumed: segments * self.hop,
+        })
+    }
+}
+
+/// Reorders the bins so the negative offsets come first (same as numpy's fftshift).
+pub fn fftshift(bins: &[f32]) -> Vec<f32> {
+    let n = bins.len();
+    (0..n).map(|i| bins[(i + n.div_ceil(2)) % n]).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    pub(crate) const SAMPLE_RATE: u32 = 62_500;
+
+    /// Complex white Gaussian noise with a mean power of `power`.
+    pub(crate) fn white_noise(len: usize, power: f32, seed: u64) -> Vec<Complex<f32>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sigma = (power / 2.0).sqrt();
+        (0..len)
+            .map(|_| {
+                // Box-Muller
+                let radius = (-2.0 * (1.0 - rng.random::<f32>()).ln()).sqrt();
+                let phase = 2.0 * PI * rng.random::<f32>();
+                Complex::from_polar(sigma * radius, phase)
+            })
+            .collect()
+    }
+
+    /// Tone of power `power` at `freq` Hz from the center.
+    pub(crate) fn tone(len: usize, power: f32, freq: f32) -> Vec<Complex<f32>> {
+        (0..len)
+            .map(|i| Complex::from_polar(power.sqrt(), 2.0 * PI * freq * i as f32 / SAMPLE_RATE as f32))
+            .collect()
+    }
+
+    fn welch(window: WindowFunction) -> PsdEstimator {
+        PsdEstimator::new(FFT_SIZE, OVERLAP, window, Averaging::Welch, SAMPLE_RATE)
+    }
+
+    #[test]
+    fn window_coefficients() {
+        assert_eq!(WindowFunction::Rectangular.coefficients(4), [1.0; 4]);
+        let hann = WindowFunction::Hann.coefficients(4);
+        for (w, expected) in hann.iter().zip([0.0, 0.5, 1.0, 0.5]) {
+            assert!((w - expected).abs() < 1e-6, "{:?}", hann);
+        }
+        // Periodic windows are symmetric around n / 2, where they peak at 1
+        for window in [WindowFunction::Hann, WindowFunction::BlackmanHarris, WindowFunction::FlatTop] {
+            let w = window.coefficients(64);
+            assert!((w[32] - 1.0).abs() < 1e-5, "{:?}: {}", window, w[32]);
+            for i in 1..32 {
+                assert!((w[i] - w[64 - i]).abs() < 1e-5, "{:?}", window);
+            }
+        }
+        assert!(WindowFunction::BlackmanHarris.coefficients(64)[0].abs() < 1e-4);
+        // Flat top windows dip below 0
+        assert!(WindowFunction::FlatTop.coefficients(64)[0] < 0.0);
+    }
+
+    #[test]
+    fn welch_density_integrates_to_the_signal_power() {
+        // Parseval: a tone's density summed over the band gives back its power, whatever the window
+        let windows = [WindowFunction::Rectangular, WindowFunction::Hann, WindowFunction::BlackmanHarris, WindowFunction::FlatTop];
+        for window in windows {
+            let psd = welch(window).estimate(&tone(8 * FFT_SIZE, 0.01, 5000.0)).unwrap();
+            let power: f32 = psd.density.iter().sum::<f32>() * psd.resolution;
+            assert!((power / 0.01 - 1.0).abs() < 1e-3, "{:?}: {}", window, power);
+        }
+
+        // White noise spreads its power evenly, at power / sample_rate per Hz
+        let psd = welch(WindowFunction::Hann).estimate(&white_noise(64 * FFT_SIZE, 1e-4, 1)).unwrap();
+        let mean = psd.density.iter().sum::<f32>() / psd.density.len() as f32;
+        let expected = 1e-4 / SAMPLE_RATE as f32;
+        assert!((density_to_db(mean) - density_to_db(expected)).abs() < 0.2, "{} vs {}", mean, expected);
+    }
+
+    #[test]
+    fn segments_and_bins() {
+        let psd = welch(WindowFunction::Hann).estimate(&tone(4 * FFT_SIZE + 100, 1.0, 0.0)).unwrap();
+        // 7 segments, starting every half FFT
+        assert_eq!(psd.consumed, 7 * FFT_SIZE / 2);
+
+        // Shifted so the DC bin is in the middle
+        assert_eq!(psd.frequencies[0], -(SAMPLE_RATE as f32) / 2.0);
+        assert_eq!(psd.frequencies[FFT_SIZE / 2], 0.0);
+        let peak = (0..FFT_SIZE).max_by(|&a, &b| psd.density[a].total_cmp(&psd.density[b])).unwrap();
+        assert_eq!(peak, FFT_SIZE / 2);
+
+        assert!(matches!(
+            welch(WindowFunction::Hann).estimate(&tone(100, 1.0, 0.0)),
+            Err(PsdError::TooFewSamples { have: 100, need: FFT_SIZE }),
+        ));
+    }
+}
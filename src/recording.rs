@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use actix::prelude::*;
+use log::{error, info};
+use num_complex::Complex;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+
+use crate::processing::DetectionInfo;
+use crate::utils::iso8601;
+
+/// Default of how much signal before an alarm is kept (in ms).
+pub const PRE_TRIGGER_MS: u64 = 5000;
+/// Default of how much signal after the last detection of a clip is kept (in ms).
+pub const POST_TRIGGER_MS: u64 = 5000;
+/// Default of the longest clip, so a drone hovering for an hour doesn't fill the disk (in ms).
+pub const MAX_CLIP_MS: u64 = 60_000;
+
+/// Version of the SigMF specification the metadata follows.
+const SIGMF_VERSION: &str = "1.0.0";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Save the I/Q samples around every raised alarm as a SigMF recording. Off by default, as
+    /// the clips take up disk space the operator has to plan for.
+    pub enabled: bool,
+    pub directory: PathBuf,
+    pub pre_trigger_ms: u64,
+    pub post_trigger_ms: u64,
+    pub max_clip_ms: u64,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: "recordings".into(),
+            pre_trigger_ms: PRE_TRIGGER_MS,
+            post_trigger_ms: POST_TRIGGER_MS,
+            max_clip_ms: MAX_CLIP_MS,
+        }
+    }
+}
+
+impl RecordingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.post_trigger_ms == 0 {
+            return Err("recording.post_trigger_ms must be greater than 0".into());
+        }
+        if self.max_clip_ms < self.pre_trigger_ms + self.post_trigger_ms {
+            return Err(format!(
+                "recording.max_clip_ms must be at least pre_trigger_ms + post_trigger_ms ({}), got {}",
+                self.pre_trigger_ms + self.post_trigger_ms, self.max_clip_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// SigMF annotation of a detection within a clip. Sample indexes are relative to the clip start.
+#[derive(Clone, Debug)]
+struct Annotation {
+    sample_start: u64,
+    sample_count: u64,
+    uav_type: String,
+    score: f32,
+    timestamp: u64,
+    /// Absolute frequencies covered by the detected emissions (in Hz), if the center is known.
+    freq_edges: Option<(f64, f64)>,
+}
+
+/// A finished capture, ready to be written to disk.
+pub struct Clip {
+    /// File name without the SigMF extensions.
+    pub name: String,
+    pub samples: Vec<Complex<f32>>,
+    pub sample_rate: u32,
+    pub center_freq: Option<u64>,
+    /// Time of the first sample, in ms since the Unix epoch.
+    pub started_at: u64,
+    pub description: String,
+    annotations: Vec<Annotation>,
+}
+
+impl Clip {
+    /// SigMF metadata (`.sigmf-meta`) of the clip.
+    fn metadata(&self) -> serde_json::Value {
+        let mut capture = json!({
+            "core:sample_start": 0,
+            "core:datetime": iso8601(self.started_at),
+        });
+        if let Some(center_freq) = self.center_freq {
+            capture["core:frequency"] = json!(center_freq);
+        }
+
+        let annotations: Vec<serde_json::Value> = self.annotations.iter()
+            .map(|annotation| {
+                let mut value = json!({
+                    "core:sample_start": annotation.sample_start,
+                    "core:sample_count": annotation.sample_count,
+                    "core:label": annotation.uav_type,
+                    "core:comment": format!("score {:.3} at {}", annotation.score, iso8601(annotation.timestamp)),
+                });
+                if let Some((lower, upper)) = annotation.freq_edges {
+                    value["core:freq_lower_edge"] = json!(lower);
+                    value["core:freq_upper_edge"] = json!(upper);
+                }
+                value
+            })
+            .collect();
+
+        json!({
+            "global": {
+                "core:datatype": "cf32_le",
+                "core:sample_rate": self.sample_rate,
+                "core:version": SIGMF_VERSION,
+                "core:description": self.description,
+                "core:recorder": env!("CARGO_PKG_NAME"),
+            },
+            "captures": [capture],
+            "annotations": annotations,
+        })
+    }
+
+    /// Samples as interleaved little-endian f32 I/Q (`.sigmf-data`).
+    fn data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.samples.len() * 8);
+        for sample in &self.samples {
+            data.extend_from_slice(&sample.re.to_le_bytes());
+            data.extend_from_slice(&sample.im.to_le_bytes());
+        }
+        data
+    }
+}
+
+struct Capture {
+    /// Sample clock of the first sample of the clip.
+    start: u64,
+    /// Sample clock at which the capture ends, moved on by every detection.
+    stop: u64,
+    samples: Vec<Complex<f32>>,
+    center_freq: Option<u64>,
+    started_at: u64,
+    uav_type: String,
+    annotations: Vec<Annotation>,
+}
+
+/// Keeps the last `pre_trigger_ms` of samples and, once an alarm is raised, captures them along
+/// with everything up to `post_trigger_ms` after the last detection. Only samples of one band are
+/// kept at a time, so a sweeping sender's clips end when it moves on to another band.
+pub struct Recorder {
+    sensor_id: String,
+    sample_rate: u32,
+    pre_trigger: VecDeque<Complex<f32>>,
+    pre_trigger_samples: usize,
+    post_trigger_samples: u64,
+    max_clip_samples: u64,
+    /// Number of samples seen so far.
+    clock: u64,
+    /// Band of the samples kept, `None` for samples sent without a header.
+    center_freq: Option<u64>,
+    capture: Option<Capture>,
+    writer: Addr<ClipWriter>,
+}
+
+impl Recorder {
+    pub fn new(config: &RecordingConfig, sensor_id: &str, sample_rate: u32, writer: Addr<ClipWriter>) -> Self {
+        let samples = |ms: u64| ms * sample_rate as u64 / 1000;
+        let pre_trigger_samples = samples(config.pre_trigger_ms) as usize;
+        Self {
+            sensor_id: sensor_id.into(),
+            sample_rate,
+            pre_trigger: VecDeque::with_capacity(pre_trigger_samples),
+            pre_trigger_samples,
+            post_trigger_samples: samples(config.post_trigger_ms),
+            max_clip_samples: samples(config.max_clip_ms),
+            clock: 0,
+            center_freq: None,
+            capture: None,
+            writer,
+        }
+    }
+
+    /// Adds samples of the band at `center_freq`. Samples of another band than the previous ones
+    /// end the capture in progress and start the pre-trigger buffer over.
+    pub fn push(&mut self, samples: &[Complex<f32>], center_freq: Option<u64>) {
+        if center_freq != self.center_freq {
+            self.finish();
+            self.pre_trigger.clear();
+            self.center_freq = center_freq;
+        }
+        self.record(samples);
+    }
+
+    fn record(&mut self, samples: &[Complex<f32>]) {
+        if let Some(capture) = &mut self.capture {
+            let room = (capture.stop - self.clock) as usize;
+            capture.samples.extend_from_slice(&samples[..samples.len().min(room)]);
+        }
+
+        for sample in samples {
+            if self.pre_trigger.len() >= self.pre_trigger_samples {
+                self.pre_trigger.pop_front();
+            }
+            if self.pre_trigger_samples > 0 {
+                self.pre_trigger.push_back(*sample);
+            }
+        }
+        self.clock += samples.len() as u64;
+
+        if self.capture.as_ref().is_some_and(|capture| self.clock >= capture.stop) {
+            self.finish();
+        }
+    }
+
+    /// Handles samples that never arrived: zeros are recorded in their place if their number is
+    /// known, otherwise the capture in progress is cut short.
+    pub fn gap(&mut self, missing_samples: Option<u64>) {
+        match missing_samples {
+            Some(missing) => {
+                let missing = missing.min(self.max_clip_samples) as usize;
+                self.record(&vec![Complex::new(0.0, 0.0); missing]);
+            }
+            None => {
+                self.finish();
+                self.pre_trigger.clear();
+            }
+        }
+    }
+
+    /// Starts a capture for a raised alarm, unless one is already running or the samples kept are
+    /// of another band than the alarm's.
+    pub fn trigger(&mut self, uav_type: &str, now: u64, center_freq: Option<u64>) {
+        if self.capture.is_some() || center_freq != self.center_freq {
+            return;
+        }
+        let pre_trigger_ms = self.pre_trigger.len() as u64 * 1000 / self.sample_rate as u64;
+        let start = self.clock - self.pre_trigger.len() as u64;
+        info!("Recording {} from {} ms before the alarm", uav_type, pre_trigger_ms);
+        self.capture = Some(Capture {
+            start,
+            stop: (self.clock + self.post_trigger_samples).min(start + self.max_clip_samples),
+            samples: self.pre_trigger.iter().copied().collect(),
+            center_freq,
+            started_at: now.saturating_sub(pre_trigger_ms),
+            uav_type: uav_type.into(),
+            annotations: Vec::new(),
+        });
+    }
+
+    /// Annotates the capture in progress with a detection made on the last `analysed` samples,
+    /// and keeps it going for another `post_trigger_ms`.
+    pub fn annotate(&mut self, detection: &DetectionInfo, analysed: usize) {
+        let Some(capture) = &mut self.capture else { return };
+        if detection.uav_type() == "Unknown" || detection.center_freq() != capture.center_freq {
+            return;
+        }
+
+        let first = self.clock.saturating_sub(analysed as u64).max(capture.start);
+        let freq_edges = detection.center_freq().zip(emission_edges(detection))
+            .map(|(center, (lower, upper))| (center as f64 + lower as f64, center as f64 + upper as f64));
+        capture.annotations.push(Annotation {
+            sample_start: first - capture.start,
+            sample_count: self.clock - first,
+            uav_type: detection.uav_type().into(),
+            score: detection.score(),
+            timestamp: detection.timestamp(),
+            freq_edges,
+        });
+        capture.stop = capture.stop.max(self.clock + self.post_trigger_samples).min(capture.start + self.max_clip_samples);
+    }
+
+    /// Hands the capture in progress, if any, to the writer.
+    fn finish(&mut self) {
+        let Some(capture) = self.capture.take() else { return };
+        let name = format!("{}_{}_{}", self.sensor_id, capture.started_at, file_safe(&capture.uav_type));
+        self.writer.do_send(WriteClip(Clip {
+            name,
+            samples: capture.samples,
+            sample_rate: self.sample_rate,
+            center_freq: capture.center_freq,
+            started_at: capture.started_at,
+            description: format!("Alarm raised for {} by {}", capture.uav_type, self.sensor_id),
+            annotations: capture.annotations,
+        }));
+    }
+}
+
+/// Lowest and highest frequency offsets covered by the detection's emissions.
+fn emission_edges(detection: &DetectionInfo) -> Option<(f32, f32)> {
+    detection.emissions().iter()
+        .map(|emission| (emission.center_freq - emission.bandwidth / 2.0, emission.center_freq + emission.bandwidth / 2.0))
+        .reduce(|(lower, upper), (low, high)| (lower.min(low), upper.max(high)))
+}
+
+/// Keeps letters, digits, `-` and `_`, replacing anything else with `_`.
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Writes finished clips to disk. Runs on its own thread through a `SyncArbiter`, like the
+/// detection store.
+pub struct ClipWriter {
+    directory: PathBuf,
+}
+
+impl ClipWriter {
+    /// Writes the data file first, so a metadata file always has its samples next to it.
+    fn write(&self, clip: &Clip) -> std::io::Result<PathBuf> {
+        let meta = serde_json::to_string_pretty(&clip.metadata()).expect("SigMF metadata should serialise to JSON");
+        std::fs::write(self.directory.join(format!("{}.sigmf-data", clip.name)), clip.data())?;
+        let meta_path = self.directory.join(format!("{}.sigmf-meta", clip.name));
+        std::fs::write(&meta_path, meta)?;
+        Ok(meta_path)
+    }
+}
+
+impl Actor for ClipWriter {
+    type Context = SyncContext<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WriteClip(pub Clip);
+
+impl Handler<WriteClip> for ClipWriter {
+    type Result = ();
+
+    fn handle(&mut self, msg: WriteClip, _: &mut Self::Context) {
+        match self.write(&msg.0) {
+            Ok(path) => info!(
+                "Saved {:.1} s recording to {}",
+                msg.0.samples.len() as f32 / msg.0.sample_rate as f32, path.display()
+            ),
+            Err(e) => error!("Could not save recording {}: {}", msg.0.name, e),
+        }
+    }
+}
+
+/// Creates the recording directory to report problems at startup, then starts the writer on its
+/// own thread.
+pub fn start_clip_writer(directory: &Path) -> std::io::Result<Addr<ClipWriter>> {
+    std::fs::create_dir_all(directory)?;
+    let directory = directory.to_path_buf();
+    info!("Saving alarm recordings in {}", directory.display());
+    Ok(SyncArbiter::start(1, move || ClipWriter { directory: directory.clone() }))
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use actix::prelude::*;
+use actix_web::rt::time::{sleep_until, Instant};
+use clap::ValueEnum;
+use log::info;
+use num_complex::Complex;
+use serde::{Serialize, Deserialize};
+use wire::formats::SampleFormat;
+
+use crate::processing::{now_ms, AddSamples, DetectionInfo, ProcessingActor, Reset, RunDetection};
+use crate::utils::wav_to_signal;
+
+/// Bytes read from a raw or SigMF data file at a time.
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+/// How fast recordings are fed to the processing actor.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplaySpeed {
+    /// At the recording's sample rate, like a live feed.
+    #[default]
+    Realtime,
+    /// As fast as the detections can be computed.
+    Fast,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Open(PathBuf, std::io::Error),
+    Read(PathBuf, std::io::Error),
+    Metadata(PathBuf, String),
+    Wav(PathBuf, String),
+    UnknownFormat(PathBuf),
+    SampleRateMismatch { path: PathBuf, sample_rate: u32, expected: u32 },
+    Processing(MailboxError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Open(path, e) => write!(f, "could not open {}: {}", path.display(), e),
+            ReplayError::Read(path, e) => write!(f, "could not read {}: {}", path.display(), e),
+            ReplayError::Metadata(path, e) => write!(f, "invalid SigMF metadata {}: {}", path.display(), e),
+            ReplayError::Wav(path, e) => write!(f, "could not decode {}: {}", path.display(), e),
+            ReplayError::UnknownFormat(path) => write!(
+                f, "can't tell the format of {}, expected .sigmf-meta, .sigmf-data, .wav, .cf32, .cs16, .cs8, .cu8 or .iq",
+                path.display()
+            ),
+            ReplayError::SampleRateMismatch { path, sample_rate, expected } => write!(
+                f, "{} is sampled at {} Hz, but the other recordings at {} Hz", path.display(), sample_rate, expected
+            ),
+            ReplayError::Processing(e) => write!(f, "processing actor unavailable: {}", e),
+        }
+    }
+}
+
+enum Samples {
+    /// Interleaved I/Q read from disk as needed.
+    Stream { reader: BufReader<File>, format: SampleFormat, leftover: Vec<u8> },
+    /// Decoded up front (WAV).
+    Memory { samples: Vec<Complex<f32>>, position: usize },
+}
+
+/// A recording opened for replay.
+pub struct Recording {
+    pub path: PathBuf,
+    pub sample_rate: u32,
+    pub center_freq: Option<u64>,
+    samples: Samples,
+}
+
+impl Recording {
+    /// Opens a SigMF (either of its files), WAV or raw I/Q recording, picked by the extension.
+    /// Raw files are read as `raw_format` unless the extension names a format, and are assumed to
+    /// be sampled at `raw_sample_rate`.
+    pub fn open(path: &Path, raw_format: SampleFormat, raw_sample_rate: u32) -> Result<Self, ReplayError> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "sigmf-meta" | "sigmf-data" => Self::open_sigmf(path),
+            "wav" => Self::open_wav(path),
+            "iq" | "raw" | "bin" => Self::open_raw(path, raw_format, raw_sample_rate),
+            other => match other.parse::<SampleFormat>() {
+                Ok(format) => Self::open_raw(path, format, raw_sample_rate),
+                Err(_) => Err(ReplayError::UnknownFormat(path.to_path_buf())),
+            },
+        }
+    }
+
+    fn open_raw(path: &Path, format: SampleFormat, sample_rate: u32) -> Result<Self, ReplayError> {
+        let file = File::open(path).map_err(|e| ReplayError::Open(path.to_path_buf(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            sample_rate,
+            center_freq: None,
+            samples: Samples::Stream { reader: BufReader::new(file), format, leftover: Vec::new() },
+        })
+    }
+
+    fn open_wav(path: &Path) -> Result<Self, ReplayError> {
+        let file = File::open(path).map_err(|e| ReplayError::Open(path.to_path_buf(), e))?;
+        let (sample_rate, samples) = wav_to_signal(file).map_err(|e| ReplayError::Wav(path.to_path_buf(), e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            sample_rate,
+            center_freq: None,
+            samples: Samples::Memory { samples, position: 0 },
+        })
+    }
+
+    fn open_sigmf(path: &Path) -> Result<Self, ReplayError> {
+        let meta_path = path.with_extension("sigmf-meta");
+        let content = std::fs::read_to_string(&meta_path).map_err(|e| ReplayError::Open(meta_path.clone(), e))?;
+        let invalid = |reason: String| ReplayError::Metadata(meta_path.clone(), reason);
+        let meta: serde_json::Value = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+
+        let global = &meta["global"];
+        let datatype = global["core:datatype"].as_str().ok_or_else(|| invalid("missing core:datatype".into()))?;
+        let format = sigmf_format(datatype).ok_or_else(|| invalid(format!("unsupported core:datatype '{}'", datatype)))?;
+        let sample_rate = global["core:sample_rate"].as_f64()
+            .filter(|rate| *rate >= 1.0 && *rate <= u32::MAX as f64)
+            .ok_or_else(|| invalid("missing or invalid core:sample_rate".into()))?;
+        let center_freq = meta["captures"][0]["core:frequency"].as_f64().map(|freq| freq as u64);
+
+        let mut recording = Self::open_raw(&path.with_extension("sigmf-data"), format, sample_rate as u32)?;
+        recording.path = meta_path;
+        recording.center_freq = center_freq;
+        Ok(recording)
+    }
+
+    /// Reads up to `max` samples. Returns an empty vector at the end of the recording.
+    fn read(&mut self, max: usize) -> Result<Vec<Complex<f32>>, ReplayError> {
+        match &mut self.samples {
+            Samples::Stream { reader, format, leftover } => {
+                let wanted = max * format.bytes_per_sample();
+                let mut data = std::mem::take(leftover);
+                let mut buffer = vec![0; READ_BUFFER_SIZE.min(wanted)];
+                while data.len() < wanted {
+                    let read = reader.read(&mut buffer[..(wanted - data.len()).min(READ_BUFFER_SIZE)])
+                        .map_err(|e| ReplayError::Read(self.path.clone(), e))?;
+                    if read == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buffer[..read]);
+                }
+                let complete = data.len() - data.len() % format.bytes_per_sample();
+                *leftover = data.split_off(complete);
+                Ok(format.parse(&data))
+            }
+            Samples::Memory { samples, position } => {
+                let end = (*position + max).min(samples.len());
+                let chunk = samples[*position..end].to_vec();
+                *position = end;
+                Ok(chunk)
+            }
+        }
+    }
+}
+
+/// Sample format of a SigMF `core:datatype`, if the UDP ingest supports it too.
+fn sigmf_format(datatype: &str) -> Option<SampleFormat> {
+    match datatype {
+        "cf32_le" => Some(SampleFormat::Cf32),
+        "ci16_le" => Some(SampleFormat::Cs16),
+        "ci8" | "ci8_le" => Some(SampleFormat::Cs8),
+        "cu8" | "cu8_le" => Some(SampleFormat::Cu8),
+        _ => None,
+    }
+}
+
+/// Opens every recording, checking they share a sample rate since the processing actor works at
+/// a single one.
+pub fn open_recordings(paths: &[PathBuf], raw_format: SampleFormat, raw_sample_rate: u32) -> Result<Vec<Recording>, ReplayError> {
+    let recordings = paths.iter()
+        .map(|path| Recording::open(path, raw_format, raw_sample_rate))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(first) = recordings.first() {
+        if let Some(other) = recordings.iter().find(|recording| recording.sample_rate != first.sample_rate) {
+            return Err(ReplayError::SampleRateMismatch {
+                path: other.path.clone(),
+                sample_rate: other.sample_rate,
+                expected: first.sample_rate,
+            });
+        }
+    }
+    Ok(recordings)
+}
+
+/// What a replay detected, per recording.
+#[derive(Serialize, Debug)]
+pub struct ReplayReport {
+    pub recordings: Vec<RecordingReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecordingReport {
+    pub path: PathBuf,
+    pub sample_rate: u32,
+    pub center_freq: Option<u64>,
+    /// Timestamp given to the first sample, in ms since the Unix epoch. Detection timestamps are
+    /// relative to it.
+    pub started_at: u64,
+    pub duration_s: f64,
+    /// Detections that named a UAV type, by type.
+    pub summary: BTreeMap<String, TypeSummary>,
+    pub detections: Vec<DetectionInfo>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct TypeSummary {
+    pub detections: usize,
+    pub max_score: f32,
+    /// Time into the recording of the first detection (in ms).
+    pub first_offset_ms: u64,
+}
+
+/// Feeds the recordings, one after the other, to the processing actor and runs a detection after
+/// every `detection_interval` of samples. The processing actor must use an external clock.
+pub async fn replay(
+    recordings: Vec<Recording>,
+    processing: Addr<ProcessingActor>,
+    detection_interval: Duration,
+    speed: ReplaySpeed,
+) -> Result<ReplayReport, ReplayError> {
+    let mut reports = Vec::new();
+    // Time the previous recording ended at, in ms since the Unix epoch.
+    let mut previous_end = None;
+    for mut recording in recordings {
+        info!("Replaying {} ({} Hz)", recording.path.display(), recording.sample_rate);
+        let chunk_size = ((recording.sample_rate as u128 * detection_interval.as_millis() / 1000) as usize).max(1);
+        let started_at = now_ms();
+        let started = Instant::now();
+        let mut position = 0u64;
+        let mut report = RecordingReport {
+            path: recording.path.clone(),
+            sample_rate: recording.sample_rate,
+            center_freq: recording.center_freq,
+            started_at,
+            duration_s: 0.0,
+            summary: BTreeMap::new(),
+            detections: Vec::new(),
+        };
+
+        // Nothing of the previous recording, samples or detection state, may carry over.
+        let timestamp = previous_end.unwrap_or(started_at);
+        processing.send(Reset { timestamp }).await.map_err(ReplayError::Processing)?;
+
+        loop {
+            let samples = recording.read(chunk_size)?;
+            if samples.is_empty() {
+                break;
+            }
+            position += samples.len() as u64;
+            let offset_ms = position * 1000 / recording.sample_rate as u64;
+            if speed == ReplaySpeed::Realtime {
+                sleep_until(started + Duration::from_millis(offset_ms)).await;
+            }
+
+            processing.send(AddSamples { samples, center_freq: recording.center_freq }).await
+                .map_err(ReplayError::Processing)?;
+            let detections = processing.send(RunDetection { timestamp: started_at + offset_ms }).await
+                .map_err(ReplayError::Processing)?;
+
+            for detection in detections {
+                if detection.uav_type() != "Unknown" {
+                    let summary = report.summary.entry(detection.uav_type().into()).or_insert_with(|| TypeSummary {
+                        first_offset_ms: offset_ms,
+                        ..Default::default()
+                    });
+                    summary.detections += 1;
+                    summary.max_score = summary.max_score.max(detection.score());
+                }
+                report.detections.push(detection);
+            }
+        }
+
+        report.duration_s = position as f64 / recording.sample_rate as f64;
+        previous_end = Some(started_at + position * 1000 / recording.sample_rate as u64);
+        info!(
+            "Replayed {:.1} s of {}: {} detections",
+            report.duration_s, recording.path.display(), report.detections.len()
+        );
+        reports.push(report);
+    }
+    Ok(ReplayReport { recordings: reports })
+}
@@ -0,0 +1,133 @@
+use serde::{Serialize, Deserialize};
+use spectrum_analyzer::FrequencySpectrum;
+
+/// Number of points of the frequency grid both spectra are resampled onto before comparing them.
+pub const GRID_BINS: usize = 512;
+/// Keeps logarithms and ratios finite on empty bins.
+const EPSILON: f32 = 1e-12;
+
+/// How to score the similarity of a live spectrum and a reference spectrum. Every measure
+/// returns a score between 0.0 (unrelated) and 1.0 (identical shape).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarityMeasure {
+    /// Cosine similarity of the magnitudes.
+    Cosine,
+    /// Cosine similarity of the log-compressed magnitudes, so weak features count as well as
+    /// the strongest peak.
+    LogCosine,
+    /// Pearson correlation of the magnitudes, taking the best of all shifts up to
+    /// `max_shift_bins` grid bins in either direction to absorb frequency offsets.
+    Correlation { max_shift_bins: usize },
+    /// `exp(-KL(live || reference))`, treating both spectra as power distributions.
+    KlDivergence,
+}
+
+impl SimilarityMeasure {
+    pub fn score(&self, spectrum: &FrequencySpectrum, ref_spectrum: &FrequencySpectrum) -> f32 {
+        let Some(grid) = common_grid(spectrum, ref_spectrum, GRID_BINS) else {
+            return 0.0;
+        };
+        let live = resample(spectrum, &grid);
+        let reference = resample(ref_spectrum, &grid);
+
+        let score = match self {
+            SimilarityMeasure::Cosine => cosine(&live, &reference),
+            SimilarityMeasure::LogCosine => cosine(&log_compress(&live), &log_compress(&reference)),
+            SimilarityMeasure::Correlation { max_shift_bins } => {
+                shifted_correlation(&live, &reference, *max_shift_bins)
+            }
+            SimilarityMeasure::KlDivergence => (-kl_divergence(&live, &reference)).exp(),
+        };
+        if score.is_finite() { score.clamp(0.0, 1.0) } else { 0.0 }
+    }
+}
+
+/// Evenly spaced frequencies covering the range both spectra have in common, or `None` if they
+/// don't overlap.
+pub fn common_grid(a: &FrequencySpectrum, b: &FrequencySpectrum, bins: usize) -> Option<Vec<f32>> {
+    let low = a.min_fr().val().max(b.min_fr().val());
+    let high = a.max_fr().val().min(b.max_fr().val());
+    if high <= low || bins < 2 {
+        return None;
+    }
+
+    let step = (high - low) / (bins - 1) as f32;
+    Some((0..bins).map(|i| low + i as f32 * step).collect())
+}
+
+/// Maps a spectrum onto `grid`. Grid cells covering several bins of the spectrum get their mean,
+/// so narrow peaks aren't skipped, and cells falling between two bins are linearly interpolated.
+pub fn resample(spectrum: &FrequencySpectrum, grid: &[f32]) -> Vec<f32> {
+    let data = spectrum.data();
+    let half_step = if grid.len() > 1 { (grid[1] - grid[0]) / 2.0 } else { 0.0 };
+
+    grid.iter()
+        .map(|&freq| {
+            let start = data.partition_point(|(f, _)| f.val() < freq - half_step);
+            let end = data.partition_point(|(f, _)| f.val() < freq + half_step);
+            if end > start {
+                return data[start..end].iter().map(|(_, v)| v.val()).sum::<f32>() / (end - start) as f32;
+            }
+
+            // No bin inside the cell, interpolate between its neighbours
+            let upper = data.partition_point(|(f, _)| f.val() < freq);
+            match (upper.checked_sub(1).and_then(|i| data.get(i)), data.get(upper)) {
+                (Some(&(f0, v0)), Some(&(f1, v1))) => {
+                    let t = (freq - f0.val()) / (f1.val() - f0.val());
+                    v0.val() + t * (v1.val() - v0.val())
+                }
+                (Some(&(_, v)), None) | (None, Some(&(_, v))) => v.val(),
+                (None, None) => 0.0,
+            }
+        })
+        .collect()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|y| y * y).sum::<f32>().sqrt();
+    if norm_a > 0.0 && norm_b > 0.0 { dot / (norm_a * norm_b) } else { 0.0 }
+}
+
+/// `ln(1 + x / mean)`, which is independent of the overall signal level.
+fn log_compress(values: &[f32]) -> Vec<f32> {
+    let mean = values.iter().sum::<f32>() / values.len().max(1) as f32;
+    values.iter().map(|v| (1.0 + v / (mean + EPSILON)).ln()).collect()
+}
+
+fn shifted_correlation(a: &[f32], b: &[f32], max_shift: usize) -> f32 {
+    let max_shift = max_shift.min(a.len().saturating_sub(2)) as isize;
+    (-max_shift..=max_shift)
+        .map(|shift| {
+            let (a, b) = if shift >= 0 {
+                (&a[shift as usize..], &b[..b.len() - shift as usize])
+            } else {
+                (&a[..a.len() - (-shift) as usize], &b[(-shift) as usize..])
+            };
+            pearson(a, b)
+        })
+        .fold(0.0, f32::max)
+}
+
+fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+    let centred_a: Vec<f32> = a.iter().map(|x| x - mean_a).collect();
+    let centred_b: Vec<f32> = b.iter().map(|y| y - mean_b).collect();
+    cosine(&centred_a, &centred_b)
+}
+
+fn kl_divergence(p: &[f32], q: &[f32]) -> f32 {
+    let total_p: f32 = p.iter().map(|v| v * v + EPSILON).sum();
+    let total_q: f32 = q.iter().map(|v| v * v + EPSILON).sum();
+    p.iter().zip(q)
+        .map(|(p, q)| {
+            let p = (p * p + EPSILON) / total_p;
+            let q = (q * q + EPSILON) / total_q;
+            p * (p / q).ln()
+        })
+        .sum()
+}
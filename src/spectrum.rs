@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+/// Leading bytes of every spectrum frame.
+pub const FRAME_MAGIC: &[u8; 4] = b"AHSP";
+pub const FRAME_VERSION: u8 = 1;
+/// Bytes before the bins: magic, version, format, bins (u16), timestamp (u64, ms since the Unix
+/// epoch), center frequency (u64 Hz, 0 if unknown), sample rate (u32), min dB (f32), max dB (f32).
+/// All little endian.
+pub const FRAME_HEADER_LEN: usize = 36;
+
+/// Default frames per second sent to a client.
+pub const SPECTRUM_FPS: f32 = 2.0;
+/// Range of frames per second a client may ask for.
+pub const SPECTRUM_MIN_FPS: f32 = 0.1;
+pub const SPECTRUM_MAX_FPS: f32 = 60.0;
+/// Default number of bins per frame.
+pub const SPECTRUM_BINS: usize = 512;
+/// Default range mapped onto the u8 bins (in dBFS/Hz).
+pub const SPECTRUM_MIN_DB: f32 = -150.0;
+pub const SPECTRUM_MAX_DB: f32 = -30.0;
+
+/// PSD of one detection interval, shared by every client that asked for spectra.
+pub struct SpectrumFrame {
+    /// Not part of the binary frame, clients pick a sensor with their filter.
+    pub sensor_id: String,
+    pub timestamp: u64,
+    pub center_freq: Option<u64>,
+    pub sample_rate: u32,
+    /// From `-sample_rate / 2` to `+sample_rate / 2`, in dBFS/Hz.
+    pub dbfs: Vec<f32>,
+}
+
+/// How each bin is quantized.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BinFormat {
+    /// 0 to 255 across `min_db` to `max_db`, clamped.
+    U8,
+    /// Hundredths of a dB, little endian.
+    I16,
+}
+
+impl BinFormat {
+    fn code(self) -> u8 {
+        match self {
+            BinFormat::U8 => 0,
+            BinFormat::I16 => 1,
+        }
+    }
+}
+
+/// Spectrum channel options of a WebSocket client. Set with the `configure_spectrum` command, or
+/// the `/ws` query string, e.g. `/ws?spectrum=true&fps=10&bins=1024&format=i16`.
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SpectrumOptions {
+    pub fps: f32,
+    pub bins: usize,
+    pub format: BinFormat,
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+impl Default for SpectrumOptions {
+    fn default() -> Self {
+        Self {
+            fps: SPECTRUM_FPS,
+            bins: SPECTRUM_BINS,
+            format: BinFormat::U8,
+            min_db: SPECTRUM_MIN_DB,
+            max_db: SPECTRUM_MAX_DB,
+        }
+    }
+}
+
+impl SpectrumOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(SPECTRUM_MIN_FPS..=SPECTRUM_MAX_FPS).contains(&self.fps) {
+            return Err(format!(
+                "fps must be between {} and {}, got {}", SPECTRUM_MIN_FPS, SPECTRUM_MAX_FPS, self.fps,
+            ));
+        }
+        if !(16..=u16::MAX as usize).contains(&self.bins) {
+            return Err(format!("bins must be between 16 and {}, got {}", u16::MAX, self.bins));
+        }
+        if !self.min_db.is_finite() || !self.max_db.is_finite() || self.min_db >= self.max_db {
+            return Err(format!("min_db ({}) must be below max_db ({})", self.min_db, self.max_db));
+        }
+        Ok(())
+    }
+}
+
+/// A client's spectrum subscription. Throttles the frames of each sensor and band to the requested
+/// rate and encodes them.
+pub struct SpectrumSubscription {
+    options: SpectrumOptions,
+    /// When a frame was last sent, by sensor and center frequency, so a busy sensor or a sweep
+    /// doesn't starve the others.
+    last_sent: HashMap<(String, Option<u64>), Instant>,
+}
+
+impl SpectrumSubscription {
+    pub fn new(options: SpectrumOptions) -> Self {
+        Self { options, last_sent: HashMap::new() }
+    }
+
+    pub fn set_options(&mut self, options: SpectrumOptions) {
+        self.options = options;
+    }
+
+    /// Encoded frame, or `None` if the client had one of the same sensor and band too recently.
+    pub fn next_frame(&mut self, frame: &SpectrumFrame) -> Option<Vec<u8>> {
+        let interval = Duration::from_secs_f32(1.0 / self.options.fps);
+        let now = Instant::now();
+        let key = (frame.sensor_id.clone(), frame.center_freq);
+        if self.last_sent.get(&key).is_some_and(|last| now.duration_since(*last) < interval) {
+            return None;
+        }
+        self.last_sent.insert(key, now);
+        Some(encode(frame, &self.options))
+    }
+}
+
+fn encode(frame: &SpectrumFrame, options: &SpectrumOptions) -> Vec<u8> {
+    let bins = downsample(&frame.dbfs, options.bins);
+
+    let mut data = Vec::with_capacity(FRAME_HEADER_LEN + 2 * bins.len());
+    data.extend_from_slice(FRAME_MAGIC);
+    data.push(FRAME_VERSION);
+    data.push(options.format.code());
+    data.extend_from_slice(&(bins.len() as u16).to_le_bytes());
+    data.extend_from_slice(&frame.timestamp.to_le_bytes());
+    data.extend_from_slice(&frame.center_freq.unwrap_or(0).to_le_bytes());
+    data.extend_from_slice(&frame.sample_rate.to_le_bytes());
+    data.extend_from_slice(&options.min_db.to_le_bytes());
+    data.extend_from_slice(&options.max_db.to_le_bytes());
+
+    match options.format {
+        BinFormat::U8 => {
+            let scale = 255.0 / (options.max_db - options.min_db);
+            data.extend(bins.iter().map(|db| ((db - options.min_db) * scale).round().clamp(0.0, 255.0) as u8));
+        }
+        BinFormat::I16 => {
+            for db in bins {
+                data.extend_from_slice(&((db * 100.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());
+            }
+        }
+    }
+    data
+}
+
+/// Reduces the PSD to at most `bins` bins, keeping the strongest value of each group so narrow
+/// signals don't vanish from the display.
+fn downsample(dbfs: &[f32], bins: usize) -> Vec<f32> {
+    if dbfs.len() <= bins {
+        return dbfs.to_vec();
+    }
+    (0..bins)
+        .map(|bin| {
+            let start = bin * dbfs.len() / bins;
+            let end = (bin + 1) * dbfs.len() / bins;
+            dbfs[start..end].iter().copied().fold(f32::NEG_INFINITY, f32::max)
+        })
+        .collect()
+}
@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use actix::prelude::*;
+use log::{error, info};
+use rusqlite::{params, Connection};
+
+use crate::config::StoreConfig;
+use crate::history::{DetectionHistory, DetectionQuery};
+use crate::processing::DetectionInfo;
+
+/// Retention is enforced every this many inserts, besides at startup.
+const RETENTION_CHECK_INTERVAL: u64 = 100;
+
+/// Schema changes, applied in order. `PRAGMA user_version` holds how many were applied, so new
+/// migrations must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE detections (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        score REAL NOT NULL,
+        uav_type TEXT NOT NULL,
+        sensor_id TEXT NOT NULL,
+        center_freq INTEGER,
+        config_version TEXT NOT NULL,
+        library_version TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE INDEX detections_timestamp ON detections (timestamp);
+    CREATE INDEX detections_uav_type ON detections (uav_type, timestamp);",
+];
+
+/// Durable detection log in an embedded SQLite database. Runs on its own thread through a
+/// `SyncArbiter`, so disk access never blocks the processing actor.
+pub struct StoreActor {
+    conn: Connection,
+    max_age: Option<Duration>,
+    max_records: Option<u64>,
+    keep_unknown: bool,
+    inserts: u64,
+}
+
+impl StoreActor {
+    pub fn open(config: &StoreConfig) -> rusqlite::Result<Self> {
+        let mut conn = Connection::open(&config.path)?;
+        migrate(&mut conn)?;
+
+        let store = Self {
+            conn,
+            max_age: config.max_age_hours.map(|hours| Duration::from_secs(hours * 3600)),
+            max_records: config.max_records,
+            keep_unknown: config.keep_unknown,
+            inserts: 0,
+        };
+        store.apply_retention()?;
+        Ok(store)
+    }
+
+    fn insert(&self, detection: &DetectionInfo) -> rusqlite::Result<()> {
+        let record = serde_json::to_string(detection).expect("detections should serialise to JSON");
+        self.conn.execute(
+            "INSERT INTO detections
+                (timestamp, score, uav_type, sensor_id, center_freq, config_version, library_version, record)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                detection.timestamp() as i64,
+                detection.score(),
+                detection.uav_type(),
+                detection.sensor_id(),
+                detection.center_freq().map(|freq| freq as i64),
+                detection.config_version(),
+                detection.library_version(),
+                record,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Detections matching `query`, oldest first.
+    fn query(&self, query: &DetectionQuery) -> rusqlite::Result<Vec<DetectionInfo>> {
+        let mut statement = self.conn.prepare(
+            "SELECT record FROM (
+                SELECT id, record FROM detections
+                WHERE (?1 IS NULL OR timestamp >= ?1)
+                  AND (?2 IS NULL OR timestamp <= ?2)
+                  AND (?3 IS NULL OR uav_type = ?3)
+                  AND (?4 IS NULL OR score >= ?4)
+                  AND (?5 IS NULL OR sensor_id = ?5)
+                  AND (?6 IS NULL OR center_freq = ?6)
+                ORDER BY id DESC
+                LIMIT ?7
+             ) ORDER BY id ASC",
+        )?;
+        let rows = statement.query_map(
+            params![
+                query.since.map(|since| since as i64),
+                query.until.map(|until| until as i64),
+                query.uav_type,
+                query.min_score,
+                query.sensor_id,
+                query.center_freq.map(|center_freq| center_freq as i64),
+                query.limit.map_or(-1, |limit| limit as i64),
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+
+        let mut detections = Vec::new();
+        for record in rows {
+            match serde_json::from_str(&record?) {
+                Ok(detection) => detections.push(detection),
+                Err(e) => error!("Skipping unreadable stored detection: {}", e),
+            }
+        }
+        Ok(detections)
+    }
+
+    /// Deletes detections older than `max_age` and the oldest ones beyond `max_records`.
+    fn apply_retention(&self) -> rusqlite::Result<()> {
+        let mut deleted = 0;
+        if let Some(max_age) = self.max_age {
+            let cutoff = SystemTime::now()
+                .checked_sub(max_age)
+                .and_then(|cutoff| cutoff.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |cutoff| cutoff.as_millis() as i64);
+            deleted += self.conn.execute("DELETE FROM detections WHERE timestamp < ?1", params![cutoff])?;
+        }
+        if let Some(max_records) = self.max_records {
+            deleted += self.conn.execute(
+                "DELETE FROM detections WHERE id <= (
+                    SELECT id FROM detections ORDER BY id DESC LIMIT 1 OFFSET ?1
+                )",
+                params![max_records as i64],
+            )?;
+        }
+        if deleted > 0 {
+            info!("Retention policy deleted {} stored detections", deleted);
+        }
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+        info!("Applied detection store migration {}", version + 1);
+    }
+    Ok(())
+}
+
+impl Actor for StoreActor {
+    type Context = SyncContext<Self>;
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StoreDetection(pub DetectionInfo);
+
+#[derive(Message)]
+#[rtype(result = "Result<Vec<DetectionInfo>, String>")]
+pub struct QueryDetections(pub DetectionQuery);
+
+impl Handler<StoreDetection> for StoreActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: StoreDetection, _: &mut Self::Context) {
+        if !self.keep_unknown && msg.0.uav_type() == "Unknown" {
+            return;
+        }
+        if let Err(e) = self.insert(&msg.0) {
+            error!("Could not store detection: {}", e);
+        }
+
+        self.inserts += 1;
+        if self.inserts.is_multiple_of(RETENTION_CHECK_INTERVAL) {
+            if let Err(e) = self.apply_retention() {
+                error!("Could not apply retention policy: {}", e);
+            }
+        }
+    }
+}
+
+impl Handler<QueryDetections> for StoreActor {
+    type Result = Result<Vec<DetectionInfo>, String>;
+
+    fn handle(&mut self, msg: QueryDetections, _: &mut Self::Context) -> Self::Result {
+        self.query(&msg.0).map_err(|e| e.to_string())
+    }
+}
+
+/// Opens the database once to report problems at startup, then starts the store on its own
+/// thread.
+pub fn start_store(config: &StoreConfig) -> rusqlite::Result<Addr<StoreActor>> {
+    StoreActor::open(config)?;
+    let config = config.clone();
+    info!("Storing detections in {}", config.path.display());
+    Ok(SyncArbiter::start(1, move || {
+        StoreActor::open(&config).expect("detection store should open after a successful check")
+    }))
+}
+
+/// Detections matching `query`, oldest first. Served from the in-memory history when it holds
+/// every match, otherwise from the store when enabled. The store leaves out Unknown detections
+/// unless `store.keep_unknown` is on, so older ones are only returned then.
+pub async fn query_detections(
+    store: Option<Addr<StoreActor>>,
+    history: Arc<Mutex<DetectionHistory>>,
+    query: DetectionQuery,
+) -> Result<Vec<DetectionInfo>, String> {
+    let Some(store) = store else {
+        return Ok(history.lock().unwrap_or_else(PoisonError::into_inner).query(&query));
+    };
+    let recent = history.lock().unwrap_or_else(PoisonError::into_inner).query_recent(&query);
+    match recent {
+        Some(detections) => Ok(detections),
+        None => store.send(QueryDetections(query)).await.map_err(|e| e.to_string())?,
+    }
+}
@@ -1,30 +1,72 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use actix_web::rt::net::UdpSocket;
 use actix::prelude::*;
-use log::error;
+use log::{error, info, warn};
+use serde::Serialize;
+use wire::formats::SampleFormat;
+use wire::packet::PacketHeader;
 
-use crate::utils::parse_samples;
-use crate::processing::{ProcessingActor, AddSamples};
+use crate::config::UdpConfig;
+use crate::packet::{Arrival, SourceStats};
+use crate::pipelines::{PipelineRegistry, SensorGap, SensorSamples};
+use crate::processing::{AddSamples, Discontinuity};
 
-const PORT: u16 = 5454;
-/// Size of the buffer for UDP packets.
+/// Default UDP port samples are received on.
+pub const PORT: u16 = 5454;
+/// Default size of the buffer for UDP packets.
 pub const BUFFER_SIZE: usize = 65536;
+/// Read errors in a row after which the listener gives up and lets its supervisor restart it.
+const MAX_CONSECUTIVE_ERRORS: u32 = 100;
+/// How long a restarted listener that couldn't bind its socket waits before trying again.
+const REBIND_DELAY: Duration = Duration::from_secs(1);
 
 pub struct UdpListenerActor {
-    socket: Arc<UdpSocket>,
-    subscriber: Option<Addr<ProcessingActor>>,
+    /// `None` if binding it again failed on a restart.
+    socket: Option<Arc<UdpSocket>>,
+    /// Address and port the socket is bound to.
+    address: String,
+    port: u16,
+    buffer_size: usize,
+    /// Shared with the receive loop, which looks it up for every datagram so a later `Subscribe`
+    /// takes effect.
+    subscriber: Arc<Mutex<Option<Addr<PipelineRegistry>>>>,
+    /// Format of datagrams sent without a `PacketHeader`.
+    format: SampleFormat,
+    /// Rate the pipelines analyse samples at. Datagrams whose header says otherwise are dropped.
+    sample_rate: u32,
+    stats: Arc<Mutex<HashMap<SocketAddr, SourceStats>>>,
+    restarts: u32,
+}
+
+/// Liveness of the listener, see `GET /api/pipelines`.
+#[derive(Serialize, Clone, Debug)]
+pub struct ListenerStatus {
+    pub address: String,
+    /// A pipeline registry is subscribed, so samples go somewhere.
+    pub forwarding: bool,
+    pub restarts: u32,
+    pub sources: Vec<SourceStats>,
 }
 
 impl UdpListenerActor {
-    pub async fn new() -> Self {
-        let socket = UdpSocket::bind(("127.0.0.1", PORT)).await
-            .expect("UDP socket binding should have been successful");
+    pub async fn new(config: &UdpConfig, sample_rate: u32) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind((config.address.as_str(), config.port)).await?;
 
-        Self {
-            socket: Arc::new(socket),
-            subscriber: None,
-        }
+        Ok(Self {
+            socket: Some(Arc::new(socket)),
+            address: config.address.clone(),
+            port: config.port,
+            buffer_size: config.buffer_size,
+            subscriber: Arc::new(Mutex::new(None)),
+            format: config.sample_format,
+            sample_rate,
+            stats: Arc::new(Mutex::new(HashMap::new())),
+            restarts: 0,
+        })
     }
 }
 
@@ -32,34 +74,149 @@ impl Actor for UdpListenerActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        let processing_addr = self.subscriber.clone();
-        let socket = self.socket.clone();
+        let Some(socket) = self.socket.clone() else {
+            ctx.run_later(REBIND_DELAY, |_, ctx| ctx.stop());
+            return;
+        };
+        let subscriber = self.subscriber.clone();
+        let format = self.format;
+        let sample_rate = self.sample_rate;
+        let stats = self.stats.clone();
+        let buffer_size = self.buffer_size;
 
         ctx.spawn(async move {
-            let mut buf = [0; BUFFER_SIZE];
-            loop {
+            let mut buf = vec![0; buffer_size];
+            let mut errors = 0;
+            while errors < MAX_CONSECUTIVE_ERRORS {
                 match socket.recv_from(&mut buf).await {
-                    Ok((size, _)) => {
-                        let samples = parse_samples(&buf[..size]);
-                        if let Some(ref addr) = processing_addr {
-                            addr.do_send(AddSamples { samples });
+                    Ok((size, peer)) => {
+                        errors = 0;
+                        let packet = PacketHeader::parse(&buf[..size]);
+                        // Senders that don't name themselves are told apart by their address
+                        let sensor_id = match &packet {
+                            Ok(Some((PacketHeader { sensor_id: Some(sensor_id), .. }, _))) => sensor_id.clone(),
+                            _ => peer.to_string(),
+                        };
+
+                        let mut stats = stats.lock().unwrap();
+                        let source = stats.entry(peer).or_insert_with(|| {
+                            info!("New UDP source: {} (sensor {})", peer, sensor_id);
+                            SourceStats::new(peer.to_string(), sensor_id.clone())
+                        });
+                        if source.sensor_id != sensor_id {
+                            source.sensor_id = sensor_id.clone();
+                        }
+
+                        let (samples, arrival, center_freq) = match packet {
+                            Ok(Some((header, payload))) => {
+                                if !source.check_sample_rate(&header, sample_rate) {
+                                    continue;
+                                }
+                                let samples = header.format.parse(payload);
+                                let arrival = source.record(&header, samples.len() as u64);
+                                (samples, arrival, Some(header.center_freq))
+                            }
+                            Ok(None) => {
+                                let samples = format.parse(&buf[..size]);
+                                source.record_raw(samples.len() as u64);
+                                (samples, Arrival::InOrder, None)
+                            }
+                            Err(e) => {
+                                source.malformed += 1;
+                                warn!("Dropping datagram from {}: {}", peer, e);
+                                continue;
+                            }
+                        };
+                        drop(stats);
+
+                        let pipelines_addr = subscriber.lock().unwrap().clone();
+                        if let Some(ref addr) = pipelines_addr {
+                            let gap = match arrival {
+                                Arrival::InOrder => None,
+                                Arrival::Gap { missing_samples } => Some(Some(missing_samples)),
+                                Arrival::Restart => Some(None),
+                                // The window has already moved past these samples.
+                                Arrival::Late | Arrival::Duplicate => continue,
+                            };
+                            if let Some(missing_samples) = gap {
+                                let gap = Discontinuity { missing_samples, center_freq };
+                                addr.do_send(SensorGap { sensor_id: sensor_id.clone(), gap });
+                            }
+                            addr.do_send(SensorSamples { sensor_id, samples: AddSamples { samples, center_freq } });
                         }
                     }
-                    Err(e) => error!("UDP read error: {}", e),
+                    Err(e) => {
+                        errors += 1;
+                        error!("UDP read error: {}", e);
+                    }
                 }
             }
-        }.into_actor(self));
+        }.into_actor(self).map(|_, _, ctx| {
+            error!("{} UDP read errors in a row, restarting the listener", MAX_CONSECUTIVE_ERRORS);
+            ctx.stop();
+        }));
+    }
+}
+
+impl Supervised for UdpListenerActor {
+    // The socket may be what kept failing, so bind a fresh one. The old one holds the port until
+    // it's dropped, and the restart already cancelled the receive loop that shared it.
+    fn restarting(&mut self, _: &mut Self::Context) {
+        self.restarts += 1;
+        self.socket = None;
+        match bind(&self.address, self.port) {
+            Ok(socket) => self.socket = Some(Arc::new(socket)),
+            Err(e) => error!("Could not bind the UDP listener to {}:{} again: {}", self.address, self.port, e),
+        }
     }
 }
 
+fn bind(address: &str, port: u16) -> std::io::Result<UdpSocket> {
+    let socket = std::net::UdpSocket::bind((address, port))?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Subscribe(pub Addr<ProcessingActor>);
+pub struct Subscribe(pub Addr<PipelineRegistry>);
 
 impl Handler<Subscribe> for UdpListenerActor {
     type Result = ();
 
     fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) {
-        self.subscriber = Some(msg.0)
+        *self.subscriber.lock().unwrap() = Some(msg.0);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<SourceStats>")]
+pub struct GetSourceStats;
+
+impl Handler<GetSourceStats> for UdpListenerActor {
+    type Result = Vec<SourceStats>;
+
+    fn handle(&mut self, _: GetSourceStats, _: &mut Self::Context) -> Self::Result {
+        self.stats.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "ListenerStatus")]
+pub struct GetListenerStatus;
+
+impl Handler<GetListenerStatus> for UdpListenerActor {
+    type Result = MessageResult<GetListenerStatus>;
+
+    fn handle(&mut self, _: GetListenerStatus, _: &mut Self::Context) -> Self::Result {
+        MessageResult(ListenerStatus {
+            address: match &self.socket {
+                Some(socket) => socket.local_addr().map_or_else(|e| e.to_string(), |address| address.to_string()),
+                None => format!("{}:{} (unbound)", self.address, self.port),
+            },
+            forwarding: self.subscriber.lock().unwrap().is_some(),
+            restarts: self.restarts,
+            sources: self.stats.lock().unwrap().values().cloned().collect(),
+        })
     }
 }
@@ -1,76 +1,135 @@
 use std::{collections::HashMap, fs::File};
 
-use spectrum_analyzer::{error::SpectrumAnalyzerError, samples_fft_to_spectrum, scaling::divide_by_N_sqrt, FrequencySpectrum, FrequencyLimit};
+use num_complex::Complex;
+use rustfft::FftPlanner;
+use spectrum_analyzer::{error::SpectrumAnalyzerError, Frequency, FrequencySpectrum, FrequencyValue};
 
-pub fn parse_samples(data: &[u8]) -> Vec<f32> {
-    data.chunks_exact(4) // Each 32-bit float is 4 bytes
-        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-        .collect()
-}
+use crate::similarity::SimilarityMeasure;
 
+/// Computes the two-sided spectrum of complex baseband samples. Frequencies are offsets from the
+/// center frequency, going from `-sampling_rate / 2` to `+sampling_rate / 2`.
 pub fn compute_spectrum(
-    samples: &[f32],
+    samples: &[Complex<f32>],
     sampling_rate: u32,
 ) -> Result<FrequencySpectrum, SpectrumAnalyzerError> {
-    samples_fft_to_spectrum(samples, sampling_rate, FrequencyLimit::All, Some(&divide_by_N_sqrt))
+    let n = samples.len();
+    if n < 2 {
+        return Err(SpectrumAnalyzerError::TooFewSamples);
+    }
+    if samples.iter().any(|s| s.re.is_nan() || s.im.is_nan()) {
+        return Err(SpectrumAnalyzerError::NaNValuesNotSupported);
+    }
+    if samples.iter().any(|s| s.re.is_infinite() || s.im.is_infinite()) {
+        return Err(SpectrumAnalyzerError::InfinityValuesNotSupported);
+    }
+
+    let mut buffer = samples.to_vec();
+    FftPlanner::new().plan_fft_forward(n).process(&mut buffer);
+
+    let resolution = sampling_rate as f32 / n as f32;
+    let scale = (n as f32).sqrt();
+
+    // Reorder the bins so the negative offsets come first (same as numpy's fftshift).
+    let data: Vec<(Frequency, FrequencyValue)> = (0..n)
+        .map(|i| {
+            let bin = buffer[(i + n.div_ceil(2)) % n];
+            let freq = (i as f32 - (n / 2) as f32) * resolution;
+            (Frequency::from(freq), FrequencyValue::from(bin.norm() / scale))
+        })
+        .collect();
+
+    let mut working_buffer = data.clone();
+    Ok(FrequencySpectrum::new(data, resolution, n as u32, &mut working_buffer))
 }
 
+/// Scores the spectrum against every reference spectrum, best match first.
 pub fn classify_uav(
-    spectrum: FrequencySpectrum,
-    reference_data: &HashMap<String, FrequencySpectrum>
-) -> (String, f32) {
-    let mut best_match = String::from("Unknown");
-    let mut best_score = 0.0;
-
-    for (uav_name, ref_spectrum) in reference_data {
-        let similarity_score = cosine_similarity(&spectrum, ref_spectrum);
-        if similarity_score > best_score {
-            best_score = similarity_score;
-            best_match = uav_name.clone();
-        }
-    }
+    spectrum: &FrequencySpectrum,
+    reference_data: &HashMap<String, FrequencySpectrum>,
+    measure: SimilarityMeasure,
+) -> Vec<(String, f32)> {
+    let mut scores: Vec<(String, f32)> = reference_data.iter()
+        .map(|(uav_name, ref_spectrum)| (uav_name.clone(), measure.score(spectrum, ref_spectrum)))
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores
+}
 
-    (best_match, best_score)
+/// Mean over the `expected` parameters of how close the `estimated` ones are, from 0 (off by
+/// `tolerance`, relative to the expected value, or more) to 1 (exact). Parameters without an
+/// expected value are skipped, missing estimates score 0.
+pub fn parameter_score(expected: &[Option<f32>], estimated: &[Option<f32>], tolerance: f32) -> f32 {
+    let scores: Vec<f32> = expected.iter()
+        .zip(estimated)
+        .filter_map(|(expected, estimated)| {
+            let expected = (*expected)?;
+            let deviation = estimated.map_or(f32::INFINITY, |estimated| (estimated - expected).abs() / expected);
+            Some((1.0 - deviation / tolerance).max(0.0))
+        })
+        .collect();
+    scores.iter().sum::<f32>() / scores.len().max(1) as f32
 }
 
-pub fn wav_to_signal(file: File) -> Result<Vec<f32>, ()> {
-    let (_, samples) = wav_io::read_from_file(file).map_err(|_| ())?;
-    Ok(samples)
+/// Reads a WAV recording and returns its sample rate and samples. Stereo files are treated as I/Q
+/// (left = I, right = Q), mono files as a real signal.
+pub fn wav_to_signal(file: File) -> Result<(u32, Vec<Complex<f32>>), String> {
+    let (header, samples) = wav_io::read_from_file(file).map_err(|e| e.to_string())?;
+    let signal = if header.channels == 2 {
+        samples.chunks_exact(2).map(|iq| Complex::new(iq[0], iq[1])).collect()
+    } else {
+        samples.into_iter().map(|sample| Complex::new(sample, 0.0)).collect()
+    };
+    Ok((header.sample_rate, signal))
 }
 
-pub fn cosine_similarity(spectrum: &FrequencySpectrum, ref_spectrum: &FrequencySpectrum) -> f32 {
-    let mut vec1 = HashMap::new();
-    let mut vec2 = HashMap::new();
+/// FNV-1a hash of `content`, used to version the signature library and the configuration. Unlike
+/// `DefaultHasher` it is stable across builds.
+pub fn content_version(content: &str) -> String {
+    let hash = content.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
 
-    // Store first spectrum's frequency-power mapping
-    for &(freq, power) in spectrum.data() {
-        vec1.insert(freq.val().to_bits(), power.val());
-    }
+/// Formats ms since the Unix epoch as an ISO 8601 UTC date and time, e.g.
+/// `2025-03-29T14:05:09.250Z`.
+pub fn iso8601(timestamp_ms: u64) -> String {
+    let (days, ms) = (timestamp_ms / 86_400_000, timestamp_ms % 86_400_000);
 
-    // Store second spectrum's frequency-power mapping
-    for &(freq, power) in ref_spectrum.data() {
-        vec2.insert(freq.val().to_bits(), power.val());
-    }
+    // Civil date from the day count, see Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
 
-    // Compute dot product and vector magnitudes
-    let mut dot_product = 0.0;
-    let mut norm1 = 0.0;
-    let mut norm2 = 0.0;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000
+    )
+}
 
-    for (&freq_bits, &power1) in &vec1 {
-        let power2 = *vec2.get(&freq_bits).unwrap_or(&0.0);
-        dot_product += power1 * power2;
-        norm1 += power1.powi(2);
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for &power2 in vec2.values() {
-        norm2 += power2.powi(2);
+    #[test]
+    fn iso8601_formats_utc_date_and_time() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(1_743_257_109_250), "2025-03-29T14:05:09.250Z");
+        assert_eq!(iso8601(946_684_799_999), "1999-12-31T23:59:59.999Z");
     }
 
-    // Compute cosine similarity
-    if norm1 > 0.0 && norm2 > 0.0 {
-        dot_product / (norm1.sqrt() * norm2.sqrt())
-    } else {
-        0.0
+    #[test]
+    fn iso8601_handles_leap_years() {
+        assert_eq!(iso8601(951_782_400_000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(iso8601(1_709_251_199_999), "2024-02-29T23:59:59.999Z");
+        assert_eq!(iso8601(1_709_251_200_000), "2024-03-01T00:00:00.000Z");
+        // 2100 is not a leap year
+        assert_eq!(iso8601(4_107_542_400_000), "2100-03-01T00:00:00.000Z");
     }
 }
@@ -1,23 +1,138 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use actix::prelude::*;
 use actix_web_actors::ws;
+use serde::Serialize;
+
+use crate::alarm::AlarmEvent;
+use crate::config::Config;
+use crate::history::DetectionHistory;
+use crate::library::LibraryEvent;
+use crate::pipelines::PipelineRegistry;
+use crate::processing::{DetectionInfo, Subscribe, Unsubscribe};
+use crate::protocol::{parse_request, Channel, Command, Envelope, ErrorCode, Filter};
+use crate::spectrum::{SpectrumFrame, SpectrumOptions, SpectrumSubscription};
+use crate::store::{query_detections, StoreActor};
+use crate::udp::{GetSourceStats, UdpListenerActor};
+
+/// How often clients subscribed to the health channel get the source counters.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+/// Most detections a `get_history` command returns.
+const HISTORY_LIMIT: usize = 1000;
 
-use crate::processing::{ProcessingActor, DetectionInfo, Subscribe, Unsubscribe};
+/// Parts of the server a WebSocket client can query.
+#[derive(Clone)]
+pub struct WsServices {
+    pub pipelines: Addr<PipelineRegistry>,
+    /// `None` when replaying recordings.
+    pub udp: Option<Addr<UdpListenerActor>>,
+    pub history: Arc<Mutex<DetectionHistory>>,
+    pub store: Option<Addr<StoreActor>>,
+    pub config: Arc<Config>,
+}
 
+/// One WebSocket client. Only gets the channels it subscribed to, see `protocol::Command`.
 pub struct WsActor {
-    pub detection_addr: Addr<ProcessingActor>
+    services: WsServices,
+    channels: HashSet<Channel>,
+    filter: Filter,
+    spectrum: SpectrumSubscription,
+}
+
+impl WsActor {
+    /// `spectrum` subscribes the client to the spectrum channel right away.
+    pub fn new(services: WsServices, spectrum: Option<SpectrumOptions>) -> Self {
+        let channels = spectrum.iter().map(|_| Channel::Spectrum).collect();
+        Self {
+            services,
+            channels,
+            filter: Filter::default(),
+            spectrum: SpectrumSubscription::new(spectrum.unwrap_or_default()),
+        }
+    }
+
+    fn send(&self, envelope: Envelope, ctx: &mut <Self as Actor>::Context) {
+        ctx.text(envelope.to_json());
+    }
+
+    fn send_event(&self, channel: Channel, data: impl Serialize, ctx: &mut <Self as Actor>::Context) {
+        if self.channels.contains(&channel) {
+            self.send(Envelope::event(channel, data), ctx);
+        }
+    }
+
+    fn handle_command(&mut self, id: Option<serde_json::Value>, command: Command, ctx: &mut <Self as Actor>::Context) {
+        match command {
+            Command::Subscribe { channels } => {
+                self.channels.extend(channels);
+                self.send(Envelope::response(id, &self.channels), ctx);
+            }
+            Command::Unsubscribe { channels } => {
+                for channel in &channels {
+                    self.channels.remove(channel);
+                }
+                self.send(Envelope::response(id, &self.channels), ctx);
+            }
+            Command::SetFilter(filter) => {
+                self.filter = filter;
+                self.send(Envelope::response(id, &self.filter), ctx);
+            }
+            Command::ConfigureSpectrum(options) => match options.validate() {
+                Ok(()) => {
+                    self.spectrum.set_options(options);
+                    self.send(Envelope::response(id, serde_json::Value::Null), ctx);
+                }
+                Err(e) => self.send(Envelope::error(id, ErrorCode::BadRequest, e), ctx),
+            },
+            Command::GetConfig => self.send(Envelope::response(id, &*self.services.config), ctx),
+            Command::GetHistory(mut query) => {
+                if let Err(e) = query.validate() {
+                    self.send(Envelope::error(id, ErrorCode::BadRequest, e), ctx);
+                    return;
+                }
+                query.limit = Some(query.limit.unwrap_or(HISTORY_LIMIT).min(HISTORY_LIMIT));
+                let services = self.services.clone();
+                query_detections(services.store, services.history, query)
+                    .into_actor(self)
+                    .map(move |result, act, ctx| match result {
+                        Ok(detections) => act.send(Envelope::response(id, detections), ctx),
+                        Err(e) => act.send(Envelope::error(id, ErrorCode::Failed, e), ctx),
+                    })
+                    .spawn(ctx);
+            }
+            Command::Ping => self.send(Envelope::response(id, "pong"), ctx),
+        }
+    }
 }
 
 impl Actor for WsActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        // Subscribe to DetectionActor. ctx.address is the address of the ws actor.
-        self.detection_addr.do_send(Subscribe(ctx.address()));
+        // Subscribe to every pipeline. ctx.address is the address of the ws actor.
+        self.services.pipelines.do_send(Subscribe(ctx.address()));
+
+        ctx.run_interval(HEALTH_INTERVAL, |act, ctx| {
+            if !act.channels.contains(&Channel::Health) {
+                return;
+            }
+            let Some(udp) = &act.services.udp else { return };
+            udp.send(GetSourceStats)
+                .into_actor(act)
+                .map(|result, act, ctx| {
+                    if let Ok(sources) = result {
+                        act.send_event(Channel::Health, serde_json::json!({ "sources": sources }), ctx);
+                    }
+                })
+                .spawn(ctx);
+        });
     }
 
     fn stopped(&mut self, ctx: &mut Self::Context) {
-        // Unsubscribe from DetectionActor
-        self.detection_addr.do_send(Unsubscribe(ctx.address()));
+        // Unsubscribe from every pipeline
+        self.services.pipelines.do_send(Unsubscribe(ctx.address()));
     }
 }
 
@@ -25,7 +140,10 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsActor {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
+            Ok(ws::Message::Text(text)) => match parse_request(&text) {
+                Ok((id, command)) => self.handle_command(id, command, ctx),
+                Err(envelope) => self.send(envelope, ctx),
+            },
             Ok(ws::Message::Close(_)) => ctx.close(None),
             _ => (),
         }
@@ -40,9 +158,53 @@ impl Handler<InfoMsg> for WsActor {
     type Result = ();
 
     fn handle(&mut self, msg: InfoMsg, ctx: &mut Self::Context) {
-        // Serialise to JSON and send to client.
-        if let Ok(json) = serde_json::to_string(&msg.0) {
-            ctx.text(json);
+        if self.filter.matches(&msg.0) {
+            self.send_event(Channel::Detections, &msg.0, ctx);
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LibraryMsg(pub LibraryEvent);
+
+impl Handler<LibraryMsg> for WsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: LibraryMsg, ctx: &mut Self::Context) {
+        self.send_event(Channel::Library, &msg.0, ctx);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AlarmMsg(pub AlarmEvent);
+
+impl Handler<AlarmMsg> for WsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: AlarmMsg, ctx: &mut Self::Context) {
+        if self.filter.matches_uav_type(msg.0.uav_type()) && self.filter.matches_sensor(msg.0.sensor_id()) {
+            self.send_event(Channel::Alarms, &msg.0, ctx);
+        }
+    }
+}
+
+/// PSD of the latest detection interval. Sent to every client, but only passed on to the ones
+/// subscribed to the spectrum channel, as binary frames.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SpectrumMsg(pub Arc<SpectrumFrame>);
+
+impl Handler<SpectrumMsg> for WsActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SpectrumMsg, ctx: &mut Self::Context) {
+        if !self.channels.contains(&Channel::Spectrum) || !self.filter.matches_sensor(&msg.0.sensor_id) {
+            return;
+        }
+        if let Some(frame) = self.spectrum.next_frame(&msg.0) {
+            ctx.binary(frame);
         }
     }
 }
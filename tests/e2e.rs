@@ -0,0 +1,69 @@
+//! Runs the server over a simulated recording and checks its detections against the scenario's
+//! ground truth.
+
+use std::path::Path;
+use std::process::Command;
+
+use simulator::scenario::Scenario;
+use simulator::signals::Simulator;
+use wire::formats::SampleFormat;
+
+/// The shipped scenario: a drone's control link and video downlink next to Wi-Fi.
+const SCENARIO: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/simulator/scenarios/mavic_with_wifi.toml");
+
+/// The shipped signature file.
+const LIBRARY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/drone_types.json");
+
+/// Writes the scenario as a cf32 recording, with its labels, into a fresh directory.
+fn render(directory: &Path, scenario: &Scenario) {
+    let mut simulator = Simulator::new(scenario);
+    let total = (scenario.duration_s * scenario.sample_rate as f64) as u64;
+    let mut data = Vec::new();
+    while simulator.position() < total {
+        let block = simulator.next_block(4096);
+        if block.is_empty() {
+            break;
+        }
+        SampleFormat::Cf32.encode(&block, &mut data);
+    }
+    std::fs::write(directory.join("drone.cf32"), data).unwrap();
+
+    let labels = serde_json::json!({ "drone.cf32": scenario.ground_truth() });
+    std::fs::write(directory.join("labels.json"), labels.to_string()).unwrap();
+}
+
+#[test]
+fn detects_a_simulated_drone() {
+    let root = std::env::temp_dir().join(format!("amsterdam-hack-e2e-{}", std::process::id()));
+    let dataset = root.join("dataset");
+    std::fs::create_dir_all(&dataset).unwrap();
+
+    let scenario: Scenario = toml::from_str(&std::fs::read_to_string(SCENARIO).unwrap()).unwrap();
+    scenario.validate().unwrap();
+    render(&dataset, &scenario);
+
+    // Nothing but the library's path differs from the defaults
+    let config = root.join("config.toml");
+    std::fs::write(&config, format!("[library]\npath = {:?}\n", LIBRARY)).unwrap();
+
+    let report = root.join("evaluation.json");
+    let output = Command::new(env!("CARGO_BIN_EXE_amsterdam-hack"))
+        .arg("--config").arg(&config)
+        .arg("--evaluate").arg(&dataset)
+        .args(["--replay-format", "cf32"])
+        .arg("--report").arg(&report)
+        .current_dir(&root)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "server failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let evaluation: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report).unwrap()).unwrap();
+    let drone = &evaluation["uav_types"]["Drone 1"];
+    assert!(drone["precision"].as_f64().unwrap() >= 0.9, "{}", evaluation);
+    assert!(drone["recall"].as_f64().unwrap() >= 0.9, "{}", evaluation);
+    // Both the control link and the video downlink are caught
+    let latency = &evaluation["latency"]["Drone 1"];
+    assert_eq!(latency["detected"], latency["segments"], "{}", evaluation);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
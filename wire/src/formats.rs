@@ -0,0 +1,179 @@
+use std::{fmt, str::FromStr};
+
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+
+/// Wire formats for interleaved I/Q samples. Names follow the usual SDR tooling convention
+/// (`cf32`, `cs16`, `cs8`, `cu8`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    /// Little-endian 32-bit floats, as sent by `sdr-sender` and GNU Radio's `gr_complex`.
+    #[default]
+    Cf32,
+    /// Little-endian 16-bit signed integers.
+    Cs16,
+    /// 8-bit signed integers (HackRF).
+    Cs8,
+    /// 8-bit unsigned integers with a 127.5 offset (RTL-SDR).
+    Cu8,
+}
+
+impl SampleFormat {
+    /// Number of bytes taken by a single I/Q pair.
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            SampleFormat::Cf32 => 8,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cs8 | SampleFormat::Cu8 => 2,
+        }
+    }
+
+    /// Format identifier of a `packet::PacketHeader`.
+    pub fn code(&self) -> u8 {
+        match self {
+            SampleFormat::Cf32 => 0,
+            SampleFormat::Cs16 => 1,
+            SampleFormat::Cs8 => 2,
+            SampleFormat::Cu8 => 3,
+        }
+    }
+
+    /// Reads the format identifier of a `packet::PacketHeader`.
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(SampleFormat::Cf32),
+            1 => Some(SampleFormat::Cs16),
+            2 => Some(SampleFormat::Cs8),
+            3 => Some(SampleFormat::Cu8),
+            _ => None,
+        }
+    }
+
+    /// Decodes a buffer of interleaved I/Q pairs into complex samples normalised to [-1.0, 1.0].
+    /// Trailing bytes that don't make up a full pair are ignored.
+    pub fn parse(&self, data: &[u8]) -> Vec<Complex<f32>> {
+        let chunks = data.chunks_exact(self.bytes_per_sample());
+        match self {
+            SampleFormat::Cf32 => chunks
+                .map(|c| Complex::new(
+                    f32::from_le_bytes([c[0], c[1], c[2], c[3]]),
+                    f32::from_le_bytes([c[4], c[5], c[6], c[7]]),
+                ))
+                .collect(),
+            SampleFormat::Cs16 => chunks
+                .map(|c| Complex::new(
+                    i16::from_le_bytes([c[0], c[1]]) as f32 / 32768.0,
+                    i16::from_le_bytes([c[2], c[3]]) as f32 / 32768.0,
+                ))
+                .collect(),
+            SampleFormat::Cs8 => chunks
+                .map(|c| Complex::new(c[0] as i8 as f32 / 128.0, c[1] as i8 as f32 / 128.0))
+                .collect(),
+            SampleFormat::Cu8 => chunks
+                .map(|c| Complex::new((c[0] as f32 - 127.5) / 127.5, (c[1] as f32 - 127.5) / 127.5))
+                .collect(),
+        }
+    }
+
+    /// Appends the samples as interleaved I/Q, scaled the way `parse` reads them back. Integer
+    /// formats clip samples beyond full scale.
+    pub fn encode(&self, samples: &[Complex<f32>], out: &mut Vec<u8>) {
+        // Casts saturate, so +1.0 ends up as the largest value rather than wrapping around.
+        let clip = |value: f32| value.clamp(-1.0, 1.0);
+        for sample in samples {
+            match self {
+                SampleFormat::Cf32 => {
+                    out.extend_from_slice(&sample.re.to_le_bytes());
+                    out.extend_from_slice(&sample.im.to_le_bytes());
+                }
+                SampleFormat::Cs16 => {
+                    out.extend_from_slice(&((clip(sample.re) * 32768.0).round() as i16).to_le_bytes());
+                    out.extend_from_slice(&((clip(sample.im) * 32768.0).round() as i16).to_le_bytes());
+                }
+                SampleFormat::Cs8 => {
+                    out.push((clip(sample.re) * 128.0).round() as i8 as u8);
+                    out.push((clip(sample.im) * 128.0).round() as i8 as u8);
+                }
+                SampleFormat::Cu8 => {
+                    out.push((clip(sample.re) * 127.5 + 127.5).round() as u8);
+                    out.push((clip(sample.im) * 127.5 + 127.5).round() as u8);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SampleFormat::Cf32 => "cf32",
+            SampleFormat::Cs16 => "cs16",
+            SampleFormat::Cs8 => "cs8",
+            SampleFormat::Cu8 => "cu8",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cf32" => Ok(SampleFormat::Cf32),
+            "cs16" => Ok(SampleFormat::Cs16),
+            "cs8" => Ok(SampleFormat::Cs8),
+            "cu8" => Ok(SampleFormat::Cu8),
+            other => Err(format!("unknown sample format '{}', expected one of cf32, cs16, cs8, cu8", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [SampleFormat; 4] = [SampleFormat::Cf32, SampleFormat::Cs16, SampleFormat::Cs8, SampleFormat::Cu8];
+
+    #[test]
+    fn codes_round_trip() {
+        for format in FORMATS {
+            assert_eq!(SampleFormat::from_code(format.code()), Some(format));
+            assert_eq!(format.to_string().parse::<SampleFormat>(), Ok(format));
+        }
+        assert_eq!(SampleFormat::from_code(4), None);
+    }
+
+    #[test]
+    fn parse_reads_back_what_encode_wrote() {
+        let samples = [Complex::new(0.5, -0.25), Complex::new(-1.0, 0.0), Complex::new(0.125, 0.75)];
+        for format in FORMATS {
+            let mut data = Vec::new();
+            format.encode(&samples, &mut data);
+            assert_eq!(data.len(), samples.len() * format.bytes_per_sample());
+
+            // Within one step of the integer formats
+            let step = match format {
+                SampleFormat::Cf32 => 0.0,
+                SampleFormat::Cs16 => 1.0 / 32768.0,
+                SampleFormat::Cs8 => 1.0 / 128.0,
+                SampleFormat::Cu8 => 1.0 / 127.5,
+            };
+            for (decoded, sample) in format.parse(&data).iter().zip(&samples) {
+                assert!((decoded - sample).norm() <= step, "{}: {} decoded as {}", format, sample, decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_clips_beyond_full_scale() {
+        let mut data = Vec::new();
+        SampleFormat::Cs16.encode(&[Complex::new(2.0, -2.0)], &mut data);
+        assert_eq!(data, [i16::MAX.to_le_bytes(), i16::MIN.to_le_bytes()].concat());
+
+        data.clear();
+        SampleFormat::Cs8.encode(&[Complex::new(1.0, -1.0)], &mut data);
+        assert_eq!(data, [i8::MAX as u8, i8::MIN as u8]);
+    }
+}
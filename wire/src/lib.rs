@@ -0,0 +1,5 @@
+//! Wire format of the server's UDP ingest, shared by the server and the senders so they can't
+//! drift apart.
+
+pub mod formats;
+pub mod packet;
@@ -0,0 +1,240 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::formats::SampleFormat;
+
+/// Marks a datagram as starting with a `PacketHeader`. Datagrams without it are treated as raw
+/// samples in the listener's configured format.
+pub const MAGIC: [u8; 4] = *b"AHIQ";
+pub const VERSION: u8 = 1;
+/// Size of the header in bytes. Layout (little-endian):
+///
+/// | offset | size | field       |
+/// |--------|------|-------------|
+/// | 0      | 4    | magic       |
+/// | 4      | 1    | version     |
+/// | 5      | 1    | format      |
+/// | 6      | 2    | header size |
+/// | 8      | 8    | sequence    |
+/// | 16     | 8    | timestamp   |
+/// | 24     | 8    | center freq |
+/// | 32     | 4    | sample rate |
+///
+/// Optionally followed by the sender's sensor id, counted in the header size:
+///
+/// | offset | size | field            |
+/// |--------|------|------------------|
+/// | 36     | 1    | sensor id length |
+/// | 37     | n    | sensor id, UTF-8 |
+pub const HEADER_LEN: usize = 36;
+
+/// Metadata sent in front of the samples of each datagram.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct PacketHeader {
+    /// Incremented by one for every datagram sent.
+    pub sequence: u64,
+    /// Sample clock: samples sent before this datagram since the stream started. Samples a sender
+    /// drops on purpose, e.g. while a retuned receiver settles, aren't counted.
+    pub timestamp: u64,
+    /// Center frequency in Hz.
+    pub center_freq: u64,
+    /// Samples per second.
+    pub sample_rate: u32,
+    pub format: SampleFormat,
+    /// Identifies the sender when several feed the server, at most 255 bytes. Senders without
+    /// one are told apart by their address.
+    pub sensor_id: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PacketError {
+    TooShort(usize),
+    UnsupportedVersion(u8),
+    UnknownFormat(u8),
+    InvalidHeaderSize(u16),
+    InvalidSensorId,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::TooShort(len) => write!(f, "datagram of {} bytes is too short for a header", len),
+            PacketError::UnsupportedVersion(version) => write!(f, "unsupported header version {}", version),
+            PacketError::UnknownFormat(code) => write!(f, "unknown sample format code {}", code),
+            PacketError::InvalidHeaderSize(size) => write!(f, "invalid header size {}", size),
+            PacketError::InvalidSensorId => write!(f, "sensor id is empty, truncated or not UTF-8"),
+        }
+    }
+}
+
+impl PacketHeader {
+    /// Splits a datagram into its header and payload. Returns `Ok(None)` if the datagram doesn't
+    /// start with `MAGIC`, i.e. it only contains samples.
+    pub fn parse(data: &[u8]) -> Result<Option<(Self, &[u8])>, PacketError> {
+        if !data.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+        if data.len() < HEADER_LEN {
+            return Err(PacketError::TooShort(data.len()));
+        }
+        if data[4] != VERSION {
+            return Err(PacketError::UnsupportedVersion(data[4]));
+        }
+        let format = SampleFormat::from_code(data[5]).ok_or(PacketError::UnknownFormat(data[5]))?;
+
+        // Newer senders may append fields, so skip the whole header rather than HEADER_LEN.
+        let header_size = u16::from_le_bytes([data[6], data[7]]);
+        if (header_size as usize) < HEADER_LEN || header_size as usize > data.len() {
+            return Err(PacketError::InvalidHeaderSize(header_size));
+        }
+
+        let sensor_id = match &data[HEADER_LEN..header_size as usize] {
+            [] => None,
+            [len, rest @ ..] => {
+                let id = rest.get(..*len as usize).ok_or(PacketError::InvalidSensorId)?;
+                match std::str::from_utf8(id) {
+                    Ok(id) if !id.is_empty() => Some(id.to_string()),
+                    _ => return Err(PacketError::InvalidSensorId),
+                }
+            }
+        };
+
+        let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let header = PacketHeader {
+            sequence: u64_at(8),
+            timestamp: u64_at(16),
+            center_freq: u64_at(24),
+            sample_rate: u32::from_le_bytes(data[32..36].try_into().unwrap()),
+            format,
+            sensor_id,
+        };
+
+        Ok(Some((header, &data[header_size as usize..])))
+    }
+
+    /// Size of the encoded header, including the sensor id.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.sensor_id.as_ref().map_or(0, |sensor_id| 1 + sensor_id.len())
+    }
+
+    /// Appends the header to `out`, to be followed by the samples.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.push(self.format.code());
+        out.extend_from_slice(&(self.encoded_len() as u16).to_le_bytes());
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+        out.extend_from_slice(&self.center_freq.to_le_bytes());
+        out.extend_from_slice(&self.sample_rate.to_le_bytes());
+        if let Some(sensor_id) = &self.sensor_id {
+            out.push(sensor_id.len() as u8);
+            out.extend_from_slice(sensor_id.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(sequence: u64, timestamp: u64) -> PacketHeader {
+        PacketHeader {
+            sequence,
+            timestamp,
+            center_freq: 2_400_000_000,
+            sample_rate: 62_500,
+            format: SampleFormat::Cs16,
+            sensor_id: None,
+        }
+    }
+
+    fn datagram(sensor_id: &[u8], payload: &[u8]) -> Vec<u8> {
+        let extension = if sensor_id.is_empty() { 0 } else { 1 + sensor_id.len() };
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.push(1);
+        data.extend_from_slice(&((HEADER_LEN + extension) as u16).to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&4096u64.to_le_bytes());
+        data.extend_from_slice(&2_400_000_000u64.to_le_bytes());
+        data.extend_from_slice(&62_500u32.to_le_bytes());
+        if !sensor_id.is_empty() {
+            data.push(sensor_id.len() as u8);
+            data.extend_from_slice(sensor_id);
+        }
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn parse_splits_header_and_payload() {
+        let data = datagram(b"", &[1, 2, 3, 4]);
+        let (parsed, payload) = PacketHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed, header(7, 4096));
+        assert_eq!(payload, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_reads_sensor_id() {
+        let data = datagram(b"roof-north", &[1, 2, 3, 4]);
+        let (parsed, payload) = PacketHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(parsed.sensor_id.as_deref(), Some("roof-north"));
+        assert_eq!(payload, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn parse_leaves_raw_samples_alone() {
+        assert_eq!(PacketHeader::parse(&[0, 1, 2, 3, 4, 5, 6, 7]), Ok(None));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_headers() {
+        let data = datagram(b"", &[]);
+        assert_eq!(PacketHeader::parse(&data[..20]), Err(PacketError::TooShort(20)));
+
+        let mut bad_version = data.clone();
+        bad_version[4] = 2;
+        assert_eq!(PacketHeader::parse(&bad_version), Err(PacketError::UnsupportedVersion(2)));
+
+        let mut bad_format = data.clone();
+        bad_format[5] = 9;
+        assert_eq!(PacketHeader::parse(&bad_format), Err(PacketError::UnknownFormat(9)));
+
+        let mut bad_size = data.clone();
+        bad_size[6..8].copy_from_slice(&100u16.to_le_bytes());
+        assert_eq!(PacketHeader::parse(&bad_size), Err(PacketError::InvalidHeaderSize(100)));
+
+        let mut truncated_id = datagram(b"roof", &[]);
+        truncated_id[HEADER_LEN] = 10;
+        assert_eq!(PacketHeader::parse(&truncated_id), Err(PacketError::InvalidSensorId));
+
+        let not_utf8 = datagram(&[0xff, 0xfe], &[]);
+        assert_eq!(PacketHeader::parse(&not_utf8), Err(PacketError::InvalidSensorId));
+    }
+
+    #[test]
+    fn encode_matches_the_layout() {
+        let mut encoded = Vec::new();
+        header(7, 4096).encode(&mut encoded);
+        assert_eq!(encoded, datagram(b"", &[]));
+
+        let mut with_id = header(7, 4096);
+        with_id.sensor_id = Some("roof-north".into());
+        encoded.clear();
+        with_id.encode(&mut encoded);
+        assert_eq!(encoded.len(), with_id.encoded_len());
+        assert_eq!(encoded, datagram(b"roof-north", &[]));
+    }
+
+    #[test]
+    fn parse_reads_back_what_encode_wrote() {
+        let mut header = header(u64::MAX, 1 << 40);
+        header.sensor_id = Some("sensor-2".into());
+        let mut data = Vec::new();
+        header.encode(&mut data);
+        data.extend_from_slice(&[9, 9]);
+        assert_eq!(PacketHeader::parse(&data), Ok(Some((header, &[9u8, 9][..]))));
+    }
+}